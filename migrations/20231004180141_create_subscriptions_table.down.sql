@@ -0,0 +1,3 @@
+-- migrations/20231004180141_create_subscriptions_table.down.sql
+-- Drop Subscriptions Table
+DROP TABLE subscriptions;
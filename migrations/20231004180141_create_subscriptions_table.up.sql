@@ -1,4 +1,4 @@
--- migrations/20231004180141_create_subscriptions_table.sql
+-- migrations/20231004180141_create_subscriptions_table.up.sql
 -- Create Subscriptions Table
 CREATE TABLE subscriptions(
     id uuid NOT NULL,
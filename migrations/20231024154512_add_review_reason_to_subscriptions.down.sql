@@ -0,0 +1,3 @@
+-- migrations/20231024154512_add_review_reason_to_subscriptions.down.sql
+-- Drop Review Reason Column from Subscriptions Table
+ALTER TABLE subscriptions DROP COLUMN review_reason;
@@ -0,0 +1,4 @@
+-- migrations/20231024154512_add_review_reason_to_subscriptions.up.sql
+-- Add Review Reason Column to Subscriptions Table
+-- Subscribers flagged by the email policy keep the reason here until reviewed.
+ALTER TABLE subscriptions ADD COLUMN review_reason TEXT NULL;
@@ -0,0 +1,9 @@
+-- migrations/20231025093027_add_status_to_subscriptions.up.sql
+-- Add Status Column to Subscriptions Table
+-- We wrap the whole migration in a transaction to make sure it succeeds or fails atomically.
+BEGIN;
+    -- Existing subscribers signed up before confirmations existed, so they count as confirmed.
+    ALTER TABLE subscriptions ADD COLUMN status TEXT NULL;
+    UPDATE subscriptions SET status = 'confirmed' WHERE status IS NULL;
+    ALTER TABLE subscriptions ALTER COLUMN status SET NOT NULL;
+COMMIT;
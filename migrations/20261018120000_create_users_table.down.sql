@@ -0,0 +1,3 @@
+-- migrations/20261018120000_create_users_table.down.sql
+-- Drop Users Table
+DROP TABLE users;
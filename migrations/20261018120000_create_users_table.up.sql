@@ -0,0 +1,10 @@
+-- migrations/20261018120000_create_users_table.up.sql
+-- Create Users Table
+-- The operators of the newsletter; passwords are stored as Argon2id PHC strings.
+CREATE TABLE users(
+    user_id uuid NOT NULL,
+    PRIMARY KEY (user_id),
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
@@ -0,0 +1,3 @@
+-- migrations/20261018130000_create_subscriber_tags_table.down.sql
+-- Drop Subscriber Tags Table
+DROP TABLE subscriber_tags;
@@ -0,0 +1,8 @@
+-- migrations/20261018130000_create_subscriber_tags_table.up.sql
+-- Create Subscriber Tags Table
+-- Free-form labels, such as those of the platforms that subscribers are imported from.
+CREATE TABLE subscriber_tags(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, tag)
+);
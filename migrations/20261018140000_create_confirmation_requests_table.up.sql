@@ -0,0 +1,7 @@
+-- migrations/20261018140000_create_confirmation_requests_table.up.sql
+-- Create Confirmation Requests Table
+-- The subscribers who are waiting for a confirmation email, such as those imported with `reconfirm`.
+CREATE TABLE confirmation_requests(
+    subscriber_id uuid NOT NULL PRIMARY KEY REFERENCES subscriptions (id) ON DELETE CASCADE,
+    requested_at timestamptz NOT NULL
+);
@@ -0,0 +1,11 @@
+-- migrations_sqlite/20231004180141_create_subscriptions_table.up.sql
+-- Create Subscriptions Table
+-- SQLite counterpart of `migrations/20231004180141_create_subscriptions_table.sql`.
+-- UUIDs and timestamps are stored as TEXT.
+CREATE TABLE subscriptions(
+    id TEXT NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    subscribed_at TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,4 @@
+-- migrations_sqlite/20231024154512_add_review_reason_to_subscriptions.up.sql
+-- Add Review Reason Column to Subscriptions Table
+-- SQLite counterpart of `migrations/20231024154512_add_review_reason_to_subscriptions.sql`.
+ALTER TABLE subscriptions ADD COLUMN review_reason TEXT NULL;
@@ -0,0 +1,3 @@
+-- migrations_sqlite/20231025093027_add_status_to_subscriptions.down.sql
+-- Drop Status Column from Subscriptions Table
+ALTER TABLE subscriptions DROP COLUMN status;
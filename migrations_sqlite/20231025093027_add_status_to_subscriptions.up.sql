@@ -0,0 +1,5 @@
+-- migrations_sqlite/20231025093027_add_status_to_subscriptions.up.sql
+-- Add Status Column to Subscriptions Table
+-- SQLite counterpart of `migrations/20231025093027_add_status_to_subscriptions.sql`.
+-- SQLite can't add a NOT NULL constraint to an existing column, so we use a default instead.
+ALTER TABLE subscriptions ADD COLUMN status TEXT NOT NULL DEFAULT 'confirmed';
@@ -0,0 +1,11 @@
+-- migrations_sqlite/20261018120000_create_users_table.up.sql
+-- Create Users Table
+-- SQLite counterpart of `migrations/20261018120000_create_users_table.up.sql`.
+-- UUIDs and timestamps are stored as TEXT.
+CREATE TABLE users(
+    user_id TEXT NOT NULL,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (user_id)
+);
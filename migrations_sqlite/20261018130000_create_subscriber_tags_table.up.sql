@@ -0,0 +1,9 @@
+-- migrations_sqlite/20261018130000_create_subscriber_tags_table.up.sql
+-- Create Subscriber Tags Table
+-- SQLite counterpart of `migrations/20261018130000_create_subscriber_tags_table.up.sql`.
+-- UUIDs are stored as TEXT.
+CREATE TABLE subscriber_tags(
+    subscriber_id TEXT NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, tag)
+);
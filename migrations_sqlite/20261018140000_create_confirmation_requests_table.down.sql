@@ -0,0 +1,3 @@
+-- migrations_sqlite/20261018140000_create_confirmation_requests_table.down.sql
+-- Drop Confirmation Requests Table
+DROP TABLE confirmation_requests;
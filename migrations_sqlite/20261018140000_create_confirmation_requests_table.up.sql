@@ -0,0 +1,8 @@
+-- migrations_sqlite/20261018140000_create_confirmation_requests_table.up.sql
+-- Create Confirmation Requests Table
+-- SQLite counterpart of `migrations/20261018140000_create_confirmation_requests_table.up.sql`.
+-- UUIDs and timestamps are stored as TEXT.
+CREATE TABLE confirmation_requests(
+    subscriber_id TEXT NOT NULL PRIMARY KEY REFERENCES subscriptions (id) ON DELETE CASCADE,
+    requested_at TEXT NOT NULL
+);
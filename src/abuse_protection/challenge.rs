@@ -0,0 +1,194 @@
+//! src/abuse_protection/challenge.rs
+
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+/// Verifies the response to a challenge, such as a CAPTCHA or a proof-of-work puzzle,
+/// which the signup form asks the client to solve
+///
+/// `response` is the value of the `challenge_response` form field, if the client sent one.
+///
+/// Returns `Ok(true)` if the challenge was solved, `Ok(false)` if it wasn't,
+/// and `Err` if the verification itself failed.
+#[async_trait::async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+    async fn verify(&self, response: Option<&str>, client_ip: &str) -> Result<bool, String>;
+}
+
+/// A `ChallengeVerifier` which doesn't require any challenge
+///
+/// Used when challenges are turned off in configuration.
+pub struct NoopChallengeVerifier;
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for NoopChallengeVerifier {
+    async fn verify(&self, _response: Option<&str>, _client_ip: &str) -> Result<bool, String> {
+        Ok(true)
+    }
+}
+
+/// A local `ChallengeVerifier` which accepts a single, known, response
+///
+/// Doesn't need the network, so it is meant for tests.
+pub struct FakeChallengeVerifier {
+    expected_response: String,
+}
+
+impl FakeChallengeVerifier {
+    pub fn new(expected_response: impl Into<String>) -> Self {
+        Self {
+            expected_response: expected_response.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for FakeChallengeVerifier {
+    async fn verify(&self, response: Option<&str>, _client_ip: &str) -> Result<bool, String> {
+        Ok(response == Some(self.expected_response.as_str()))
+    }
+}
+
+/// A `ChallengeVerifier` which asks a CAPTCHA provider to verify the response
+///
+/// It speaks the "siteverify" protocol, which is shared by hCaptcha, Cloudflare Turnstile
+/// and reCAPTCHA: the secret, the response and the client IP are posted as a form,
+/// and the provider answers with a JSON object with a boolean `success` field.
+#[derive(Debug)]
+pub struct SiteVerifyChallengeVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret: Secret<String>,
+}
+
+impl SiteVerifyChallengeVerifier {
+    pub fn new(verify_url: String, secret: Secret<String>, timeout: std::time::Duration) -> Self {
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("Failed to build an HTTP client.");
+        Self {
+            http_client,
+            verify_url,
+            secret,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for SiteVerifyChallengeVerifier {
+    #[tracing::instrument(name = "Verifying the challenge response", skip(self, response))]
+    async fn verify(&self, response: Option<&str>, client_ip: &str) -> Result<bool, String> {
+        let response = match response {
+            Some(response) if !response.is_empty() => response,
+            _ => return Ok(false),
+        };
+
+        let outcome: SiteVerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&[
+                ("secret", self.secret.expose_secret().as_str()),
+                ("response", response),
+                ("remoteip", client_ip),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(outcome.success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claims::{assert_err, assert_ok_eq};
+    use rstest::rstest;
+    use wiremock::matchers::{body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[rstest]
+    #[case::expected(Some("solved"), true)]
+    #[case::unexpected(Some("guessed"), false)]
+    #[case::missing(None, false)]
+    #[tokio::test]
+    async fn fake_verifier_only_accepts_the_expected_response(
+        #[case] response: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        let verifier = FakeChallengeVerifier::new("solved");
+        assert_ok_eq!(verifier.verify(response, "10.0.0.1").await, expected);
+    }
+
+    #[rstest]
+    #[case::success(r#"{"success": true}"#, true)]
+    #[case::failure(
+        r#"{"success": false, "error-codes": ["invalid-input-response"]}"#,
+        false
+    )]
+    #[tokio::test]
+    async fn site_verify_verifier_returns_the_providers_verdict(
+        #[case] body: &str,
+        #[case] expected: bool,
+    ) {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("response=solved"))
+            .and(body_string_contains("secret=top-secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/json"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let verifier = SiteVerifyChallengeVerifier::new(
+            mock_server.uri(),
+            Secret::new(String::from("top-secret")),
+            std::time::Duration::from_millis(200),
+        );
+
+        assert_ok_eq!(verifier.verify(Some("solved"), "10.0.0.1").await, expected);
+    }
+
+    #[tokio::test]
+    async fn site_verify_verifier_rejects_a_missing_response_without_asking_the_provider() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let verifier = SiteVerifyChallengeVerifier::new(
+            mock_server.uri(),
+            Secret::new(String::from("top-secret")),
+            std::time::Duration::from_millis(200),
+        );
+
+        assert_ok_eq!(verifier.verify(None, "10.0.0.1").await, false);
+    }
+
+    #[tokio::test]
+    async fn site_verify_verifier_fails_if_the_provider_fails() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+        let verifier = SiteVerifyChallengeVerifier::new(
+            mock_server.uri(),
+            Secret::new(String::from("top-secret")),
+            std::time::Duration::from_millis(200),
+        );
+
+        assert_err!(verifier.verify(Some("solved"), "10.0.0.1").await);
+    }
+}
@@ -0,0 +1,91 @@
+//! src/abuse_protection/form_token.rs
+
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+/// Issues and verifies the tokens which tell when the signup form was rendered
+///
+/// A token is the Unix timestamp in milliseconds at which it was issued, and an HMAC-SHA256
+/// of that timestamp, keyed by a secret that only the server knows, as in `1700000000000.9f86d0...`.
+/// Clients can read the timestamp, but they can't change it, or make up a token of their own.
+pub struct FormTokens {
+    key: Secret<String>,
+}
+
+impl FormTokens {
+    pub fn new(key: Secret<String>) -> Self {
+        Self { key }
+    }
+
+    /// Issues a token for a form rendered at `rendered_at`
+    pub fn issue(&self, rendered_at: DateTime<Utc>) -> String {
+        let timestamp = rendered_at.timestamp_millis().to_string();
+        let signature = self.mac(&timestamp).finalize().into_bytes();
+
+        format!("{}.{}", timestamp, hex::encode(signature))
+    }
+
+    /// Returns when the form was rendered, if `token` was issued by `issue`
+    pub fn verify(&self, token: &str) -> Option<DateTime<Utc>> {
+        let (timestamp, signature) = token.split_once('.')?;
+        let signature = hex::decode(signature).ok()?;
+        // `verify_slice` compares in constant time, so response times don't leak the signature
+        self.mac(timestamp).verify_slice(&signature).ok()?;
+
+        Utc.timestamp_millis_opt(timestamp.parse().ok()?).single()
+    }
+
+    fn mac(&self, timestamp: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.expose_secret().as_bytes())
+            .expect("HMAC accepts keys of any length.");
+        mac.update(timestamp.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claims::{assert_none, assert_some_eq};
+    use rstest::rstest;
+
+    fn form_tokens() -> FormTokens {
+        FormTokens::new(Secret::new(String::from("form-token-key")))
+    }
+
+    #[test]
+    fn issued_tokens_are_verified() {
+        let rendered_at = Utc.timestamp_millis_opt(1_700_000_000_123).unwrap();
+
+        let token = form_tokens().issue(rendered_at);
+
+        assert_some_eq!(form_tokens().verify(&token), rendered_at);
+    }
+
+    #[rstest]
+    #[case::empty("")]
+    #[case::no_signature("1700000000000")]
+    #[case::not_hex("1700000000000.zz")]
+    #[case::wrong_signature("1700000000000.00")]
+    fn forged_tokens_are_rejected(#[case] token: &str) {
+        assert_none!(form_tokens().verify(token));
+    }
+
+    #[test]
+    fn tokens_with_a_changed_timestamp_are_rejected() {
+        let token = form_tokens().issue(Utc::now());
+        let (_, signature) = token.split_once('.').unwrap();
+
+        assert_none!(form_tokens().verify(&format!("0.{}", signature)));
+    }
+
+    #[test]
+    fn tokens_issued_with_another_key_are_rejected() {
+        let token = FormTokens::new(Secret::new(String::from("another-key"))).issue(Utc::now());
+
+        assert_none!(form_tokens().verify(&token));
+    }
+}
@@ -0,0 +1,144 @@
+//! src/abuse_protection/mod.rs
+
+mod challenge;
+mod form_token;
+mod rate_limiter;
+
+pub use challenge::*;
+pub use form_token::*;
+pub use rate_limiter::*;
+
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Form tokens older than this are refused, so that one token can't be reused forever
+const MAX_FORM_TOKEN_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Everything that protects the public signup endpoint from bots
+///
+/// It consists of:
+///  - `ip_rate_limiter` - used by the `IpRateLimit` middleware, before the form is even parsed;
+///  - `domain_rate_limiter` - limits signups per email domain, so that a bot can't
+///    mass-subscribe the victims at one organization from many IP addresses;
+///  - `use_forwarded_ip` - whether to trust the `Forwarded` and `X-Forwarded-For` headers;
+///  - `min_fill_time` - humans need some time to fill in a form, bots don't;
+///  - `form_tokens` - tell when the form was rendered, without trusting the client;
+///  - `challenge_verifier` - an optional CAPTCHA or proof-of-work check.
+///
+/// The form also contains a *honeypot* field, which is hidden from humans,
+/// so only bots fill it in.
+pub struct SignupProtection {
+    pub ip_rate_limiter: Arc<RateLimiter>,
+    pub domain_rate_limiter: Arc<RateLimiter>,
+    pub use_forwarded_ip: bool,
+    pub min_fill_time: Duration,
+    pub form_tokens: FormTokens,
+    pub challenge_verifier: Arc<dyn ChallengeVerifier>,
+}
+
+impl SignupProtection {
+    /// Checks the bot traps in the signup form
+    ///
+    /// `honeypot` is the value of the hidden honeypot field, and `form_token` is the token
+    /// that `FormTokens` issued when the form was rendered, if the form sent one.
+    /// Unless `min_fill_time` is zero, which turns the check off, a submission without
+    /// a valid token is as suspicious as one that is filled in too fast.
+    ///
+    /// Returns the reason if the submission looks automated, and `None` otherwise.
+    pub fn detect_bot(&self, honeypot: &str, form_token: Option<&str>) -> Option<String> {
+        if !honeypot.is_empty() {
+            return Some(String::from("the honeypot field is filled in"));
+        }
+        if self.min_fill_time.is_zero() {
+            return None;
+        }
+
+        let Some(form_token) = form_token.filter(|form_token| !form_token.is_empty()) else {
+            return Some(String::from("the form token is missing"));
+        };
+        let Some(rendered_at) = self.form_tokens.verify(form_token) else {
+            return Some(String::from("the form token is forged"));
+        };
+        let fill_time_millis = (Utc::now() - rendered_at).num_milliseconds();
+        if fill_time_millis < self.min_fill_time.as_millis() as i64 {
+            return Some(format!(
+                "the form was filled in {} ms, which is too fast",
+                fill_time_millis
+            ));
+        }
+        if fill_time_millis > MAX_FORM_TOKEN_AGE.as_millis() as i64 {
+            return Some(String::from("the form token has expired"));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::{fixture, rstest};
+    use secrecy::Secret;
+
+    #[fixture]
+    fn protection() -> SignupProtection {
+        SignupProtection {
+            ip_rate_limiter: Arc::new(RateLimiter::new(1, Duration::from_secs(60))),
+            domain_rate_limiter: Arc::new(RateLimiter::new(1, Duration::from_secs(60))),
+            use_forwarded_ip: false,
+            min_fill_time: Duration::from_secs(2),
+            form_tokens: FormTokens::new(Secret::new(String::from("form-token-key"))),
+            challenge_verifier: Arc::new(NoopChallengeVerifier),
+        }
+    }
+
+    /// A token for a form that was rendered `millis_ago`
+    fn token(protection: &SignupProtection, millis_ago: i64) -> String {
+        let rendered_at = Utc::now() - chrono::Duration::milliseconds(millis_ago);
+        protection.form_tokens.issue(rendered_at)
+    }
+
+    #[rstest]
+    fn detect_bot_lets_humans_through(protection: SignupProtection) {
+        let form_token = token(&protection, 10_000);
+
+        assert_eq!(None, protection.detect_bot("", Some(&form_token)));
+    }
+
+    #[rstest]
+    #[case::honeypot_filled_in("https://spam.yq", Some(10_000))]
+    #[case::without_a_token("", None)]
+    #[case::filled_in_too_fast("", Some(100))]
+    #[case::rendered_in_the_future("", Some(-10_000))]
+    #[case::expired("", Some(MAX_FORM_TOKEN_AGE.as_millis() as i64 + 10_000))]
+    fn detect_bot_catches_bots(
+        protection: SignupProtection,
+        #[case] honeypot: &str,
+        #[case] rendered_millis_ago: Option<i64>,
+    ) {
+        let form_token = rendered_millis_ago.map(|millis_ago| token(&protection, millis_ago));
+
+        assert!(protection
+            .detect_bot(honeypot, form_token.as_deref())
+            .is_some());
+    }
+
+    #[rstest]
+    fn detect_bot_catches_forged_tokens(protection: SignupProtection) {
+        let rendered_at = Utc::now().timestamp_millis() - 10_000;
+
+        assert!(protection
+            .detect_bot("", Some(&format!("{}.00", rendered_at)))
+            .is_some());
+    }
+
+    #[rstest]
+    fn tokens_are_not_needed_without_a_minimum_fill_time(mut protection: SignupProtection) {
+        protection.min_fill_time = Duration::ZERO;
+
+        assert_eq!(None, protection.detect_bot("", None));
+        assert!(protection.detect_bot("https://spam.yq", None).is_some());
+    }
+}
@@ -0,0 +1,293 @@
+//! src/abuse_protection/rate_limiter.rs
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::ErrorTooManyRequests;
+use actix_web::http::header::{FORWARDED, X_FORWARDED_FOR};
+use actix_web::HttpRequest;
+use futures_util::future::LocalBoxFuture;
+use lru::LruCache;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// The most keys tracked at once; the least recently seen one makes room for a new one.
+const MAX_TRACKED_KEYS: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+
+/// A fixed-window rate limiter
+///
+/// Allows up to `max_requests` per `window` for every key, such as an IP address
+/// or an email domain. Counters live in memory, so every instance of the
+/// application keeps its own.
+///
+/// The limits can be changed while it is in use, with `set_limits`.
+///
+/// At most `MAX_TRACKED_KEYS` keys are tracked. Evicting the least recently seen key
+/// resets its count, which only matters to clients who sent nothing for the longest.
+pub struct RateLimiter {
+    limits: RwLock<(u32, Duration)>,
+    windows: Mutex<LruCache<String, (u32, Instant)>>,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, window: Duration) -> Self {
+        Self {
+            limits: RwLock::new((max_requests, window)),
+            windows: Mutex::new(LruCache::new(MAX_TRACKED_KEYS)),
+        }
+    }
+
+    /// Replaces the limits; the requests counted so far still count
+    pub fn set_limits(&self, max_requests: u32, window: Duration) {
+        *self.limits.write().expect("Rate limiter lock is poisoned.") = (max_requests, window);
+    }
+
+    /// Counts a request for `key`
+    ///
+    /// Returns `true` if the request is within the limit, and `false` if it should be refused.
+    pub fn check(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let (max_requests, window) = *self.limits.read().expect("Rate limiter lock is poisoned.");
+        let mut windows = self.windows.lock().expect("Rate limiter lock is poisoned.");
+
+        let (count, started_at) = windows.get_or_insert_mut(key.to_string(), || (0, now));
+        if now.duration_since(*started_at) >= window {
+            *count = 0;
+            *started_at = now;
+        }
+
+        if *count < max_requests {
+            *count += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Middleware which limits the number of requests per client IP address
+///
+/// Requests over the limit are refused with `429 Too Many Requests`.
+///
+/// The IP address is the peer address of the connection, unless `use_forwarded_ip` is set,
+/// in which case it is the address that the proxy in front of the application appended
+/// to the `Forwarded` or `X-Forwarded-For` header. See `client_ip`.
+pub struct IpRateLimit {
+    limiter: Arc<RateLimiter>,
+    use_forwarded_ip: bool,
+}
+
+impl IpRateLimit {
+    pub fn new(limiter: Arc<RateLimiter>, use_forwarded_ip: bool) -> Self {
+        Self {
+            limiter,
+            use_forwarded_ip,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IpRateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = IpRateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IpRateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+            use_forwarded_ip: self.use_forwarded_ip,
+        }))
+    }
+}
+
+pub struct IpRateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: Arc<RateLimiter>,
+    use_forwarded_ip: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for IpRateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let ip = client_ip(req.request(), self.use_forwarded_ip);
+        let is_allowed = self.limiter.check(&ip);
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            if is_allowed {
+                service.call(req).await
+            } else {
+                tracing::warn!("Too many requests from the IP address {}.", ip);
+                Err(ErrorTooManyRequests("Too many requests."))
+            }
+        })
+    }
+}
+
+/// Gets the client IP address of the request as a string
+///
+/// With `use_forwarded_ip`, the application must run behind exactly one trusted proxy,
+/// which appends the address of its peer to the `Forwarded` or `X-Forwarded-For` header.
+/// Only that last entry is taken: the ones before it were sent by the client, who can forge them.
+pub fn client_ip(req: &HttpRequest, use_forwarded_ip: bool) -> String {
+    if use_forwarded_ip {
+        if let Some(ip) = forwarded_ip(req) {
+            return ip.to_string();
+        }
+    }
+
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default()
+}
+
+/// Gets the last address in the `Forwarded` header, or else in the `X-Forwarded-For` header
+fn forwarded_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let last_entry = |name| {
+        let value = req.headers().get_all(name).last()?.to_str().ok()?;
+        value.rsplit(',').next().map(str::trim)
+    };
+
+    if let Some(element) = last_entry(FORWARDED) {
+        let node = element.split(';').find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            name.eq_ignore_ascii_case("for").then_some(value)
+        })?;
+        return parse_node(node.trim_matches('"'));
+    }
+    parse_node(last_entry(X_FORWARDED_FOR)?)
+}
+
+/// Parses an IP address with an optional port, as in `192.0.2.1:4711` or `[2001:db8::1]:4711`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    match node.strip_prefix('[') {
+        Some(bracketed) => bracketed.split_once(']')?.0.parse().ok(),
+        None => node.split_once(':')?.0.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test::{call_service, init_service, try_call_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+    use rstest::rstest;
+
+    #[test]
+    fn limiter_refuses_requests_over_the_limit() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+
+        assert!(limiter.check("a"));
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+        assert!(limiter.check("b"), "Keys must be limited independently.");
+    }
+
+    #[test]
+    fn limiter_starts_a_new_window_once_the_old_one_expires() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(20));
+
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(limiter.check("a"));
+    }
+
+    #[test]
+    fn limits_can_be_changed_while_in_use() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+        limiter.set_limits(2, Duration::from_secs(60));
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+    }
+
+    #[test]
+    fn limiter_evicts_the_least_recently_seen_key() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+
+        assert!(limiter.check("a"));
+        for key in 0..MAX_TRACKED_KEYS.get() {
+            limiter.check(&key.to_string());
+        }
+
+        assert_eq!(
+            MAX_TRACKED_KEYS.get(),
+            limiter.windows.lock().unwrap().len()
+        );
+        assert!(limiter.check("a"), "The evicted key must start over.");
+    }
+
+    #[rstest]
+    #[case::x_forwarded_for(X_FORWARDED_FOR, "203.0.113.7", "203.0.113.7")]
+    #[case::x_forwarded_for_spoofed(X_FORWARDED_FOR, "198.51.100.1, 203.0.113.7", "203.0.113.7")]
+    #[case::forwarded(FORWARDED, "for=203.0.113.7;proto=https", "203.0.113.7")]
+    #[case::forwarded_spoofed(FORWARDED, "for=198.51.100.1, for=203.0.113.7", "203.0.113.7")]
+    #[case::forwarded_with_port(FORWARDED, r#"for="203.0.113.7:4711""#, "203.0.113.7")]
+    #[case::forwarded_ipv6(FORWARDED, r#"for="[2001:db8::1]:4711""#, "2001:db8::1")]
+    #[case::not_an_address(X_FORWARDED_FOR, "unknown", "10.0.0.1")]
+    #[case::obfuscated(FORWARDED, "for=_hidden", "10.0.0.1")]
+    fn client_ip_is_the_entry_appended_by_the_proxy(
+        #[case] header: actix_web::http::header::HeaderName,
+        #[case] value: &str,
+        #[case] expected: &str,
+    ) {
+        let request = TestRequest::get()
+            .peer_addr("10.0.0.1:4242".parse().unwrap())
+            .insert_header((header, value))
+            .to_http_request();
+
+        assert_eq!(expected, client_ip(&request, true));
+        assert_eq!("10.0.0.1", client_ip(&request, false));
+    }
+
+    #[actix_web::test]
+    async fn middleware_responds_with_429_over_the_limit() {
+        let limiter = Arc::new(RateLimiter::new(1, Duration::from_secs(60)));
+        let app = init_service(
+            App::new()
+                .wrap(IpRateLimit::new(limiter, false))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let peer_addr = "10.0.0.1:4242".parse().unwrap();
+
+        let request = TestRequest::get().peer_addr(peer_addr).to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(200, response.status().as_u16());
+
+        let request = TestRequest::get().peer_addr(peer_addr).to_request();
+        let response = try_call_service(&app, request).await;
+        assert_eq!(
+            429,
+            response
+                .expect_err("The request should be refused.")
+                .as_response_error()
+                .status_code()
+                .as_u16()
+        );
+    }
+}
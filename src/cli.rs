@@ -0,0 +1,378 @@
+//! src/cli.rs
+//!
+//! The operations that the binary can run besides serving the app.
+//! Data goes to stdout, while progress and errors go to stderr.
+
+use crate::configuration::{current_environment, Settings};
+use crate::database::MigrationState;
+use crate::domain::{SubscriberEmail, SubscriptionStatus};
+use crate::export::{encode, parse_timestamp, ExportFormat};
+use crate::import::{ImportOptions, SubscriberImport};
+use crate::maintenance::{check_environment, drop_test_databases};
+use crate::metrics::Metrics;
+use crate::repository::{RepositoryError, Subscriber, SubscriberFilter};
+use crate::users::create_admin;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+use secrecy::Secret;
+use std::io::{BufRead, Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+type CommandResult = Result<(), Box<dyn std::error::Error>>;
+
+#[derive(Debug, Parser)]
+#[command(name = "zero2prod", about = "A newsletter API", version)]
+pub struct Cli {
+    /// Same as `config print`, kept for the scripts that already use it
+    #[arg(long, hide = true)]
+    pub check_config: bool,
+
+    /// What to do; the app is started if it is missing
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// The command to run, once the flags are resolved
+    pub fn into_command(self) -> Command {
+        match (self.check_config, self.command) {
+            (true, _) => Command::Config {
+                command: ConfigCommand::Print,
+            },
+            (false, Some(command)) => command,
+            (false, None) => Command::Serve,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the app
+    Serve,
+    /// Manage the database schema, with the migrations that are embedded in the binary
+    Migrate {
+        #[command(subcommand)]
+        command: MigrateCommand,
+    },
+    /// Create an admin user, reading the password from the first line of stdin
+    CreateAdmin { username: String },
+    /// Export or import the subscribers
+    Subscribers {
+        #[command(subcommand)]
+        command: SubscribersCommand,
+    },
+    /// Send an email to `address` through the configured email provider
+    SendTestEmail { address: String },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Drop the databases that the integration tests left behind
+    GcTestDatabases {
+        /// Only drop the databases that are older than this
+        #[arg(long, default_value_t = 24)]
+        older_than_hours: u64,
+        /// Run even if the environment isn't `local` nor `test`, such as in production
+        #[arg(long)]
+        any_environment: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateCommand {
+    /// Apply the pending migrations
+    Up,
+    /// List the migrations, and whether they are applied
+    Status,
+    /// Revert the last applied migration
+    Revert,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SubscribersCommand {
+    /// Write the subscribers to stdout, oldest first, one CSV row or JSON object per line
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::default())]
+        format: ExportFormat,
+        /// Only export the subscribers with this status
+        #[arg(long, value_parser = parse_status)]
+        status: Option<SubscriptionStatus>,
+        /// Only export those who subscribed at, or after, this date or RFC 3339 timestamp
+        #[arg(long, value_parser = parse_timestamp)]
+        subscribed_since: Option<DateTime<Utc>>,
+        /// Only export those who subscribed before this date or RFC 3339 timestamp
+        #[arg(long, value_parser = parse_timestamp)]
+        subscribed_before: Option<DateTime<Utc>>,
+    },
+    /// Import subscribers, such as those of another platform, out of the CSV `file`, or stdin
+    ///
+    /// The CSV has a header row, with the `email` and `name` columns, and optionally
+    /// the `tags` and `subscribed_at` columns. The report of every row is written to stdout.
+    /// The subscribers are pending confirmation, like those who sign up, unless `--confirmed` is set.
+    Import {
+        file: Option<PathBuf>,
+        /// Mark the subscribers as confirmed, instead of pending confirmation
+        #[arg(long, conflicts_with = "reconfirm")]
+        confirmed: bool,
+        /// Queue a confirmation email for each of the subscribers, who are pending confirmation
+        #[arg(long)]
+        reconfirm: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration, and print it with the secrets redacted
+    Print,
+}
+
+/// Runs `command`, which mustn't be `Command::Serve`, as that one is run by `main`
+pub async fn run(command: Command, configuration: Settings) -> CommandResult {
+    match command {
+        Command::Serve => unreachable!("The app is started by `main`."),
+        Command::Migrate { command } => migrate(command, &configuration).await,
+        Command::CreateAdmin { username } => {
+            let password = read_password(std::io::stdin().lock())?;
+            let pool = configuration.database.get_pool().await?;
+            let outcome = create_admin(&pool, &username, &password).await;
+            pool.close().await;
+            let user_id = outcome?;
+            println!("{}", user_id);
+            eprintln!("Created the admin '{}'.", username.trim());
+            Ok(())
+        }
+        Command::Subscribers {
+            command:
+                SubscribersCommand::Export {
+                    format,
+                    status,
+                    subscribed_since,
+                    subscribed_before,
+                },
+        } => {
+            let filter = SubscriberFilter {
+                status,
+                subscribed_since,
+                subscribed_before,
+            };
+            export_subscribers(filter, format, &configuration).await
+        }
+        Command::Subscribers {
+            command:
+                SubscribersCommand::Import {
+                    file,
+                    confirmed,
+                    reconfirm,
+                },
+        } => {
+            let input: Box<dyn Read> = match file {
+                Some(file) => Box::new(std::fs::File::open(file)?),
+                None => Box::new(std::io::stdin()),
+            };
+            let options = ImportOptions {
+                confirmed,
+                reconfirm,
+            };
+            import_csv(input, options, &configuration).await
+        }
+        Command::SendTestEmail { address } => {
+            let recipient = SubscriberEmail::parse(address)?;
+            let email_client = configuration.email_client.get_client(Metrics::new())?;
+            email_client
+                .send_email(
+                    recipient,
+                    "zero2prod test email",
+                    "<p>The email provider is set up correctly.</p>",
+                    "The email provider is set up correctly.",
+                )
+                .await?;
+            eprintln!("Sent the test email.");
+            Ok(())
+        }
+        Command::Config {
+            command: ConfigCommand::Print,
+        } => {
+            println!("{}", serde_json::to_string_pretty(&configuration)?);
+            Ok(())
+        }
+        Command::GcTestDatabases {
+            older_than_hours,
+            any_environment,
+        } => {
+            check_environment(&current_environment()?, any_environment)?;
+            let connection_options = configuration.database.without_db();
+            let older_than = Duration::from_secs(older_than_hours * 60 * 60);
+            let dropped = drop_test_databases(&connection_options, older_than).await?;
+            for database_name in &dropped {
+                println!("{}", database_name);
+            }
+            eprintln!("Dropped {} test databases.", dropped.len());
+            Ok(())
+        }
+    }
+}
+
+async fn migrate(command: MigrateCommand, configuration: &Settings) -> CommandResult {
+    let pool = configuration.database.get_pool().await?;
+    let outcome = match command {
+        MigrateCommand::Up => pool.run_migrations().await.map(|applied| {
+            for version in &applied {
+                println!("{}", version);
+            }
+            eprintln!("Applied {} migrations.", applied.len());
+        }),
+        MigrateCommand::Status => pool.migration_status().await.map(|status| {
+            for migration in status {
+                println!(
+                    "{}\t{}\t{}",
+                    migration.version,
+                    migration.state.as_str(),
+                    migration.description
+                );
+                if migration.state == MigrationState::Unknown {
+                    eprintln!(
+                        "The migration {} was applied by a newer version of the app.",
+                        migration.version
+                    );
+                }
+            }
+        }),
+        MigrateCommand::Revert => {
+            pool.revert_last_migration()
+                .await
+                .map(|reverted| match reverted {
+                    Some(version) => {
+                        println!("{}", version);
+                        eprintln!("Reverted the migration {}.", version);
+                    }
+                    None => eprintln!("No migration is applied."),
+                })
+        }
+    };
+    pool.close().await;
+
+    Ok(outcome?)
+}
+
+fn parse_status(status: &str) -> Result<SubscriptionStatus, String> {
+    SubscriptionStatus::try_from(status.to_owned())
+}
+
+/// Reads the password from the first line of `input`, so that it isn't in the shell's history
+fn read_password(mut input: impl BufRead) -> Result<Secret<String>, std::io::Error> {
+    let mut password = String::new();
+    input.read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']).to_owned();
+
+    Ok(Secret::new(password))
+}
+
+async fn export_subscribers(
+    filter: SubscriberFilter,
+    format: ExportFormat,
+    configuration: &Settings,
+) -> CommandResult {
+    let repository = configuration.database.get_repository().await?;
+    let mut exported = 0;
+    let outcome = write_export(
+        repository.export(filter).inspect(|_| exported += 1).boxed(),
+        format,
+    )
+    .await;
+    repository.close().await;
+    outcome?;
+    eprintln!("Exported {} subscribers.", exported);
+
+    Ok(())
+}
+
+/// Writes the subscribers to stdout, as they are read, rather than once they all are
+async fn write_export(
+    subscribers: BoxStream<'_, Result<Subscriber, RepositoryError>>,
+    format: ExportFormat,
+) -> CommandResult {
+    let mut stdout = std::io::BufWriter::new(std::io::stdout());
+    let mut chunks = encode(subscribers, format);
+    while let Some(chunk) = chunks.try_next().await? {
+        stdout.write_all(&chunk)?;
+    }
+    stdout.flush()?;
+
+    Ok(())
+}
+
+async fn import_csv(
+    mut input: impl Read,
+    options: ImportOptions,
+    configuration: &Settings,
+) -> CommandResult {
+    let repository = configuration.database.get_repository().await?;
+    let mut import = SubscriberImport::new(repository.clone(), options);
+    // Read in chunks, so that large files aren't held in memory
+    let mut chunk = vec![0; 64 * 1024];
+    let outcome = loop {
+        let read = input.read(&mut chunk)?;
+        if read == 0 {
+            break import.finish().await;
+        }
+        if let Err(e) = import.feed(&chunk[..read]).await {
+            break Err(e);
+        }
+    };
+    repository.close().await;
+    let report = outcome?;
+
+    report.write_csv(std::io::stdout().lock())?;
+    eprintln!(
+        "Accepted {} rows, skipped {} duplicates, and rejected {} rows.",
+        report.accepted(),
+        report.duplicates(),
+        report.rejected()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn the_cli_definition_is_consistent() {
+        <Cli as clap::CommandFactory>::command().debug_assert();
+    }
+
+    #[test]
+    fn check_config_is_an_alias_of_config_print() {
+        let cli = Cli::try_parse_from(["zero2prod", "--check-config"]).unwrap();
+
+        assert!(matches!(
+            cli.into_command(),
+            Command::Config {
+                command: ConfigCommand::Print
+            }
+        ));
+    }
+
+    #[test]
+    fn the_app_is_served_without_a_command() {
+        let cli = Cli::try_parse_from(["zero2prod"]).unwrap();
+
+        assert!(matches!(cli.into_command(), Command::Serve));
+    }
+
+    #[test]
+    fn only_the_first_line_is_the_password() {
+        let password = read_password("correct horse battery staple\r\nignored\n".as_bytes());
+
+        assert_eq!(
+            "correct horse battery staple",
+            password.unwrap().expose_secret()
+        );
+    }
+}
@@ -1,34 +1,140 @@
 //! src/configuration.rs
 
-use crate::domain::SubscriberEmail;
+use crate::abuse_protection::{
+    ChallengeVerifier, FormTokens, NoopChallengeVerifier, RateLimiter, SignupProtection,
+    SiteVerifyChallengeVerifier,
+};
+use crate::consts::ROLE_LOCAL_PARTS;
+use crate::database::DatabasePool;
+use crate::domain::{EmailPolicy, PolicyAction, PolicyRule, SubscriberEmail};
+use crate::domain_verifier::{
+    CachedDomainVerifier, DnsDomainVerifier, DomainVerifier, NoopDomainVerifier,
+};
+use crate::email_client::EmailClient;
+use crate::metrics::Metrics;
+use crate::redaction::RedactionPolicy;
+use crate::repository::{
+    PostgresSubscriberRepository, SqliteSubscriberRepository, SubscriberRepository,
+};
+use crate::routes::ReadinessCheck;
+use crate::secrets::{FileSecretProvider, SecretProvider};
+use crate::shutdown::Shutdown;
+use crate::telemetry::{get_tracer_provider, LogFormat};
+use crate::tls::{Tls, TlsError};
+use opentelemetry::trace::TraceError;
+use opentelemetry_sdk::trace::TracerProvider;
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
-use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::ConnectOptions;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub email_policy: EmailPolicySettings,
+    pub domain_verification: DomainVerificationSettings,
+    pub abuse_protection: AbuseProtectionSettings,
+    pub readiness: ReadinessSettings,
+    pub metrics: MetricsSettings,
+    pub opentelemetry: OpenTelemetrySettings,
+    pub logging: LoggingSettings,
+    pub subscribers: SubscribersSettings,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct ApplicationSettings {
     pub host: String,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
+    /// Take the request id from the incoming `X-Request-Id` header, if it is well-formed
+    #[serde(default)]
+    pub trust_incoming_request_id: bool,
+    /// How long the requests in flight, and then the background jobs, get, in all, to finish on shutdown
+    #[serde(default = "default_shutdown_grace_period_secs")]
+    pub shutdown_grace_period_secs: u64,
+    #[serde(default)]
+    pub tls: TlsSettings,
 }
 
-#[derive(serde::Deserialize)]
+fn default_shutdown_grace_period_secs() -> u64 {
+    30
+}
+
+impl ApplicationSettings {
+    pub fn get_shutdown(&self) -> Shutdown {
+        Shutdown::new(std::time::Duration::from_secs(
+            self.shutdown_grace_period_secs,
+        ))
+    }
+}
+
+/// HTTPS, for when the app isn't behind a proxy which terminates TLS
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct TlsSettings {
+    pub enabled: bool,
+    /// A PEM file with the certificate chain, leaf first
+    #[serde(default)]
+    pub certificate_path: String,
+    /// A PEM file with the PKCS#8, RSA or EC private key
+    #[serde(default)]
+    pub private_key_path: String,
+    /// How often to check the certificate files for changes, and reload them
+    #[serde(default = "default_tls_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+    /// If set, plain HTTP requests to this port are redirected to HTTPS
+    #[serde(default)]
+    pub redirect_port: Option<u16>,
+    /// Send the `Strict-Transport-Security` header over HTTPS, so that browsers stick to it,
+    /// even if TLS isn't enabled, for when the proxy in front of the app terminates it
+    #[serde(default)]
+    pub hsts: bool,
+}
+
+fn default_tls_reload_interval_secs() -> u64 {
+    60
+}
+
+impl TlsSettings {
+    /// Loads the certificate, if TLS is enabled
+    pub fn get_tls(&self) -> Result<Option<Tls>, TlsError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+
+        Tls::new(&self.certificate_path, &self.private_key_path).map(Some)
+    }
+
+    pub fn get_reload_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.reload_interval_secs)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct DatabaseSettings {
+    /// Which database to store our data in; all other fields except `sqlite_path` are for Postgres
+    #[serde(default)]
+    pub backend: DatabaseBackend,
+    pub sqlite_path: String,
     pub username: String,
+    #[serde(serialize_with = "serialize_secret")]
     pub password: Secret<String>,
     pub host: String,
     pub database_name: String,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub require_ssl: bool,
+    /// Apply the pending migrations when the app starts
+    #[serde(default)]
+    pub migrate_on_boot: bool,
 }
 
 impl DatabaseSettings {
@@ -54,12 +160,59 @@ impl DatabaseSettings {
             .log_statements(tracing::log::LevelFilter::Trace);
         options
     }
+
+    pub fn sqlite_options(&self) -> SqliteConnectOptions {
+        SqliteConnectOptions::new().filename(&self.sqlite_path)
+    }
+
+    /// Connects to the configured database, for what isn't behind a `SubscriberRepository`
+    ///
+    /// Unlike `get_repository`, it doesn't migrate the SQLite database.
+    pub async fn get_pool(&self) -> Result<DatabasePool, sqlx::Error> {
+        match self.backend {
+            DatabaseBackend::Postgres => Ok(DatabasePool::Postgres(
+                PgPoolOptions::new().connect_with(self.with_db()).await?,
+            )),
+            DatabaseBackend::Sqlite => Ok(DatabasePool::Sqlite(
+                SqlitePoolOptions::new()
+                    .connect_with(self.sqlite_options().create_if_missing(true))
+                    .await?,
+            )),
+        }
+    }
+
+    /// Builds the `SubscriberRepository` for the configured backend
+    ///
+    /// The Postgres pool connects lazily, on first use, while the SQLite database
+    /// is opened and migrated right away.
+    pub async fn get_repository(&self) -> Result<Arc<dyn SubscriberRepository>, sqlx::Error> {
+        match self.backend {
+            DatabaseBackend::Postgres => {
+                let db_pool = PgPoolOptions::new().connect_lazy_with(self.with_db());
+                Ok(Arc::new(PostgresSubscriberRepository::new(db_pool)))
+            }
+            DatabaseBackend::Sqlite => {
+                let repository = SqliteSubscriberRepository::connect(self.sqlite_options()).await?;
+                Ok(Arc::new(repository))
+            }
+        }
+    }
+}
+
+/// Supported storage backends
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    #[default]
+    Postgres,
+    Sqlite,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct EmailClientSettings {
     pub base_url: String,
     sender_email: String,
+    #[serde(serialize_with = "serialize_secret")]
     pub authorization_token: Secret<String>,
     timeout_millis: u64,
 }
@@ -69,51 +222,754 @@ impl EmailClientSettings {
         SubscriberEmail::parse(self.sender_email.clone())
     }
 
+    /// Builds the `EmailClient`, which records its requests in `metrics`
+    pub fn get_client(&self, metrics: Metrics) -> Result<EmailClient, String> {
+        Ok(EmailClient::new(
+            self.base_url.clone(),
+            self.get_sender()?,
+            self.authorization_token.clone(),
+            self.get_timeout(),
+            metrics,
+        ))
+    }
+
     pub fn get_timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_millis)
     }
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory.");
-    let configuration_directory = base_path.join("configuration");
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct EmailPolicySettings {
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    #[serde(default)]
+    pub denied_domains: Vec<String>,
+    pub denied_domains_action: PolicyAction,
+    pub disposable_domains_action: PolicyAction,
+    pub disposable_domains_file: Option<String>,
+    pub role_local_parts: Option<Vec<String>>,
+    pub role_action: PolicyAction,
+}
 
-    // Detect the running environment. Default to "local" if unspecified.
-    let environment: Environment = std::env::var("APP_ENVIRONMENT")
+impl EmailPolicySettings {
+    /// Builds the `EmailPolicy` out of the settings
+    ///
+    /// The bundled list of disposable domains is extended with the contents of
+    /// `disposable_domains_file`, if set, so the list can be updated without a rebuild.
+    pub fn get_policy(&self) -> Result<EmailPolicy, std::io::Error> {
+        let extra_disposable_domains = match &self.disposable_domains_file {
+            Some(path) => Some(std::fs::read_to_string(path)?),
+            None => None,
+        };
+
+        let role_local_parts = match &self.role_local_parts {
+            Some(role_local_parts) => PolicyRule::new(role_local_parts, self.role_action),
+            None => PolicyRule::new(ROLE_LOCAL_PARTS, self.role_action),
+        };
+
+        Ok(EmailPolicy {
+            allowed_domains: self
+                .allowed_domains
+                .iter()
+                .map(|domain| domain.trim().to_lowercase())
+                .collect(),
+            denied_domains: PolicyRule::new(&self.denied_domains, self.denied_domains_action),
+            disposable_domains: PolicyRule::disposable_domains(
+                extra_disposable_domains.as_deref(),
+                self.disposable_domains_action,
+            ),
+            role_local_parts,
+        })
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct DomainVerificationSettings {
+    pub enabled: bool,
+    /// Name servers to query, as "ip:port"; the system resolver configuration is used if empty
+    #[serde(default)]
+    pub name_servers: Vec<SocketAddr>,
+    timeout_millis: u64,
+    cache_ttl_secs: u64,
+    /// How many domains the verification results are cached for
+    #[serde(default = "default_domain_cache_capacity")]
+    cache_capacity: NonZeroUsize,
+}
+
+fn default_domain_cache_capacity() -> NonZeroUsize {
+    NonZeroUsize::new(10_000).unwrap()
+}
+
+impl DomainVerificationSettings {
+    /// Builds the `DomainVerifier` out of the settings
+    ///
+    /// Returns a verifier that accepts every domain if verification is disabled.
+    pub fn get_verifier(&self) -> Result<Arc<dyn DomainVerifier>, String> {
+        if !self.enabled {
+            return Ok(Arc::new(NoopDomainVerifier));
+        }
+
+        let timeout = std::time::Duration::from_millis(self.timeout_millis);
+        let ttl = std::time::Duration::from_secs(self.cache_ttl_secs);
+        let dns_verifier = DnsDomainVerifier::new(&self.name_servers, timeout)?;
+
+        Ok(Arc::new(CachedDomainVerifier::new(
+            dns_verifier,
+            timeout,
+            ttl,
+            self.cache_capacity,
+        )))
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct AbuseProtectionSettings {
+    pub ip_rate_limit: RateLimitSettings,
+    pub domain_rate_limit: RateLimitSettings,
+    pub use_forwarded_ip: bool,
+    pub min_fill_time_millis: u64,
+    /// The key of the HMAC which signs the signup form tokens; it has no default
+    #[serde(default = "missing_secret", serialize_with = "serialize_secret")]
+    pub form_token_secret: Secret<String>,
+    pub challenge: ChallengeSettings,
+}
+
+impl AbuseProtectionSettings {
+    /// Builds the `SignupProtection` out of the settings
+    pub fn get_protection(&self) -> SignupProtection {
+        SignupProtection {
+            ip_rate_limiter: Arc::new(self.ip_rate_limit.get_limiter()),
+            domain_rate_limiter: Arc::new(self.domain_rate_limit.get_limiter()),
+            use_forwarded_ip: self.use_forwarded_ip,
+            min_fill_time: std::time::Duration::from_millis(self.min_fill_time_millis),
+            form_tokens: FormTokens::new(self.form_token_secret.clone()),
+            challenge_verifier: self.challenge.get_verifier(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct RateLimitSettings {
+    pub max_requests: u32,
+    pub window_secs: u64,
+}
+
+impl RateLimitSettings {
+    pub fn get_limiter(&self) -> RateLimiter {
+        RateLimiter::new(
+            self.max_requests,
+            std::time::Duration::from_secs(self.window_secs),
+        )
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ChallengeSettings {
+    pub enabled: bool,
+    pub verify_url: String,
+    #[serde(default = "missing_secret", serialize_with = "serialize_secret")]
+    pub secret: Secret<String>,
+    timeout_millis: u64,
+}
+
+impl ChallengeSettings {
+    /// Builds the `ChallengeVerifier` out of the settings
+    ///
+    /// Returns a verifier that doesn't require any challenge if challenges are disabled.
+    pub fn get_verifier(&self) -> Arc<dyn ChallengeVerifier> {
+        if !self.enabled {
+            return Arc::new(NoopChallengeVerifier);
+        }
+
+        Arc::new(SiteVerifyChallengeVerifier::new(
+            self.verify_url.clone(),
+            self.secret.clone(),
+            std::time::Duration::from_millis(self.timeout_millis),
+        ))
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ReadinessSettings {
+    timeout_millis: u64,
+    pub probe_email_transport: bool,
+}
+
+impl ReadinessSettings {
+    /// Builds the `ReadinessCheck` out of the settings
+    pub fn get_check(&self) -> ReadinessCheck {
+        ReadinessCheck {
+            timeout: std::time::Duration::from_millis(self.timeout_millis),
+            probe_email_transport: self.probe_email_transport,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct MetricsSettings {
+    /// Serve the admin routes, such as `/metrics`, on this port, instead of on the application port, if set
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub admin_port: Option<u16>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct OpenTelemetrySettings {
+    /// The OTLP/HTTP endpoint of the collector; spans aren't exported if it isn't set
+    pub otlp_endpoint: Option<String>,
+    timeout_millis: u64,
+}
+
+impl OpenTelemetrySettings {
+    /// Builds the `TracerProvider` out of the settings
+    pub fn get_tracer_provider(&self, service_name: &str) -> Result<TracerProvider, TraceError> {
+        get_tracer_provider(
+            service_name,
+            self.otlp_endpoint.as_deref(),
+            std::time::Duration::from_millis(self.timeout_millis),
+        )
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct LoggingSettings {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// The filter directives, unless `RUST_LOG` is set
+    pub level: String,
+    /// The bearer token for the log filter admin endpoints, which are disabled if it isn't set
+    #[serde(serialize_with = "serialize_optional_secret")]
+    pub admin_token: Option<Secret<String>>,
+    /// How personal data is written to logs and spans
+    #[serde(default)]
+    pub redaction: RedactionPolicy,
+    /// The key of the `hashed` redaction; a random one is used if it isn't set
+    #[serde(default, serialize_with = "serialize_optional_secret")]
+    pub redaction_key: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct SubscribersSettings {
+    /// The bearer token for the subscriber import and export admin endpoints, which are disabled
+    /// if it isn't set; it is separate from the log filter's, as they read and write the whole list
+    #[serde(serialize_with = "serialize_optional_secret")]
+    pub admin_token: Option<Secret<String>>,
+}
+
+/// The settings which hold secrets
+///
+/// Each of them can be fetched from a `SecretProvider`, instead of being set in plain text,
+/// by setting its `_file` counterpart, such as `database.password_file`.
+pub const SECRET_SETTINGS: [&str; 7] = [
+    "database.password",
+    "email_client.authorization_token",
+    "abuse_protection.form_token_secret",
+    "abuse_protection.challenge.secret",
+    "logging.admin_token",
+    "logging.redaction_key",
+    "subscribers.admin_token",
+];
+
+/// How long the keys which the app signs with must be in production, in bytes
+const MIN_PRODUCTION_KEY_LENGTH: usize = 32;
+
+/// Loads the configuration for the running environment and validates it
+///
+/// Secrets referenced through `_file` settings are read from files.
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    get_configuration_with(&FileSecretProvider::default())
+}
+
+/// Loads the configuration for the running environment, with secrets from `secret_provider`,
+/// and validates it
+///
+/// The running environment is set with `APP_ENVIRONMENT`, and defaults to `local`.
+/// The configuration files are read from `APP_CONFIG_DIR`, which defaults to
+/// the `configuration` directory in the current directory.
+///
+/// See `load_configuration` for how the settings are layered.
+pub fn get_configuration_with(
+    secret_provider: &dyn SecretProvider,
+) -> Result<Settings, ConfigurationError> {
+    let configuration_directory = match std::env::var_os("APP_CONFIG_DIR") {
+        Some(directory) => PathBuf::from(directory),
+        None => std::env::current_dir()
+            .expect("Failed to determine the current directory.")
+            .join("configuration"),
+    };
+
+    let environment = current_environment()?;
+
+    load_configuration(&configuration_directory, &environment, secret_provider)
+}
+
+/// Detects the running environment, from `APP_ENVIRONMENT`, which defaults to `local`
+pub fn current_environment() -> Result<Environment, ConfigurationError> {
+    std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| String::from("local"))
         .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT.");
-    let environment_filename = format!("{}.yaml", environment.as_str());
+        .map_err(ConfigurationError::Environment)
+}
+
+/// Loads the configuration of `environment` from `configuration_directory`,
+/// with secrets from `secret_provider`, and validates it
+///
+/// The settings are layered, and each layer overrides the ones before it:
+///  - `base.yaml`;
+///  - the environment's own file, such as `production.yaml` or `staging.yaml`, which must exist;
+///  - the environment's optional local file, such as `staging.local.yaml`,
+///    which is gitignored and meant for the overrides of a single machine;
+///  - the `APP_`-prefixed environment variables.
+///
+/// Then, every secret setting whose `_file` counterpart is set, by any of them,
+/// is replaced with the secret that `secret_provider` fetches for it.
+/// All the invalid settings are reported at once, instead of one by one.
+pub fn load_configuration(
+    configuration_directory: &Path,
+    environment: &Environment,
+    secret_provider: &dyn SecretProvider,
+) -> Result<Settings, ConfigurationError> {
+    let environment_file = configuration_directory.join(format!("{}.yaml", environment.as_str()));
+    if !environment_file.is_file() {
+        return Err(ConfigurationError::Environment(format!(
+            "There is no configuration file for the '{}' environment: '{}' is missing.",
+            environment.as_str(),
+            environment_file.display()
+        )));
+    }
+    let local_file = configuration_directory.join(format!("{}.local.yaml", environment.as_str()));
 
     let settings = config::Config::builder()
         .add_source(config::File::from(
             configuration_directory.join("base.yaml"),
         ))
-        .add_source(config::File::from(
-            configuration_directory.join(environment_filename),
-        ))
+        .add_source(config::File::from(environment_file))
+        .add_source(config::File::from(local_file).required(false))
         .add_source(
             config::Environment::with_prefix("APP")
                 .prefix_separator("_")
                 .separator("__"),
         )
         .build()?;
+    let settings = with_secrets(settings, secret_provider)?;
 
-    settings.try_deserialize::<Settings>()
+    let settings = settings.try_deserialize::<Settings>()?;
+    settings.validate(environment)?;
+
+    Ok(settings)
+}
+
+/// Overrides the secret settings whose `_file` counterparts are set with the fetched secrets
+fn with_secrets(
+    settings: config::Config,
+    secret_provider: &dyn SecretProvider,
+) -> Result<config::Config, ConfigurationError> {
+    let mut builder = config::Config::builder().add_source(settings.clone());
+    for setting in SECRET_SETTINGS {
+        let reference_setting = format!("{}_file", setting);
+        let reference = match settings.get_string(&reference_setting) {
+            Ok(reference) => reference,
+            Err(config::ConfigError::NotFound(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let secret = secret_provider.fetch(&reference).map_err(|e| {
+            ConfigurationError::Secret(format!(
+                "Failed to fetch the secret for {} from {}: {}",
+                setting, reference_setting, e
+            ))
+        })?;
+        builder = builder.set_override(setting, secret.expose_secret().as_str())?;
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Why the configuration couldn't be loaded
+#[derive(Debug)]
+pub enum ConfigurationError {
+    /// `APP_ENVIRONMENT` isn't a valid environment name, or the environment has no configuration file
+    Environment(String),
+    /// The configuration files are missing or malformed, or a setting is missing
+    Load(config::ConfigError),
+    /// A secret couldn't be fetched; the message doesn't contain the secret
+    Secret(String),
+    /// The settings are all there, but some of them are invalid
+    Invalid(ValidationReport),
+}
+
+impl std::fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigurationError::Environment(e) => write!(f, "{}", e),
+            ConfigurationError::Load(e) => write!(f, "Failed to load the configuration: {}", e),
+            ConfigurationError::Secret(e) => write!(f, "{}", e),
+            ConfigurationError::Invalid(report) => write!(f, "{}", report),
+        }
+    }
+}
+
+impl std::error::Error for ConfigurationError {}
+
+impl From<config::ConfigError> for ConfigurationError {
+    fn from(e: config::ConfigError) -> Self {
+        ConfigurationError::Load(e)
+    }
+}
+
+impl From<ValidationReport> for ConfigurationError {
+    fn from(report: ValidationReport) -> Self {
+        ConfigurationError::Invalid(report)
+    }
+}
+
+/// All the invalid settings, by their path, such as `email_client.sender_email`
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub errors: Vec<(&'static str, String)>,
+}
+
+impl ValidationReport {
+    /// Records an error for `setting` unless `is_valid`
+    fn check(&mut self, is_valid: bool, setting: &'static str, error: impl FnOnce() -> String) {
+        if !is_valid {
+            self.errors.push((setting, error()));
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The configuration is invalid:")?;
+        for (setting, error) in &self.errors {
+            write!(f, "\n  - {}: {}", setting, error)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Settings {
+    /// The secret settings, in the order of `SECRET_SETTINGS`, with their values, if they are set
+    pub fn secrets(&self) -> [(&'static str, Option<&Secret<String>>); 7] {
+        [
+            ("database.password", Some(&self.database.password)),
+            (
+                "email_client.authorization_token",
+                Some(&self.email_client.authorization_token),
+            ),
+            (
+                "abuse_protection.form_token_secret",
+                Some(&self.abuse_protection.form_token_secret),
+            ),
+            (
+                "abuse_protection.challenge.secret",
+                Some(&self.abuse_protection.challenge.secret),
+            ),
+            ("logging.admin_token", self.logging.admin_token.as_ref()),
+            ("logging.redaction_key", self.logging.redaction_key.as_ref()),
+            (
+                "subscribers.admin_token",
+                self.subscribers.admin_token.as_ref(),
+            ),
+        ]
+    }
+
+    /// Checks the settings which can be wrong even though they were deserialized,
+    /// including those which are only required in production, such as TLS
+    pub fn validate(&self, environment: &Environment) -> Result<(), ValidationReport> {
+        let mut report = ValidationReport::default();
+        let is_production = *environment == Environment::Production;
+
+        report.check(
+            !self.application.host.trim().is_empty(),
+            "application.host",
+            || String::from("must not be empty"),
+        );
+        // Any port can be 0, which asks the OS for a free one, so two 0s don't collide
+        let collides =
+            |port: Option<u16>, other: u16| port.is_some_and(|port| port != 0 && port == other);
+        report.check(
+            !collides(self.metrics.admin_port, self.application.port),
+            "metrics.admin_port",
+            || String::from("must differ from application.port"),
+        );
+        let tls = &self.application.tls;
+        if tls.enabled {
+            report.check(
+                Path::new(&tls.certificate_path).is_file(),
+                "application.tls.certificate_path",
+                || format!("'{}' is not a file", tls.certificate_path),
+            );
+            report.check(
+                Path::new(&tls.private_key_path).is_file(),
+                "application.tls.private_key_path",
+                || format!("'{}' is not a file", tls.private_key_path),
+            );
+            report.check(
+                tls.reload_interval_secs != 0,
+                "application.tls.reload_interval_secs",
+                || String::from("must not be 0"),
+            );
+            report.check(
+                !collides(tls.redirect_port, self.application.port),
+                "application.tls.redirect_port",
+                || String::from("must differ from application.port"),
+            );
+            report.check(
+                !tls.redirect_port
+                    .is_some_and(|port| collides(self.metrics.admin_port, port)),
+                "application.tls.redirect_port",
+                || String::from("must differ from metrics.admin_port"),
+            );
+        }
+
+        match self.database.backend {
+            DatabaseBackend::Postgres => {
+                report.check(
+                    !self.database.host.trim().is_empty(),
+                    "database.host",
+                    || String::from("must not be empty"),
+                );
+                report.check(self.database.port != 0, "database.port", || {
+                    String::from("must not be 0")
+                });
+                report.check(
+                    !self.database.database_name.trim().is_empty(),
+                    "database.database_name",
+                    || String::from("must not be empty"),
+                );
+                report.check(
+                    !is_production || self.database.require_ssl,
+                    "database.require_ssl",
+                    || String::from("must be true in production"),
+                );
+            }
+            DatabaseBackend::Sqlite => {
+                report.check(
+                    !self.database.sqlite_path.trim().is_empty(),
+                    "database.sqlite_path",
+                    || String::from("must not be empty"),
+                );
+            }
+        }
+
+        if let Err(e) = self.email_client.get_sender() {
+            report.errors.push(("email_client.sender_email", e));
+        }
+        check_url(
+            &mut report,
+            "email_client.base_url",
+            &self.email_client.base_url,
+            is_production,
+        );
+        report.check(
+            !self
+                .email_client
+                .authorization_token
+                .expose_secret()
+                .is_empty(),
+            "email_client.authorization_token",
+            || String::from("must not be empty"),
+        );
+        check_timeout(
+            &mut report,
+            "email_client.timeout_millis",
+            self.email_client.timeout_millis,
+        );
+
+        if let Some(path) = &self.email_policy.disposable_domains_file {
+            report.check(
+                std::path::Path::new(path).is_file(),
+                "email_policy.disposable_domains_file",
+                || format!("'{}' is not a file", path),
+            );
+        }
+
+        if self.domain_verification.enabled {
+            check_timeout(
+                &mut report,
+                "domain_verification.timeout_millis",
+                self.domain_verification.timeout_millis,
+            );
+        }
+
+        let abuse_protection = &self.abuse_protection;
+        for (setting, rate_limit) in [
+            (
+                "abuse_protection.ip_rate_limit",
+                &abuse_protection.ip_rate_limit,
+            ),
+            (
+                "abuse_protection.domain_rate_limit",
+                &abuse_protection.domain_rate_limit,
+            ),
+        ] {
+            report.check(
+                rate_limit.max_requests > 0 && rate_limit.window_secs > 0,
+                setting,
+                || String::from("max_requests and window_secs must be greater than 0"),
+            );
+        }
+        if abuse_protection.min_fill_time_millis > 0 {
+            let form_token_secret = abuse_protection.form_token_secret.expose_secret();
+            report.check(
+                !form_token_secret.is_empty(),
+                "abuse_protection.form_token_secret",
+                || String::from("must be set, unless min_fill_time_millis is 0"),
+            );
+            report.check(
+                !is_production
+                    || form_token_secret.is_empty()
+                    || form_token_secret.len() >= MIN_PRODUCTION_KEY_LENGTH,
+                "abuse_protection.form_token_secret",
+                || {
+                    format!(
+                        "must be at least {} bytes long in production",
+                        MIN_PRODUCTION_KEY_LENGTH
+                    )
+                },
+            );
+        }
+        if abuse_protection.challenge.enabled {
+            check_url(
+                &mut report,
+                "abuse_protection.challenge.verify_url",
+                &abuse_protection.challenge.verify_url,
+                is_production,
+            );
+            report.check(
+                !abuse_protection.challenge.secret.expose_secret().is_empty(),
+                "abuse_protection.challenge.secret",
+                || String::from("must be set when challenges are enabled"),
+            );
+            check_timeout(
+                &mut report,
+                "abuse_protection.challenge.timeout_millis",
+                abuse_protection.challenge.timeout_millis,
+            );
+        }
+
+        check_timeout(
+            &mut report,
+            "readiness.timeout_millis",
+            self.readiness.timeout_millis,
+        );
+
+        if let Some(otlp_endpoint) = &self.opentelemetry.otlp_endpoint {
+            // The collector usually runs next to the app, so plain HTTP is fine even in production
+            check_url(
+                &mut report,
+                "opentelemetry.otlp_endpoint",
+                otlp_endpoint,
+                false,
+            );
+        }
+        check_timeout(
+            &mut report,
+            "opentelemetry.timeout_millis",
+            self.opentelemetry.timeout_millis,
+        );
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.level) {
+            report.errors.push(("logging.level", e.to_string()));
+        }
+        if let Some(admin_token) = &self.logging.admin_token {
+            report.check(
+                !admin_token.expose_secret().is_empty(),
+                "logging.admin_token",
+                || String::from("must not be empty; set it to null to disable the endpoint"),
+            );
+        }
+        if let Some(admin_token) = &self.subscribers.admin_token {
+            report.check(
+                !admin_token.expose_secret().is_empty(),
+                "subscribers.admin_token",
+                || String::from("must not be empty; set it to null to disable the endpoints"),
+            );
+            report.check(
+                self.logging
+                    .admin_token
+                    .as_ref()
+                    .map(ExposeSecret::expose_secret)
+                    != Some(admin_token.expose_secret()),
+                "subscribers.admin_token",
+                || String::from("must differ from logging.admin_token"),
+            );
+        }
+
+        if report.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(report)
+        }
+    }
+}
+
+/// Checks that `url` is an absolute HTTP(S) URL, and that it uses HTTPS if `require_https`
+fn check_url(report: &mut ValidationReport, setting: &'static str, url: &str, require_https: bool) {
+    match reqwest::Url::parse(url) {
+        Ok(url) => {
+            report.check(matches!(url.scheme(), "http" | "https"), setting, || {
+                format!("'{}' is not an HTTP(S) URL", url)
+            });
+            report.check(!require_https || url.scheme() == "https", setting, || {
+                format!("'{}' must use HTTPS in production", url)
+            });
+        }
+        Err(e) => report
+            .errors
+            .push((setting, format!("'{}' is not a valid URL: {}", url, e))),
+    }
+}
+
+fn check_timeout(report: &mut ValidationReport, setting: &'static str, timeout_millis: u64) {
+    report.check(timeout_millis > 0, setting, || {
+        String::from("must be greater than 0")
+    });
+}
+
+/// The value of the secret settings which aren't set, and which `validate` rejects if they are needed
+fn missing_secret() -> Secret<String> {
+    Secret::new(String::new())
+}
+
+/// Serializes a secret without exposing it, so that the settings can be printed
+fn serialize_secret<S: serde::Serializer>(
+    _secret: &Secret<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("[REDACTED]")
+}
+
+fn serialize_optional_secret<S: serde::Serializer>(
+    secret: &Option<Secret<String>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(secret) => serialize_secret(secret, serializer),
+        None => serializer.serialize_none(),
+    }
 }
 
 /// Possible runtime environments for our application
+///
+/// Besides `local` and `production`, any other named profile, such as `staging`, `test` or `ci`,
+/// is an environment too, as long as it has a configuration file.
+/// Only `production` enforces the production-only settings, such as TLS.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Environment {
     Local,
     Production,
+    /// A named profile, such as `staging`
+    Other(String),
 }
 
 impl Environment {
     /// Convert `Environment` into a string slice
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             Environment::Local => "local",
             Environment::Production => "production",
+            Environment::Other(name) => name,
         }
     }
 }
@@ -121,14 +977,368 @@ impl Environment {
 impl TryFrom<String> for Environment {
     type Error = String;
 
+    /// Accepts any name of lowercase ASCII letters, digits, `-` and `_`, except for `base`,
+    /// which is the file that all the environments share
     fn try_from(value: String) -> Result<Self, Self::Error> {
         match value.to_lowercase().as_str() {
             "local" => Ok(Self::Local),
             "production" => Ok(Self::Production),
+            "base" => Err(String::from(
+                "'base' is not a valid environment, because 'base.yaml' is shared by all of them.",
+            )),
+            other
+                if !other.is_empty()
+                    && other
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')) =>
+            {
+                Ok(Self::Other(other.to_string()))
+            }
             other => Err(format!(
-                "'{}' is not a supported environment. Supported environments are 'local' and 'production'.",
+                "'{}' is not a valid environment. Environment names are made of letters, digits, '-' and '_'.",
                 other
             )),
         }
     }
 }
+
+/// Loads the local configuration, without the local overrides and the `APP_` environment variables,
+/// so that the unit tests don't depend on the machine they run on
+#[cfg(test)]
+pub(crate) fn local_test_settings() -> Settings {
+    let settings: Settings = tests::local_config(&[])
+        .try_deserialize()
+        .expect("Failed to deserialize the local configuration.");
+    settings
+        .validate(&Environment::Local)
+        .expect("The local configuration is invalid.");
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    /// Loads the local configuration, which the tests then break in various ways
+    fn local_settings() -> Settings {
+        local_test_settings()
+    }
+
+    fn invalid_settings(report: &ValidationReport) -> Vec<&'static str> {
+        report.errors.iter().map(|(setting, _)| *setting).collect()
+    }
+
+    #[test]
+    fn all_invalid_settings_are_reported_at_once() {
+        let mut settings = local_settings();
+        settings.application.host = String::from(" ");
+        settings.email_client.sender_email = String::from("not-an-email");
+        settings.email_client.timeout_millis = 0;
+        settings.logging.level = String::from("not a [valid filter");
+
+        let report = settings.validate(&Environment::Local).unwrap_err();
+
+        assert_eq!(
+            vec![
+                "application.host",
+                "email_client.sender_email",
+                "email_client.timeout_millis",
+                "logging.level"
+            ],
+            invalid_settings(&report)
+        );
+        let report = report.to_string();
+        assert!(
+            report.contains("  - application.host: must not be empty"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn production_requires_tls() {
+        let mut settings = local_settings();
+        settings.abuse_protection.form_token_secret = Secret::new("k".repeat(32));
+        settings.database.require_ssl = false;
+        settings.email_client.base_url = String::from("http://api.postmarkapp.com");
+
+        let report = settings.validate(&Environment::Production).unwrap_err();
+
+        assert_eq!(
+            vec!["database.require_ssl", "email_client.base_url"],
+            invalid_settings(&report)
+        );
+        assert!(settings.validate(&Environment::Local).is_ok());
+    }
+
+    #[test]
+    fn the_form_token_secret_has_no_default() {
+        let config = local_config(&[("abuse_protection.form_token_secret", "")]);
+        let mut settings: Settings = config.try_deserialize().unwrap();
+
+        let report = settings.validate(&Environment::Local).unwrap_err();
+
+        assert_eq!(
+            vec!["abuse_protection.form_token_secret"],
+            invalid_settings(&report)
+        );
+        settings.abuse_protection.min_fill_time_millis = 0;
+        assert!(settings.validate(&Environment::Local).is_ok());
+    }
+
+    #[test]
+    fn production_rejects_short_form_token_secrets() {
+        let mut settings = local_settings();
+        settings.database.require_ssl = true;
+        settings.email_client.base_url = String::from("https://api.postmarkapp.com");
+
+        let report = settings.validate(&Environment::Production).unwrap_err();
+
+        assert_eq!(
+            vec!["abuse_protection.form_token_secret"],
+            invalid_settings(&report)
+        );
+        settings.abuse_protection.form_token_secret = Secret::new("k".repeat(32));
+        assert!(settings.validate(&Environment::Production).is_ok());
+    }
+
+    #[test]
+    fn ports_can_be_picked_by_the_os() {
+        let mut settings = local_settings();
+        settings.application.port = 0;
+        settings.metrics.admin_port = Some(0);
+
+        assert!(settings.validate(&Environment::Local).is_ok());
+
+        settings.application.port = 8000;
+        settings.metrics.admin_port = Some(8000);
+        let report = settings.validate(&Environment::Local).unwrap_err();
+
+        assert_eq!(vec!["metrics.admin_port"], invalid_settings(&report));
+    }
+
+    #[test]
+    fn the_redirect_port_must_differ_from_the_admin_port() {
+        let mut settings = local_settings();
+        settings.application.tls.enabled = true;
+        settings.application.tls.certificate_path =
+            String::from("tests/fixtures/tls/localhost.crt");
+        settings.application.tls.private_key_path =
+            String::from("tests/fixtures/tls/localhost.key");
+        settings.application.tls.redirect_port = Some(9000);
+        settings.metrics.admin_port = Some(9000);
+
+        let report = settings.validate(&Environment::Local).unwrap_err();
+
+        assert_eq!(
+            vec!["application.tls.redirect_port"],
+            invalid_settings(&report)
+        );
+    }
+
+    #[test]
+    fn the_subscribers_admin_token_must_differ_from_the_log_filter_one() {
+        let mut settings = local_settings();
+        settings.logging.admin_token = Some(Secret::new(String::from("s3cr3t")));
+        settings.subscribers.admin_token = Some(Secret::new(String::from("s3cr3t")));
+
+        let report = settings.validate(&Environment::Local).unwrap_err();
+
+        assert_eq!(vec!["subscribers.admin_token"], invalid_settings(&report));
+        settings.subscribers.admin_token = Some(Secret::new(String::from("another")));
+        assert!(settings.validate(&Environment::Local).is_ok());
+    }
+
+    #[test]
+    fn urls_must_be_absolute() {
+        let mut settings = local_settings();
+        settings.email_client.base_url = String::from("127.0.0.1");
+        settings.opentelemetry.otlp_endpoint = Some(String::from("localhost:4318"));
+
+        let report = settings.validate(&Environment::Local).unwrap_err();
+
+        assert_eq!(
+            vec!["email_client.base_url", "opentelemetry.otlp_endpoint"],
+            invalid_settings(&report)
+        );
+    }
+
+    #[test]
+    fn secrets_are_redacted_when_the_settings_are_printed() {
+        let mut settings = local_settings();
+        settings.logging.admin_token = Some(Secret::new(String::from("s3cr3t")));
+
+        let printed = serde_json::to_string(&settings).unwrap();
+
+        assert!(
+            printed.contains(r#""password":"[REDACTED]""#),
+            "{}",
+            printed
+        );
+        assert!(
+            printed.contains(r#""admin_token":"[REDACTED]""#),
+            "{}",
+            printed
+        );
+        assert!(!printed.contains("my-secret-token"), "{}", printed);
+        assert!(!printed.contains("s3cr3t"), "{}", printed);
+    }
+
+    /// The local configuration, without the environment variables, plus `overrides`
+    pub(super) fn local_config(overrides: &[(&str, &str)]) -> config::Config {
+        let mut builder = config::Config::builder()
+            .add_source(config::File::with_name("configuration/base"))
+            .add_source(config::File::with_name("configuration/local"));
+        for (key, value) in overrides {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    /// A provider which returns the reference, reversed, as the secret
+    struct ReversingSecretProvider;
+
+    impl SecretProvider for ReversingSecretProvider {
+        fn fetch(&self, reference: &str) -> Result<Secret<String>, String> {
+            Ok(Secret::new(reference.chars().rev().collect()))
+        }
+    }
+
+    #[test]
+    fn secrets_are_fetched_for_the_settings_whose_file_counterparts_are_set() {
+        let config = local_config(&[
+            ("database.password_file", "drowssap"),
+            ("logging.admin_token_file", "nekot"),
+        ]);
+
+        let settings: Settings = with_secrets(config, &ReversingSecretProvider)
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        assert_eq!("password", settings.database.password.expose_secret());
+        assert_eq!(
+            "token",
+            settings.logging.admin_token.unwrap().expose_secret()
+        );
+        assert_eq!(
+            "my-secret-token",
+            settings.email_client.authorization_token.expose_secret()
+        );
+    }
+
+    #[test]
+    fn a_missing_secret_file_is_reported_by_its_setting() {
+        let config = local_config(&[("email_client.authorization_token_file", "missing")]);
+        let provider = FileSecretProvider::in_directory(std::env::temp_dir());
+
+        let error = with_secrets(config, &provider).unwrap_err().to_string();
+
+        assert!(
+            error.contains("email_client.authorization_token_file"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn every_secret_setting_is_listed() {
+        let mut settings = local_settings();
+        settings.logging.admin_token = Some(Secret::new(String::from("s3cr3t")));
+        settings.logging.redaction_key = Some(Secret::new(String::from("s3cr3t")));
+        settings.subscribers.admin_token = Some(Secret::new(String::from("s3cr3t")));
+        let printed = serde_json::to_value(&settings).unwrap();
+
+        let mut redacted = Vec::new();
+        for (section, values) in printed.as_object().unwrap() {
+            collect_redacted(section.clone(), values, &mut redacted);
+        }
+
+        redacted.sort();
+        let mut listed = SECRET_SETTINGS.to_vec();
+        listed.sort();
+        assert_eq!(listed, redacted);
+    }
+
+    #[test]
+    fn the_secrets_are_those_listed() {
+        let settings = local_settings();
+
+        let paths: Vec<_> = settings.secrets().iter().map(|(path, _)| *path).collect();
+
+        assert_eq!(SECRET_SETTINGS.to_vec(), paths);
+    }
+
+    /// A configuration directory with the shared `base.yaml`, and the given environment `files`
+    fn configuration_directory(files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        std::fs::copy("configuration/base.yaml", directory.join("base.yaml")).unwrap();
+        for (name, contents) in files {
+            std::fs::write(directory.join(name), contents).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn named_profiles_are_layered_with_their_local_overrides() {
+        let directory = configuration_directory(&[
+            (
+                "staging.yaml",
+                "application:\n  host: 127.0.0.1\n  port: 9000\ndatabase:\n  require_ssl: false\n\
+                 abuse_protection:\n  form_token_secret: staging-secret\n",
+            ),
+            ("staging.local.yaml", "application:\n  port: 9001\n"),
+        ]);
+        let environment = Environment::try_from(String::from("Staging")).unwrap();
+
+        let settings =
+            load_configuration(&directory, &environment, &FileSecretProvider::default()).unwrap();
+
+        assert_eq!(Environment::Other(String::from("staging")), environment);
+        assert_eq!("127.0.0.1", settings.application.host);
+        assert_eq!(9001, settings.application.port);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_profile_without_a_configuration_file_is_an_error() {
+        let directory =
+            configuration_directory(&[("ci.local.yaml", "application:\n  port: 9001\n")]);
+        let environment = Environment::try_from(String::from("ci")).unwrap();
+
+        let error =
+            match load_configuration(&directory, &environment, &FileSecretProvider::default()) {
+                Ok(_) => panic!("The configuration was loaded without ci.yaml."),
+                Err(error) => error,
+            };
+
+        assert!(matches!(error, ConfigurationError::Environment(_)));
+        assert!(error.to_string().contains("ci.yaml"), "{}", error);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[rstest]
+    #[case::empty("")]
+    #[case::base("base")]
+    #[case::path("../production")]
+    #[case::dotted("staging.local")]
+    fn invalid_environment_names_are_rejected(#[case] name: &str) {
+        assert!(Environment::try_from(name.to_string()).is_err());
+    }
+
+    /// Collects the paths of the redacted values, which are the secrets
+    fn collect_redacted(path: String, value: &serde_json::Value, redacted: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(values) => {
+                for (key, value) in values {
+                    collect_redacted(format!("{}.{}", path, key), value, redacted);
+                }
+            }
+            serde_json::Value::String(value) if value == "[REDACTED]" => redacted.push(path),
+            _ => {}
+        }
+    }
+}
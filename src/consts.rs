@@ -1,2 +1,35 @@
 pub const FORBIDDEN_NAME_CHARACTERS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
 pub const MAX_NAME_LEN: usize = 256;
+
+/// Local parts of email addresses that usually belong to a role, and not to a person.
+/// Used by the `EmailPolicy` when `email_policy.role_local_parts` isn't configured.
+pub const ROLE_LOCAL_PARTS: [&str; 16] = [
+    "abuse",
+    "admin",
+    "administrator",
+    "billing",
+    "contact",
+    "help",
+    "hostmaster",
+    "info",
+    "marketing",
+    "no-reply",
+    "noreply",
+    "office",
+    "postmaster",
+    "sales",
+    "support",
+    "webmaster",
+];
+
+/// Invisible characters that are stripped from subscriber names.
+///
+/// Zero-width (non-)joiners are not on the list, because some scripts need them.
+pub const ZERO_WIDTH_NAME_CHARACTERS: [char; 4] = ['\u{180E}', '\u{200B}', '\u{2060}', '\u{FEFF}'];
+
+/// Bidirectional formatting characters, which can make a name look different
+/// from what it is, and which are therefore rejected in subscriber names.
+pub const BIDI_CONTROL_CHARACTERS: [char; 12] = [
+    '\u{061C}', '\u{200E}', '\u{200F}', '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}', '\u{202E}',
+    '\u{2066}', '\u{2067}', '\u{2068}', '\u{2069}',
+];
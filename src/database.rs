@@ -0,0 +1,574 @@
+//! src/database.rs
+
+use sqlx::migrate::{AppliedMigration, Migrate, MigrateError, Migrator};
+use sqlx::{Acquire, Connection, PgPool, SqlitePool};
+use std::collections::HashMap;
+use std::ops::Deref;
+
+/// The Postgres migrations, embedded in the binary
+pub(crate) static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// The SQLite migrations, embedded in the binary
+pub(crate) static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations_sqlite");
+
+/// The SHA-384 checksums that Postgres migrations had before their header comments were fixed,
+/// by version
+///
+/// The databases which applied them then are upgraded to the current checksums,
+/// instead of being reported as changed, since their schema is the same.
+const POSTGRES_PREVIOUS_CHECKSUMS: [(i64, &str); 3] = [
+    (20231004180141, "94d69f31e9275893c858146c2e52bb2dcd1790ae329f1afa095ee14dbf98e9730d8205bf2286d7692c422f0b246cc98d"),
+    (20231024154512, "23457967256ef7a257b8ef82137df7aed2deac9556dfa0a5e091419f4a88d5cdf105709aee96c075beba9410506026d2"),
+    (20231025093027, "00f75bc44649264502e27c24a2b723e2c6465c94be9b00a43b4f39e7203f309444d322eba9d5c8cd8f9d1a37fd4923ee"),
+];
+
+/// The same as `POSTGRES_PREVIOUS_CHECKSUMS`, for the SQLite migrations
+const SQLITE_PREVIOUS_CHECKSUMS: [(i64, &str); 3] = [
+    (20231004180141, "29963374d28cc55a8382cbb78aee44213b2859e3216d56de218755e24c4360d9a5a4ab171c4f551e7f56096e26d04471"),
+    (20231024154512, "b150ea5b3209c87a7007827410ec64e02d56a9bf4d78a74eafdd2730e852eeb5e759f1ff30e14e8e8dc70485a7ccace5"),
+    (20231025093027, "04a424333c04fe4dcc0f3658215a25752a349b72974ef560ea99aef37fd43638d456aec8cac0e4d7a70334ad474f1d18"),
+];
+
+/// A connection pool to the configured database, for what isn't behind a `SubscriberRepository`,
+/// such as the migrations and the users
+#[derive(Clone, Debug)]
+pub enum DatabasePool {
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+/// Whether a migration is applied, as reported by `DatabasePool::migration_status`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but its SQL has been changed since
+    Changed,
+    /// Applied, but this binary doesn't know it, so it was applied by a newer one
+    Unknown,
+}
+
+impl MigrationState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::Changed => "changed",
+            MigrationState::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    /// Empty for `MigrationState::Unknown` migrations
+    pub description: String,
+    pub state: MigrationState,
+}
+
+/// Errors of the migration commands
+#[derive(Debug)]
+pub enum MigrationError {
+    Migrate(MigrateError),
+    /// The migration has no down migration, so it can't be reverted
+    Irreversible(i64),
+    /// These applied migrations are unknown to the binary, which is older than the schema
+    SchemaNewer(Vec<i64>),
+}
+
+impl std::fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationError::Migrate(e) => write!(f, "Failed to migrate the database: {}", e),
+            MigrationError::Irreversible(version) => write!(
+                f,
+                "The migration {} can't be reverted, as it has no down migration.",
+                version
+            ),
+            MigrationError::SchemaNewer(versions) => write!(
+                f,
+                "The database schema is newer than the app, which doesn't know the applied migrations {:?}.",
+                versions
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MigrationError::Migrate(e) => Some(e),
+            MigrationError::Irreversible(_) | MigrationError::SchemaNewer(_) => None,
+        }
+    }
+}
+
+impl From<MigrateError> for MigrationError {
+    fn from(e: MigrateError) -> Self {
+        MigrationError::Migrate(e)
+    }
+}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(e: sqlx::Error) -> Self {
+        MigrationError::Migrate(MigrateError::Execute(e))
+    }
+}
+
+impl DatabasePool {
+    fn migrator(&self) -> &'static Migrator {
+        match self {
+            DatabasePool::Postgres(_) => &POSTGRES_MIGRATOR,
+            DatabasePool::Sqlite(_) => &SQLITE_MIGRATOR,
+        }
+    }
+
+    fn previous_checksums(&self) -> &'static [(i64, &'static str)] {
+        match self {
+            DatabasePool::Postgres(_) => &POSTGRES_PREVIOUS_CHECKSUMS,
+            DatabasePool::Sqlite(_) => &SQLITE_PREVIOUS_CHECKSUMS,
+        }
+    }
+
+    /// Lists the applied migrations, with their current checksums if they had previous ones,
+    /// and creates the migrations table if it is missing
+    async fn applied_migrations(&self) -> Result<Vec<AppliedMigration>, MigrateError> {
+        let applied = match self {
+            DatabasePool::Postgres(pool) => applied_migrations(pool).await?,
+            DatabasePool::Sqlite(pool) => applied_migrations(pool).await?,
+        };
+        Ok(with_current_checksums(
+            self.migrator(),
+            self.previous_checksums(),
+            applied,
+        ))
+    }
+
+    /// Whether the migrations table exists, which is only created once migrations are run
+    async fn has_migrations_table(&self) -> Result<bool, sqlx::Error> {
+        match self {
+            DatabasePool::Postgres(pool) => {
+                sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+                    .fetch_one(pool)
+                    .await
+            }
+            DatabasePool::Sqlite(pool) => {
+                sqlx::query_scalar(
+                    "SELECT EXISTS (SELECT 1 FROM sqlite_master \
+                     WHERE type = 'table' AND name = '_sqlx_migrations')",
+                )
+                .fetch_one(pool)
+                .await
+            }
+        }
+    }
+
+    /// Replaces the previous checksums of the applied migrations with their current ones,
+    /// which the migrator checks before it applies or reverts anything
+    async fn upgrade_checksums(&self) -> Result<(), sqlx::Error> {
+        let query =
+            "UPDATE _sqlx_migrations SET checksum = $1 WHERE version = $2 AND checksum = $3";
+        for (version, previous) in self.previous_checksums() {
+            let Some(migration) = self.migrator().iter().find(|migration| {
+                migration.version == *version && !migration.migration_type.is_down_migration()
+            }) else {
+                continue;
+            };
+            let current = migration.checksum.to_vec();
+            let previous = hex::decode(previous).expect("The previous checksums are hex.");
+            match self {
+                DatabasePool::Postgres(pool) => {
+                    sqlx::query(query)
+                        .bind(current)
+                        .bind(version)
+                        .bind(previous)
+                        .execute(pool)
+                        .await?;
+                }
+                DatabasePool::Sqlite(pool) => {
+                    sqlx::query(query)
+                        .bind(current)
+                        .bind(version)
+                        .bind(previous)
+                        .execute(pool)
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the pending migrations, and returns their versions
+    ///
+    /// On Postgres, it holds an advisory lock meanwhile, so that the instances which start
+    /// together don't race to apply the same migrations. Nothing is applied if the database
+    /// has migrations that the binary doesn't know, as its code may not fit the schema anymore.
+    pub async fn run_migrations(&self) -> Result<Vec<i64>, MigrationError> {
+        match self {
+            DatabasePool::Postgres(pool) => {
+                // Advisory locks belong to the session, so the connection is closed afterwards,
+                // rather than returned to the pool, to release the lock even if unlocking fails
+                let mut lock = pool.acquire().await?.detach();
+                lock.lock().await?;
+                let outcome = self.run_pending_migrations().await;
+                let _ = lock.close().await;
+                outcome
+            }
+            // A SQLite database is locked as a whole while it is written to
+            DatabasePool::Sqlite(_) => self.run_pending_migrations().await,
+        }
+    }
+
+    async fn run_pending_migrations(&self) -> Result<Vec<i64>, MigrationError> {
+        let migrator = self.migrator();
+        let before = self.applied_migrations().await?;
+        self.upgrade_checksums().await?;
+        let unknown: Vec<i64> = migration_status(migrator, &before)
+            .into_iter()
+            .filter(|status| status.state == MigrationState::Unknown)
+            .map(|status| status.version)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(MigrationError::SchemaNewer(unknown));
+        }
+
+        // The lock is already held, and the migrator would wait for it on another connection
+        let migrator = Migrator {
+            migrations: migrator.migrations.clone(),
+            ignore_missing: migrator.ignore_missing,
+            locking: false,
+        };
+        match self {
+            DatabasePool::Postgres(pool) => migrator.run(pool).await?,
+            DatabasePool::Sqlite(pool) => migrator.run(pool).await?,
+        }
+        let after = self.applied_migrations().await?;
+
+        Ok(after
+            .iter()
+            .map(|migration| migration.version)
+            .filter(|version| !before.iter().any(|applied| applied.version == *version))
+            .collect())
+    }
+
+    /// Lists the migrations of the binary, and those of the database that the binary doesn't know,
+    /// by version
+    ///
+    /// It only reads the database: if the migrations table doesn't exist yet, none is applied.
+    pub async fn migration_status(&self) -> Result<Vec<MigrationStatus>, MigrationError> {
+        if !self.has_migrations_table().await? {
+            return Ok(migration_status(self.migrator(), &[]));
+        }
+        let applied = self.applied_migrations().await?;
+        Ok(migration_status(self.migrator(), &applied))
+    }
+
+    /// Reverts the last applied migration, and returns its version, if any was applied
+    pub async fn revert_last_migration(&self) -> Result<Option<i64>, MigrationError> {
+        let mut applied: Vec<i64> = self
+            .applied_migrations()
+            .await?
+            .iter()
+            .map(|migration| migration.version)
+            .collect();
+        applied.sort_unstable();
+        let Some(last) = applied.pop() else {
+            return Ok(None);
+        };
+        self.upgrade_checksums().await?;
+        let is_reversible = self.migrator().iter().any(|migration| {
+            migration.version == last && migration.migration_type.is_down_migration()
+        });
+        if !is_reversible {
+            return Err(MigrationError::Irreversible(last));
+        }
+
+        // Everything after the previous migration is reverted, and that is only the last one
+        let target = applied.last().copied().unwrap_or(0);
+        match self {
+            DatabasePool::Postgres(pool) => POSTGRES_MIGRATOR.undo(pool, target).await?,
+            DatabasePool::Sqlite(pool) => SQLITE_MIGRATOR.undo(pool, target).await?,
+        }
+
+        Ok(Some(last))
+    }
+
+    /// Closes the pool's connections
+    pub async fn close(&self) {
+        match self {
+            DatabasePool::Postgres(pool) => pool.close().await,
+            DatabasePool::Sqlite(pool) => pool.close().await,
+        }
+    }
+}
+
+async fn applied_migrations<'a, A>(connection: A) -> Result<Vec<AppliedMigration>, MigrateError>
+where
+    A: Acquire<'a>,
+    <A::Connection as Deref>::Target: Migrate,
+{
+    let mut connection = connection.acquire().await?;
+    connection.ensure_migrations_table().await?;
+    connection.list_applied_migrations().await
+}
+
+/// Replaces the `previous` checksums of the `applied` migrations with those of `migrator`
+fn with_current_checksums(
+    migrator: &Migrator,
+    previous: &[(i64, &str)],
+    mut applied: Vec<AppliedMigration>,
+) -> Vec<AppliedMigration> {
+    for migration in &mut applied {
+        let is_previous = previous.iter().any(|(version, checksum)| {
+            *version == migration.version && hex::encode(&migration.checksum) == *checksum
+        });
+        let current = migrator.iter().find(|current| {
+            current.version == migration.version && !current.migration_type.is_down_migration()
+        });
+        if let (true, Some(current)) = (is_previous, current) {
+            migration.checksum = current.checksum.clone();
+        }
+    }
+    applied
+}
+
+fn migration_status(migrator: &Migrator, applied: &[AppliedMigration]) -> Vec<MigrationStatus> {
+    let applied_checksums: HashMap<i64, &[u8]> = applied
+        .iter()
+        .map(|migration| (migration.version, migration.checksum.as_ref()))
+        .collect();
+
+    let mut status: Vec<MigrationStatus> = migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let state = match applied_checksums.get(&migration.version) {
+                None => MigrationState::Pending,
+                Some(checksum) if *checksum == migration.checksum.as_ref() => {
+                    MigrationState::Applied
+                }
+                Some(_) => MigrationState::Changed,
+            };
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+            }
+        })
+        .collect();
+    status.extend(
+        applied
+            .iter()
+            .filter(|applied| !migrator.iter().any(|m| m.version == applied.version))
+            .map(|applied| MigrationStatus {
+                version: applied.version,
+                description: String::new(),
+                state: MigrationState::Unknown,
+            }),
+    );
+    status.sort_by_key(|migration| migration.version);
+
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sqlx::migrate::{Migration, MigrationType};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    fn migration(version: i64, sql: &'static str) -> Migration {
+        Migration::new(
+            version,
+            "migration".into(),
+            MigrationType::ReversibleUp,
+            sql.into(),
+        )
+    }
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            checksum: migration.checksum.clone(),
+        }
+    }
+
+    async fn in_memory_sqlite() -> DatabasePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        DatabasePool::Sqlite(pool)
+    }
+
+    #[test]
+    fn status_reports_pending_changed_and_unknown_migrations() {
+        let migrator = Migrator {
+            migrations: vec![
+                migration(1, "SELECT 1;"),
+                migration(2, "SELECT 2;"),
+                migration(3, "SELECT 3;"),
+            ]
+            .into(),
+            ignore_missing: false,
+            locking: true,
+        };
+        let applied = [
+            applied(&migration(1, "SELECT 1;")),
+            applied(&migration(2, "SELECT 'changed';")),
+            applied(&migration(4, "SELECT 4;")),
+        ];
+
+        let states: Vec<_> = migration_status(&migrator, &applied)
+            .into_iter()
+            .map(|status| (status.version, status.state))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (1, MigrationState::Applied),
+                (2, MigrationState::Changed),
+                (3, MigrationState::Pending),
+                (4, MigrationState::Unknown),
+            ],
+            states
+        );
+    }
+
+    #[tokio::test]
+    async fn migrations_are_applied_and_the_last_one_is_reverted() {
+        let pool = in_memory_sqlite().await;
+        let all: Vec<i64> = SQLITE_MIGRATOR
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| migration.version)
+            .collect();
+
+        assert_eq!(all, pool.run_migrations().await.unwrap());
+        assert!(pool.run_migrations().await.unwrap().is_empty());
+        let reverted = pool.revert_last_migration().await.unwrap();
+
+        assert_eq!(all.last().copied(), reverted);
+        let pending: Vec<_> = pool
+            .migration_status()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|status| status.state == MigrationState::Pending)
+            .map(|status| status.version)
+            .collect();
+        assert_eq!(vec![all[all.len() - 1]], pending);
+    }
+
+    #[tokio::test]
+    async fn the_status_does_not_create_the_migrations_table() {
+        let pool = in_memory_sqlite().await;
+
+        let status = pool.migration_status().await.unwrap();
+
+        assert!(status
+            .iter()
+            .all(|status| status.state == MigrationState::Pending));
+        assert!(!pool.has_migrations_table().await.unwrap());
+    }
+
+    #[rstest::rstest]
+    #[case::postgres(&POSTGRES_MIGRATOR, "migrations", &POSTGRES_PREVIOUS_CHECKSUMS)]
+    #[case::sqlite(&SQLITE_MIGRATOR, "migrations_sqlite", &SQLITE_PREVIOUS_CHECKSUMS)]
+    fn the_previous_checksums_are_those_of_the_previous_headers(
+        #[case] migrator: &Migrator,
+        #[case] directory: &str,
+        #[case] previous: &[(i64, &str)],
+    ) {
+        use sha2::{Digest, Sha384};
+
+        for (version, checksum) in previous {
+            let migration = migrator
+                .iter()
+                .find(|m| m.version == *version && !m.migration_type.is_down_migration())
+                .unwrap();
+            let name = format!("{}_{}", version, migration.description.replace(' ', "_"));
+            let previous_sql = migration.sql.replacen(
+                &format!("-- {}/{}.up.sql", directory, name),
+                &format!("-- {}/{}.sql", directory, name),
+                1,
+            );
+
+            assert_ne!(previous_sql, migration.sql);
+            assert_eq!(
+                *checksum,
+                hex::encode(Sha384::digest(previous_sql.as_bytes()))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn the_previous_checksums_are_upgraded() {
+        let pool = in_memory_sqlite().await;
+        pool.run_migrations().await.unwrap();
+        let DatabasePool::Sqlite(sqlite_pool) = &pool else {
+            unreachable!()
+        };
+        let (version, previous) = SQLITE_PREVIOUS_CHECKSUMS[0];
+        sqlx::query("UPDATE _sqlx_migrations SET checksum = $1 WHERE version = $2")
+            .bind(hex::decode(previous).unwrap())
+            .bind(version)
+            .execute(sqlite_pool)
+            .await
+            .unwrap();
+
+        assert!(pool
+            .migration_status()
+            .await
+            .unwrap()
+            .iter()
+            .all(|status| status.state == MigrationState::Applied));
+        assert!(pool.run_migrations().await.unwrap().is_empty());
+
+        let checksum: Vec<u8> =
+            sqlx::query_scalar("SELECT checksum FROM _sqlx_migrations WHERE version = $1")
+                .bind(version)
+                .fetch_one(sqlite_pool)
+                .await
+                .unwrap();
+        let current = SQLITE_MIGRATOR
+            .iter()
+            .find(|m| m.version == version && !m.migration_type.is_down_migration())
+            .unwrap();
+        assert_eq!(current.checksum.as_ref(), checksum.as_slice());
+    }
+
+    #[tokio::test]
+    async fn migrations_are_not_applied_to_a_newer_schema() {
+        let pool = in_memory_sqlite().await;
+        let DatabasePool::Sqlite(sqlite_pool) = &pool else {
+            unreachable!()
+        };
+        let newer = migration(99991231000000, "SELECT 1;");
+        Migrator {
+            migrations: vec![newer.clone()].into(),
+            ignore_missing: true,
+            locking: false,
+        }
+        .run(sqlite_pool)
+        .await
+        .unwrap();
+
+        let outcome = pool.run_migrations().await;
+
+        assert!(
+            matches!(outcome, Err(MigrationError::SchemaNewer(ref versions)) if versions == &[newer.version]),
+            "{:?}",
+            outcome
+        );
+        assert!(pool
+            .migration_status()
+            .await
+            .unwrap()
+            .iter()
+            .all(|status| status.state != MigrationState::Applied));
+    }
+}
@@ -0,0 +1,259 @@
+use crate::domain::SubscriberEmail;
+use crate::redaction::Redacted;
+use std::collections::HashSet;
+
+/// The bundled list of disposable email domains, compiled into the binary
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// What to do with an email address that matches a policy rule
+///
+/// It is deserialized from configuration as `"allow"`, `"flag"` or `"reject"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    /// Let the address through, as if the rule didn't exist.
+    Allow,
+    /// Accept the address, but mark the subscriber for a manual review.
+    Flag,
+    /// Refuse the address.
+    Reject,
+}
+
+/// The outcome of checking an email address against an `EmailPolicy`
+///
+/// `Flag` and `Reject` carry a human-readable reason.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PolicyVerdict {
+    Accept,
+    Flag(String),
+    Reject(String),
+}
+
+/// A set of (lower-case) entries together with the action to take when one of them matches
+pub struct PolicyRule {
+    entries: HashSet<String>,
+    action: PolicyAction,
+}
+
+impl PolicyRule {
+    /// Constructs a new rule out of the given entries
+    ///
+    /// Entries are trimmed and lower-cased. Empty entries are skipped.
+    pub fn new<I, S>(entries: I, action: PolicyAction) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let entries = entries
+            .into_iter()
+            .map(|entry| entry.as_ref().trim().to_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+
+        Self { entries, action }
+    }
+
+    /// Constructs a new rule out of the bundled list of disposable domains,
+    /// extended with the entries from `extra_list`, which has the same format
+    pub fn disposable_domains(extra_list: Option<&str>, action: PolicyAction) -> Self {
+        let bundled = parse_list(BUNDLED_DISPOSABLE_DOMAINS);
+        let extra = extra_list.into_iter().flat_map(parse_list);
+
+        Self::new(bundled.chain(extra), action)
+    }
+
+    /// Checks whether the domain, or any of its parent domains, is contained in the rule
+    fn matches_domain(&self, domain: &str) -> bool {
+        parent_domains(domain).any(|domain| self.entries.contains(domain))
+    }
+
+    fn matches(&self, entry: &str) -> bool {
+        self.entries.contains(entry)
+    }
+}
+
+/// Signup policy for subscriber email addresses
+///
+/// `SubscriberEmail::parse` only tells us whether an address is syntactically valid.
+/// The policy goes one step further and tells us whether we want the address on our list.
+///
+/// It consists of:
+///  - `allowed_domains` - domains that are always accepted, regardless of the other rules;
+///  - `denied_domains` - domains that we don't want on our list;
+///  - `disposable_domains` - known throwaway email providers;
+///  - `role_local_parts` - local parts such as `info` or `admin`, which belong to a role
+///    account rather than to a person.
+///
+/// A domain rule also matches all subdomains of its entries.
+/// When several rules match, the strictest action wins, and all flag reasons are kept.
+pub struct EmailPolicy {
+    pub allowed_domains: HashSet<String>,
+    pub denied_domains: PolicyRule,
+    pub disposable_domains: PolicyRule,
+    pub role_local_parts: PolicyRule,
+}
+
+impl EmailPolicy {
+    /// Checks the email address against all rules of the policy
+    pub fn evaluate(&self, email: &SubscriberEmail) -> PolicyVerdict {
+        let domain = email.domain().to_lowercase();
+        if parent_domains(&domain).any(|domain| self.allowed_domains.contains(domain)) {
+            return PolicyVerdict::Accept;
+        }
+
+        // Ignore sub-addressing, so that "info+newsletter" is treated as "info".
+        let local_part = email.local_part().to_lowercase();
+        let local_part = local_part.split('+').next().unwrap_or_default();
+
+        let matches = [
+            (
+                self.denied_domains.matches_domain(&domain),
+                self.denied_domains.action,
+                format!(
+                    "the domain '{}' is on the deny list",
+                    Redacted::email(&domain)
+                ),
+            ),
+            (
+                self.disposable_domains.matches_domain(&domain),
+                self.disposable_domains.action,
+                format!(
+                    "'{}' is a disposable email domain",
+                    Redacted::email(&domain)
+                ),
+            ),
+            (
+                self.role_local_parts.matches(local_part),
+                self.role_local_parts.action,
+                format!("'{}' is a role account", Redacted::email(local_part)),
+            ),
+        ];
+
+        let mut flag_reasons = vec![];
+        for (is_match, action, reason) in matches {
+            if !is_match {
+                continue;
+            }
+            match action {
+                PolicyAction::Allow => {}
+                PolicyAction::Flag => flag_reasons.push(reason),
+                PolicyAction::Reject => return PolicyVerdict::Reject(reason),
+            }
+        }
+
+        if flag_reasons.is_empty() {
+            PolicyVerdict::Accept
+        } else {
+            PolicyVerdict::Flag(flag_reasons.join("; "))
+        }
+    }
+}
+
+/// Parses a list of entries in the format of the bundled disposable domains file
+///
+/// One entry per line. Empty lines and lines starting with `#` are ignored.
+pub fn parse_list(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// Yields the domain itself, followed by all of its parent domains
+///
+/// For example, "a.b.com" yields "a.b.com", "b.com" and "com".
+fn parent_domains(domain: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(domain), |domain| {
+        domain.split_once('.').map(|(_, parent)| parent)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn policy() -> EmailPolicy {
+        EmailPolicy {
+            allowed_domains: HashSet::from([String::from("trusted.yq")]),
+            denied_domains: PolicyRule::new(["Denied.yq"], PolicyAction::Reject),
+            disposable_domains: PolicyRule::disposable_domains(
+                Some("# comment\n\nthrowaway.yq\n"),
+                PolicyAction::Reject,
+            ),
+            role_local_parts: PolicyRule::new(["info", "admin"], PolicyAction::Flag),
+        }
+    }
+
+    fn email(email: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(email.to_string()).unwrap()
+    }
+
+    #[rstest]
+    #[case::regular("john.doe@domain.yq")]
+    #[case::allowed_role_account("info@trusted.yq")]
+    #[case::allowed_subdomain("admin@mail.trusted.yq")]
+    #[case::role_as_a_substring("information@domain.yq")]
+    fn evaluate_accepts(policy: EmailPolicy, #[case] address: &str) {
+        assert_eq!(PolicyVerdict::Accept, policy.evaluate(&email(address)));
+    }
+
+    #[rstest]
+    #[case::denied_domain("john.doe@denied.yq")]
+    #[case::denied_subdomain("john.doe@mail.DENIED.yq")]
+    #[case::bundled_disposable_domain("john.doe@mailinator.com")]
+    #[case::extra_disposable_domain("john.doe@throwaway.yq")]
+    #[case::role_account_on_disposable_domain("info@yopmail.com")]
+    fn evaluate_rejects(policy: EmailPolicy, #[case] address: &str) {
+        assert!(matches!(
+            policy.evaluate(&email(address)),
+            PolicyVerdict::Reject(_)
+        ));
+    }
+
+    #[rstest]
+    #[case::role_account("info@domain.yq")]
+    #[case::upper_case_role_account("ADMIN@domain.yq")]
+    #[case::sub_addressed_role_account("info+news@domain.yq")]
+    fn evaluate_flags(policy: EmailPolicy, #[case] address: &str) {
+        assert!(matches!(
+            policy.evaluate(&email(address)),
+            PolicyVerdict::Flag(_)
+        ));
+    }
+
+    #[rstest]
+    fn evaluate_ignores_rules_set_to_allow(mut policy: EmailPolicy) {
+        policy.disposable_domains.action = PolicyAction::Allow;
+        policy.role_local_parts.action = PolicyAction::Allow;
+
+        assert_eq!(
+            PolicyVerdict::Accept,
+            policy.evaluate(&email("info@mailinator.com"))
+        );
+    }
+
+    #[rstest]
+    fn evaluate_keeps_all_flag_reasons(mut policy: EmailPolicy) {
+        policy.disposable_domains.action = PolicyAction::Flag;
+
+        match policy.evaluate(&email("info@mailinator.com")) {
+            PolicyVerdict::Flag(reason) => {
+                assert!(reason.contains("disposable"), "{}", reason);
+                assert!(reason.contains("role account"), "{}", reason);
+                // The parts of the address are redacted, as the reason is logged
+                assert!(!reason.contains("mailinator"), "{}", reason);
+                assert!(!reason.contains("'info'"), "{}", reason);
+            }
+            verdict => panic!("Expected a flag, got {:?}.", verdict),
+        }
+    }
+
+    #[test]
+    fn bundled_disposable_domains_list_is_not_empty() {
+        assert!(parse_list(BUNDLED_DISPOSABLE_DOMAINS).count() > 0);
+        assert!(parse_list(BUNDLED_DISPOSABLE_DOMAINS).all(|domain| !domain.contains(' ')));
+    }
+}
@@ -1,9 +1,13 @@
 //! src/domain/mod.rs
 
+mod email_policy;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_status;
 
+pub use email_policy::{parse_list, EmailPolicy, PolicyAction, PolicyRule, PolicyVerdict};
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscription_status::SubscriptionStatus;
@@ -1,3 +1,4 @@
+use crate::redaction::Redacted;
 use validator::validate_email;
 
 /// `SubscriberEmail` either contains a valid email address (`String`),
@@ -17,7 +18,10 @@ use validator::validate_email;
 /// of execution, or it is immediately discarded at that moment, so it doesn't enter our
 /// system as an invalid value, and the user of the `SubscriberEmail::parse` function
 /// will be notified of the error and should handle it properly (as desired).
-#[derive(Clone, Debug)]
+///
+/// `Debug` redacts the email address according to the current `RedactionPolicy`,
+/// so that it doesn't leak into the logs.
+#[derive(Clone)]
 pub struct SubscriberEmail(String);
 
 impl SubscriberEmail {
@@ -36,6 +40,18 @@ impl SubscriberEmail {
             Err(format!(r#""{}" is not a valid subscriber email."#, email))
         }
     }
+
+    /// Gets the local part of the email address, i.e., the part before the last `@`
+    pub fn local_part(&self) -> &str {
+        self.0
+            .rsplit_once('@')
+            .map_or("", |(local_part, _)| local_part)
+    }
+
+    /// Gets the domain of the email address, i.e., the part after the last `@`
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').map_or("", |(_, domain)| domain)
+    }
 }
 
 /// Needed so we can extract the contained private `String` field.
@@ -47,6 +63,14 @@ impl AsRef<str> for SubscriberEmail {
     }
 }
 
+impl std::fmt::Debug for SubscriberEmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SubscriberEmail")
+            .field(&Redacted::email(&self.0))
+            .finish()
+    }
+}
+
 /// We shouldn't (have to) test a third-party library,
 /// but we are doing it here for the sake of exercise and example.
 /// To be fair, we are testing our own `parse` function, and not
@@ -179,4 +203,29 @@ mod tests {
 
         assert!(SubscriberEmail::parse(email.to_string()).is_err());
     }
+
+    #[rstest(
+        email,
+        local_part,
+        domain,
+        case::simple("john.doe@domain.yq", "john.doe", "domain.yq"),
+        case::subdomain("info@mail.domain.yq", "info", "mail.domain.yq"),
+        case::sub_addressed("john.doe+news@domain.yq", "john.doe+news", "domain.yq")
+    )]
+    fn local_part_and_domain_are_split_at_the_last_at_symbol(
+        email: &str,
+        local_part: &str,
+        domain: &str,
+    ) {
+        let email = SubscriberEmail::parse(email.to_string()).unwrap();
+        assert_eq!(local_part, email.local_part());
+        assert_eq!(domain, email.domain());
+    }
+
+    #[test]
+    fn debug_does_not_leak_the_email() {
+        let email = SubscriberEmail::parse(String::from("john.doe@domain.yq")).unwrap();
+
+        assert!(!format!("{:?}", email).contains("john.doe"));
+    }
 }
@@ -1,4 +1,8 @@
-use crate::consts::{FORBIDDEN_NAME_CHARACTERS, MAX_NAME_LEN};
+use crate::consts::{
+    BIDI_CONTROL_CHARACTERS, FORBIDDEN_NAME_CHARACTERS, MAX_NAME_LEN, ZERO_WIDTH_NAME_CHARACTERS,
+};
+use crate::redaction::Redacted;
+use unicode_normalization::UnicodeNormalization;
 use unicode_segmentation::UnicodeSegmentation;
 
 /// This is a tuple-struct with a single private anonymous `String` field.
@@ -20,7 +24,9 @@ use unicode_segmentation::UnicodeSegmentation;
 /// `SubscriberName` satisfy *all* our validation constraints.
 /// We have effectively made it *impossible* for an instance of
 /// `SubscriberName` to violate those constraints.
-#[derive(Debug)]
+///
+/// `Debug` redacts the name according to the current `RedactionPolicy`,
+/// so that it doesn't leak into the logs.
 pub struct SubscriberName(String);
 
 impl SubscriberName {
@@ -31,9 +37,13 @@ impl SubscriberName {
     /// `Err<String>` otherwise.
     ///
     /// We have implemented our own validation logic per our constraints.
+    ///
+    /// The name is normalized before it is validated, see `normalize_name`,
+    /// so the stored name may differ from the input.
     pub fn parse(name: String) -> Result<SubscriberName, String> {
-        if is_valid_name(&name) {
-            Ok(SubscriberName(name))
+        let normalized_name = normalize_name(&name);
+        if is_valid_name(&normalized_name) {
+            Ok(SubscriberName(normalized_name))
         } else {
             Err(format!(r#""{}" is not a valid subscriber name."#, name))
         }
@@ -49,10 +59,39 @@ impl AsRef<str> for SubscriberName {
     }
 }
 
+impl std::fmt::Debug for SubscriberName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SubscriberName")
+            .field(&Redacted::name(&self.0))
+            .finish()
+    }
+}
+
+/// Brings a new user's name into its canonical form
+///
+/// - Strips zero-width characters, which are invisible anyway;
+/// - applies the Unicode Normalization Form C (NFC), so that, for example,
+///   "e" followed by a combining acute accent and a precomposed "é" are stored the same way.
+///   It comes after the stripping, because a zero-width character between a letter and
+///   its accent would keep them from being composed;
+/// - trims the name and collapses all internal whitespace into a single space.
+fn normalize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| !ZERO_WIDTH_NAME_CHARACTERS.contains(c))
+        .nfc()
+        .collect();
+
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
 /// Checks validity of a new user's name
 ///
 /// Returns `true` if **ALL** input validation constraints are satisfied,
 /// `false` otherwise.
+///
+/// Whitespace control characters, such as tabs and new lines, are allowed here,
+/// because `normalize_name` turns them into spaces.
 fn is_valid_name(name: &str) -> bool {
     let is_empty_or_whitespace = name.trim().is_empty();
 
@@ -61,7 +100,14 @@ fn is_valid_name(name: &str) -> bool {
     let contains_a_forbidden_character =
         name.chars().any(|c| FORBIDDEN_NAME_CHARACTERS.contains(&c));
 
-    !(is_empty_or_whitespace || is_too_long || contains_a_forbidden_character)
+    let contains_a_control_character = name
+        .chars()
+        .any(|c| (c.is_control() && !c.is_whitespace()) || BIDI_CONTROL_CHARACTERS.contains(&c));
+
+    !(is_empty_or_whitespace
+        || is_too_long
+        || contains_a_forbidden_character
+        || contains_a_control_character)
 }
 
 #[cfg(test)]
@@ -70,7 +116,9 @@ mod tests {
 
     use claims::{assert_err, assert_ok};
     use once_cell::sync::Lazy;
+    use quickcheck_macros::quickcheck;
     use rstest::rstest;
+    use unicode_normalization::is_nfc;
 
     static VALID_MAX_LONG_NAME: Lazy<String> = Lazy::new(|| "å".repeat(MAX_NAME_LEN));
     static TOO_LONG_NAME: Lazy<String> = Lazy::new(|| "a".repeat(MAX_NAME_LEN + 1));
@@ -214,7 +262,7 @@ mod tests {
         let valid_name = String::from(valid_name);
         let subscriber_name = SubscriberName::parse(valid_name.clone()).unwrap();
         assert_eq!(
-            valid_name,
+            valid_name.split_whitespace().collect::<Vec<_>>().join(" "),
             subscriber_name.as_ref(),
             "Rejected a valid name '{}'.",
             valid_name
@@ -269,4 +317,88 @@ mod tests {
             );
         }
     }
+
+    #[rstest]
+    #[case::decomposed_accent("Rene\u{301}e", "Renée")]
+    #[case::zero_width_space("Jo\u{200B}hn", "John")]
+    #[case::byte_order_mark("\u{FEFF}John", "John")]
+    #[case::internal_whitespace("John \t\u{00A0}  Doe", "John Doe")]
+    #[case::zero_width_joiner(
+        "\u{0915}\u{094D}\u{200D}\u{0937}",
+        "\u{0915}\u{094D}\u{200D}\u{0937}"
+    )]
+    #[case::zero_width_space_before_an_accent("Rene\u{200B}\u{301}e", "Renée")]
+    #[case::word_joiner_before_an_accent("Rene\u{2060}\u{301}e", "Renée")]
+    #[case::zero_width_joiner_before_an_accent("e\u{200D}\u{301}", "e\u{200D}\u{301}")]
+    fn parse_normalizes_names(#[case] name: &str, #[case] expected: &str) {
+        let subscriber_name = SubscriberName::parse(String::from(name)).unwrap();
+        assert_eq!(expected, subscriber_name.as_ref());
+        // The normalized name is in NFC, and normalizing it again doesn't change it
+        assert!(unicode_normalization::is_nfc(subscriber_name.as_ref()));
+        assert_eq!(
+            subscriber_name.as_ref(),
+            SubscriberName::parse(expected.to_string())
+                .unwrap()
+                .as_ref()
+        );
+    }
+
+    #[rstest]
+    #[case::right_to_left_override("John\u{202E}eoD")]
+    #[case::left_to_right_isolate("\u{2066}John")]
+    #[case::right_to_left_mark("John\u{200F}")]
+    #[case::null("John\u{0}Doe")]
+    #[case::escape("John\u{1B}[31mDoe")]
+    #[case::delete("John\u{7F}")]
+    #[case::only_zero_width_characters("\u{200B}\u{FEFF}")]
+    fn parse_rejects_names_with_control_characters(#[case] invalid_name: &str) {
+        assert_err!(
+            SubscriberName::parse(invalid_name.to_string()),
+            r#"Didn't reject the invalid name "{}"."#,
+            invalid_name.escape_unicode()
+        );
+    }
+
+    /// Property-based tests with `quickcheck`, which feeds arbitrary Unicode strings,
+    /// including whitespace and control characters, to `SubscriberName::parse`.
+    ///
+    /// Whatever we get as input, a name that we accept is always in its canonical form.
+    #[quickcheck]
+    fn parsed_names_are_normalized(name: String) -> bool {
+        match SubscriberName::parse(name) {
+            Ok(subscriber_name) => {
+                let name = subscriber_name.as_ref();
+                is_nfc(name)
+                    && name == name.trim()
+                    && !name.contains("  ")
+                    && !name.chars().any(|c| c.is_control())
+                    && !name
+                        .chars()
+                        .any(|c| ZERO_WIDTH_NAME_CHARACTERS.contains(&c))
+                    && !name.chars().any(|c| BIDI_CONTROL_CHARACTERS.contains(&c))
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Parsing an already parsed name gives the same name back.
+    #[quickcheck]
+    fn parse_is_idempotent(name: String) -> bool {
+        match SubscriberName::parse(name) {
+            Ok(subscriber_name) => {
+                let name = subscriber_name.as_ref();
+                SubscriberName::parse(name.to_string()).map(|n| n.0) == Ok(name.to_string())
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Whitespace around and between the words doesn't change the parsed name.
+    #[quickcheck]
+    fn parse_ignores_extra_whitespace(words: Vec<String>) -> bool {
+        let spaced = format!(" \t{}\n ", words.join(" \t \n "));
+        let compact = words.join(" ");
+        SubscriberName::parse(spaced).ok().map(|n| n.0)
+            == SubscriberName::parse(compact).ok().map(|n| n.0)
+    }
 }
@@ -0,0 +1,63 @@
+/// The lifecycle of a subscription
+///
+/// A new subscriber is `PendingConfirmation` until they confirm their email address.
+/// It is stored in the database as a string, see `as_str`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriptionStatus {
+    /// Convert `SubscriptionStatus` into a string slice
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+impl TryFrom<String> for SubscriptionStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "pending_confirmation" => Ok(Self::PendingConfirmation),
+            "confirmed" => Ok(Self::Confirmed),
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            other => Err(format!("'{}' is not a valid subscription status.", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus;
+
+    use claims::assert_err;
+    use rstest::rstest;
+
+    #[rstest]
+    fn status_survives_a_round_trip_through_a_string(
+        #[values(
+            SubscriptionStatus::PendingConfirmation,
+            SubscriptionStatus::Confirmed,
+            SubscriptionStatus::Unsubscribed
+        )]
+        status: SubscriptionStatus,
+    ) {
+        assert_eq!(
+            Ok(status),
+            SubscriptionStatus::try_from(status.as_str().to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        assert_err!(SubscriptionStatus::try_from(String::from("deleted")));
+    }
+}
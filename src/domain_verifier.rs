@@ -0,0 +1,358 @@
+//! src/domain_verifier.rs
+
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::rr::Name;
+use hickory_resolver::TokioAsyncResolver;
+use lru::LruCache;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Checks whether a domain can receive emails
+///
+/// `SubscriberEmail::parse` only checks the syntax of an address,
+/// so typos in the domain, such as `gmial.con`, get through.
+/// A `DomainVerifier` catches those by looking the domain up.
+///
+/// Returns `Ok(true)` if the domain can receive emails, `Ok(false)` if it certainly can't,
+/// and `Err` if we couldn't find out, for example, because the lookup timed out.
+/// Callers are expected to *fail open* on `Err`, so an unreachable DNS server
+/// doesn't stop people from subscribing.
+#[async_trait::async_trait]
+pub trait DomainVerifier: Send + Sync {
+    async fn verify(&self, domain: &str) -> Result<bool, String>;
+}
+
+/// A `DomainVerifier` which accepts every domain
+///
+/// Used when domain verification is turned off in configuration.
+pub struct NoopDomainVerifier;
+
+#[async_trait::async_trait]
+impl DomainVerifier for NoopDomainVerifier {
+    async fn verify(&self, _domain: &str) -> Result<bool, String> {
+        Ok(true)
+    }
+}
+
+/// A `DomainVerifier` which looks up DNS records
+///
+/// A domain can receive emails if it has an MX record, or, as a fallback
+/// defined in RFC 5321, an A or an AAAA record. A "null MX" record, defined in RFC 7505,
+/// says that the domain doesn't receive emails, whatever its other records.
+///
+/// It uses the system resolver configuration, or the given name servers,
+/// which makes it possible to point it at a local DNS server.
+/// Either way, a verification which takes longer than `timeout` fails.
+pub struct DnsDomainVerifier {
+    resolver: TokioAsyncResolver,
+    timeout: Duration,
+}
+
+impl DnsDomainVerifier {
+    /// Constructs a new `DnsDomainVerifier`
+    ///
+    /// Uses the system resolver configuration if `name_servers` is empty.
+    pub fn new(name_servers: &[SocketAddr], timeout: Duration) -> Result<Self, String> {
+        let (config, mut options) = if name_servers.is_empty() {
+            hickory_resolver::system_conf::read_system_conf().map_err(|e| e.to_string())?
+        } else {
+            let mut group = NameServerConfigGroup::new();
+            for name_server in name_servers {
+                group.merge(NameServerConfigGroup::from_ips_clear(
+                    &[name_server.ip()],
+                    name_server.port(),
+                    true,
+                ));
+            }
+            (
+                ResolverConfig::from_parts(None, vec![], group),
+                ResolverOpts::default(),
+            )
+        };
+        options.timeout = timeout;
+        let resolver = TokioAsyncResolver::tokio(config, options);
+
+        Ok(Self { resolver, timeout })
+    }
+
+    async fn lookup(&self, domain: &str) -> Result<bool, String> {
+        // A trailing dot makes the name fully-qualified, so search domains aren't appended.
+        let fqdn = format!("{}.", domain.trim_end_matches('.'));
+
+        match self.resolver.mx_lookup(fqdn.as_str()).await {
+            Ok(mx) => {
+                if let Some(receives_emails) = mx_receives_emails(mx.iter().map(|mx| mx.exchange()))
+                {
+                    return Ok(receives_emails);
+                }
+            }
+            Err(e) => check_no_records(e)?,
+        }
+
+        match self.resolver.lookup_ip(fqdn.as_str()).await {
+            Ok(ips) => Ok(ips.iter().next().is_some()),
+            Err(e) => check_no_records(e).map(|_| false),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl DomainVerifier for DnsDomainVerifier {
+    #[tracing::instrument(name = "Looking up the domain's DNS records", skip(self))]
+    async fn verify(&self, domain: &str) -> Result<bool, String> {
+        // The resolver's own timeout applies to each query, and each query can be retried
+        tokio::time::timeout(self.timeout, self.lookup(domain))
+            .await
+            .map_err(|_| format!("The DNS lookup of '{}' timed out.", domain))?
+    }
+}
+
+/// Tells whether the domain receives emails, by the exchanges of its MX records
+///
+/// Returns `None` if there are no MX records, so the A and AAAA records decide.
+/// A domain whose only exchange is the root, `.`, has a null MX, so it doesn't.
+fn mx_receives_emails<'a>(exchanges: impl IntoIterator<Item = &'a Name>) -> Option<bool> {
+    let mut exchanges = exchanges.into_iter().peekable();
+    exchanges.peek()?;
+    Some(!exchanges.all(Name::is_root))
+}
+
+/// Turns a "no records" response into `Ok`, and any other resolver error into `Err`
+fn check_no_records(error: ResolveError) -> Result<(), String> {
+    match error.kind() {
+        ResolveErrorKind::NoRecordsFound { .. } => Ok(()),
+        _ => Err(error.to_string()),
+    }
+}
+
+/// A `DomainVerifier` with a fixed set of deliverable domains
+///
+/// Doesn't need the network, so it is meant for tests.
+pub struct StubDomainVerifier {
+    deliverable_domains: HashSet<String>,
+}
+
+impl StubDomainVerifier {
+    pub fn new<I, S>(deliverable_domains: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            deliverable_domains: deliverable_domains.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl DomainVerifier for StubDomainVerifier {
+    async fn verify(&self, domain: &str) -> Result<bool, String> {
+        Ok(self.deliverable_domains.contains(domain))
+    }
+}
+
+/// Wraps another `DomainVerifier` and adds a timeout and a result cache to it
+///
+/// Lookups that don't finish within `timeout` yield an error.
+/// Successful lookups are cached for `ttl`, both positive and negative ones.
+/// Errors are not cached, so a lookup is retried on the next signup.
+///
+/// The cache holds up to `capacity` domains, and evicts the least recently used one
+/// to make room, so signups with random domains can't grow it without bounds.
+pub struct CachedDomainVerifier<V> {
+    inner: V,
+    timeout: Duration,
+    ttl: Duration,
+    cache: Mutex<LruCache<String, (bool, Instant)>>,
+}
+
+impl<V: DomainVerifier> CachedDomainVerifier<V> {
+    pub fn new(inner: V, timeout: Duration, ttl: Duration, capacity: NonZeroUsize) -> Self {
+        Self {
+            inner,
+            timeout,
+            ttl,
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    fn cached(&self, domain: &str) -> Option<bool> {
+        let mut cache = self.cache.lock().expect("Domain cache lock is poisoned.");
+        match cache.get(domain) {
+            Some((is_deliverable, verified_at)) if verified_at.elapsed() < self.ttl => {
+                Some(*is_deliverable)
+            }
+            Some(_) => {
+                cache.pop(domain);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<V: DomainVerifier> DomainVerifier for CachedDomainVerifier<V> {
+    async fn verify(&self, domain: &str) -> Result<bool, String> {
+        let domain = domain.to_lowercase();
+        if let Some(is_deliverable) = self.cached(&domain) {
+            return Ok(is_deliverable);
+        }
+
+        let is_deliverable = tokio::time::timeout(self.timeout, self.inner.verify(&domain))
+            .await
+            .map_err(|_| format!("Verification of the domain '{}' timed out.", domain))??;
+
+        let mut cache = self.cache.lock().expect("Domain cache lock is poisoned.");
+        cache.put(domain, (is_deliverable, Instant::now()));
+
+        Ok(is_deliverable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claims::{assert_err, assert_ok_eq};
+    use rstest::rstest;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const CAPACITY: NonZeroUsize = NonZeroUsize::new(100).unwrap();
+
+    /// Counts the lookups, and takes `delay` to answer each of them
+    struct CountingVerifier {
+        lookups: AtomicUsize,
+        delay: Duration,
+    }
+
+    impl CountingVerifier {
+        fn new(delay: Duration) -> Self {
+            Self {
+                lookups: AtomicUsize::new(0),
+                delay,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl DomainVerifier for CountingVerifier {
+        async fn verify(&self, domain: &str) -> Result<bool, String> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            Ok(domain == "domain.yq")
+        }
+    }
+
+    #[rstest]
+    #[case::deliverable("domain.yq", true)]
+    #[case::undeliverable("gmial.con", false)]
+    #[tokio::test]
+    async fn stub_verifier_only_accepts_its_domains(#[case] domain: &str, #[case] expected: bool) {
+        let verifier = StubDomainVerifier::new(["domain.yq"]);
+        assert_ok_eq!(verifier.verify(domain).await, expected);
+    }
+
+    #[tokio::test]
+    async fn cached_verifier_looks_each_domain_up_only_once() {
+        let verifier = CachedDomainVerifier::new(
+            CountingVerifier::new(Duration::ZERO),
+            Duration::from_secs(1),
+            Duration::from_secs(60),
+            CAPACITY,
+        );
+
+        assert_ok_eq!(verifier.verify("domain.yq").await, true);
+        assert_ok_eq!(verifier.verify("DOMAIN.yq").await, true);
+        assert_ok_eq!(verifier.verify("gmial.con").await, false);
+        assert_ok_eq!(verifier.verify("gmial.con").await, false);
+
+        assert_eq!(2, verifier.inner.lookups.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn cached_verifier_looks_the_domain_up_again_after_the_ttl() {
+        let verifier = CachedDomainVerifier::new(
+            CountingVerifier::new(Duration::ZERO),
+            Duration::from_secs(1),
+            Duration::ZERO,
+            CAPACITY,
+        );
+
+        assert_ok_eq!(verifier.verify("domain.yq").await, true);
+        assert_ok_eq!(verifier.verify("domain.yq").await, true);
+
+        assert_eq!(2, verifier.inner.lookups.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn cached_verifier_evicts_the_least_recently_used_domain_when_full() {
+        let verifier = CachedDomainVerifier::new(
+            CountingVerifier::new(Duration::ZERO),
+            Duration::from_secs(1),
+            Duration::from_secs(60),
+            NonZeroUsize::new(2).unwrap(),
+        );
+
+        for domain in [
+            "domain.yq",
+            "gmial.con",
+            "domain.yq",
+            "other.yq",
+            "domain.yq",
+        ] {
+            verifier.verify(domain).await.unwrap();
+        }
+        assert_eq!(3, verifier.inner.lookups.load(Ordering::SeqCst));
+
+        // `gmial.con` was the least recently used domain when `other.yq` was added
+        verifier.verify("gmial.con").await.unwrap();
+        assert_eq!(4, verifier.inner.lookups.load(Ordering::SeqCst));
+    }
+
+    #[rstest]
+    #[case::no_records(&[], None)]
+    #[case::exchanges(&["mx1.domain.yq.", "mx2.domain.yq."], Some(true))]
+    #[case::null_mx(&["."], Some(false))]
+    fn null_mx_means_the_domain_receives_no_emails(
+        #[case] exchanges: &[&str],
+        #[case] expected: Option<bool>,
+    ) {
+        let exchanges: Vec<Name> = exchanges
+            .iter()
+            .map(|exchange| Name::from_str(exchange).unwrap())
+            .collect();
+
+        assert_eq!(expected, mx_receives_emails(&exchanges));
+    }
+
+    #[tokio::test]
+    async fn cached_verifier_fails_if_the_lookup_takes_too_long() {
+        let verifier = CachedDomainVerifier::new(
+            CountingVerifier::new(Duration::from_secs(180)),
+            Duration::from_millis(50),
+            Duration::from_secs(60),
+            CAPACITY,
+        );
+
+        assert_err!(verifier.verify("domain.yq").await);
+    }
+
+    /// The name server is a local UDP socket that never answers, so no network is needed.
+    #[tokio::test]
+    async fn dns_verifier_fails_if_the_name_server_does_not_answer() {
+        let name_server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let verifier = DnsDomainVerifier::new(
+            &[name_server.local_addr().unwrap()],
+            Duration::from_millis(50),
+        )
+        .unwrap();
+
+        assert_err!(verifier.verify("domain.yq").await);
+    }
+}
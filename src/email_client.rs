@@ -1,8 +1,16 @@
 //! src/email_client.rs
 
 use crate::domain::SubscriberEmail;
+use crate::metrics::Metrics;
+use crate::reload::LiveSettings;
+use crate::request_id::RequestId;
+use crate::telemetry::trace_context_headers;
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
+use std::time::Instant;
+
+/// The label of our email transport in metrics
+const TRANSPORT: &str = "postmark";
 
 /// Our REST email client which talks to an email API provider
 ///
@@ -20,16 +28,23 @@ use secrecy::{ExposeSecret, Secret};
 ///  - `sender: SubscriberEmail` - a valid email address that is registered with
 ///     the email provider and which we use to send emails from;
 ///  - `authorization_token: Secret<String>` - wrapped in `secrecy::Secret`
-///     because we don't want to log this by accident.
+///     because we don't want to log this by accident;
+///  - `timeout: Duration` - how long to wait for the email provider to respond,
+///     unless `live_settings: Option<LiveSettings>` is set and says otherwise;
+///  - `metrics: Metrics` - where we count sent emails and measure how long sending takes.
 ///
 /// Create an instance of an `EmailClient` through the `new` function,
 /// and then send emails through the instance's `send_email` method.
+#[allow(clippy::doc_overindented_list_items)]
 #[derive(Clone, Debug)]
 pub struct EmailClient {
     http_client: Client,
     base_url: String,
     sender: SubscriberEmail,
     authorization_token: Secret<String>,
+    timeout: std::time::Duration,
+    live_settings: Option<LiveSettings>,
+    metrics: Metrics,
 }
 
 impl EmailClient {
@@ -42,15 +57,18 @@ impl EmailClient {
     ///  - `sender: SubscriberEmail` - a valid email address that is registered with
     ///     the email provider and which we use to send emails from;
     ///  - `authorization_token: Secret<String>` - wrapped in `secrecy::Secret`
-    ///     because we don't want to log this by accident.
+    ///     because we don't want to log this by accident;
+    ///  - `timeout: Duration` - how long to wait for the email provider to respond;
+    ///  - `metrics: Metrics` - where we count sent emails.
+    #[allow(clippy::doc_overindented_list_items)]
     pub fn new(
         base_url: String,
         sender: SubscriberEmail,
         authorization_token: Secret<String>,
         timeout: std::time::Duration,
+        metrics: Metrics,
     ) -> Self {
         let http_client = Client::builder()
-            .timeout(timeout)
             .build()
             .expect("Failed to build an HTTP client.");
         Self {
@@ -58,6 +76,24 @@ impl EmailClient {
             base_url,
             sender,
             authorization_token,
+            timeout,
+            live_settings: None,
+            metrics,
+        }
+    }
+
+    /// Takes the timeout from `live_settings` instead, so that it changes
+    /// when the configuration is reloaded
+    pub fn with_live_settings(mut self, live_settings: LiveSettings) -> Self {
+        self.live_settings = Some(live_settings);
+        self
+    }
+
+    /// How long to wait for the email provider to respond, as currently configured
+    fn timeout(&self) -> std::time::Duration {
+        match &self.live_settings {
+            Some(live_settings) => live_settings.current().email_client_timeout,
+            None => self.timeout,
         }
     }
 
@@ -75,17 +111,40 @@ impl EmailClient {
             subject,
             html_body,
             text_body,
+            metadata: RequestId::current().map(|request_id| EmailMetadata {
+                request_id: request_id.to_string(),
+            }),
         };
-        self.http_client
+        let start = Instant::now();
+        let outcome = self
+            .http_client
             .post(&url)
+            .timeout(self.timeout())
+            .headers(trace_context_headers())
             .header(
                 "X-Postmark-Server-Token",
                 self.authorization_token.expose_secret(),
             )
             .json(&request_body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await
+            .and_then(|response| response.error_for_status());
+        self.metrics
+            .observe_email_sent(TRANSPORT, outcome.is_ok(), start.elapsed());
+
+        outcome.map(|_| ())
+    }
+
+    /// Checks that the email provider can be reached, without sending an email
+    ///
+    /// Any HTTP response counts, even an error status, because it means that the provider is up.
+    pub async fn probe(&self) -> Result<(), reqwest::Error> {
+        self.http_client
+            .head(&self.base_url)
+            .timeout(self.timeout())
+            .headers(trace_context_headers())
+            .send()
+            .await?;
 
         Ok(())
     }
@@ -99,6 +158,15 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<EmailMetadata>,
+}
+
+/// Custom data which the email provider keeps with the email,
+/// so that we can trace an email back to the request that sent it
+#[derive(serde::Serialize)]
+struct EmailMetadata {
+    request_id: String,
 }
 
 #[cfg(test)]
@@ -106,14 +174,21 @@ mod tests {
     use super::EmailClient;
 
     use crate::domain::SubscriberEmail;
+    use crate::metrics::Metrics;
+    use crate::reload::{LiveSettings, ReloadableSettings};
+    use crate::request_id::RequestId;
+    use crate::telemetry::{get_subscriber, get_tracer_provider, LogFormat};
 
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
     use rstest::{fixture, rstest};
     use secrecy::Secret;
-    use wiremock::matchers::{any, header, header_exists, method, path};
+    use tracing::Instrument;
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
     use wiremock::{Match, Mock, MockServer, Request, ResponseTemplate};
 
     struct SendEmailBodyMatcher;
@@ -184,6 +259,7 @@ mod tests {
             sender,
             Secret::new(Faker.fake()),
             std::time::Duration::from_millis(200),
+            Metrics::new(),
         );
 
         Arrange {
@@ -316,4 +392,187 @@ mod tests {
         // Assert
         assert_err!(outcome);
     }
+
+    #[rstest]
+    #[tokio::test]
+    async fn probe_succeeds_if_the_server_responds_with_any_status(
+        #[future] arrange: Arrange<'static>,
+    ) {
+        // Arrange
+        let arrange = arrange.await;
+
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .named("HEAD; 404")
+            .mount(&arrange.mock_server)
+            .await;
+
+        // Act
+        let outcome = arrange.email_client.probe().await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn probe_fails_if_the_server_takes_too_long(#[future] arrange: Arrange<'static>) {
+        // Arrange
+        let arrange = arrange.await;
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .named("Any matcher; timeout")
+            .mount(&arrange.mock_server)
+            .await;
+
+        // Act
+        let outcome = arrange.email_client.probe().await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn the_live_timeout_overrides_the_initial_one(#[future] arrange: Arrange<'static>) {
+        // Arrange
+        let arrange = arrange.await;
+        let settings = crate::configuration::local_test_settings();
+        let mut reloadable = ReloadableSettings::from(&settings);
+        reloadable.email_client_timeout = std::time::Duration::from_secs(10);
+        let email_client = arrange
+            .email_client
+            .with_live_settings(LiveSettings::new(reloadable));
+
+        // Slower than the initial timeout of 200 ms, but faster than the live one
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_millis(500));
+        Mock::given(method("HEAD"))
+            .respond_with(response)
+            .expect(1)
+            .named("HEAD; slow")
+            .mount(&arrange.mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.probe().await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_counts_the_outcome() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let metrics = Metrics::new();
+        let email_client = EmailClient::new(
+            mock_server.uri(),
+            SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            metrics.clone(),
+        );
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .named("Any matcher; 500")
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(subscriber_email, "Subject", "Content", "Content")
+            .await;
+
+        // Assert
+        assert!(metrics
+            .render()
+            .contains(r#"zero2prod_emails_sent_total{outcome="failure",transport="postmark"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn send_email_propagates_the_trace_context() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = EmailClient::new(
+            mock_server.uri(),
+            SubscriberEmail::parse(SafeEmail().fake()).unwrap(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            Metrics::new(),
+        );
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake()).unwrap();
+        let tracer_provider =
+            get_tracer_provider("test", None, std::time::Duration::from_secs(1)).unwrap();
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let (subscriber, _) = get_subscriber(
+            "test",
+            "info",
+            LogFormat::Bunyan,
+            std::io::sink,
+            tracer_provider.tracer("test"),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        Mock::given(header_exists("traceparent"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .named("traceparent is set")
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(subscriber_email, "Subject", "Content", "Content")
+            .instrument(tracing::info_span!("Sending an email"))
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn emails_sent_by_background_jobs_carry_the_request_id(
+        #[future] arrange: Arrange<'static>,
+    ) {
+        // Arrange
+        let arrange = arrange.await;
+
+        let mock_server = arrange.mock_server;
+        let email_client = arrange.email_client;
+        let subscriber_email = arrange.email_fields.subscriber_email.clone();
+        let request_id = RequestId::parse("req-1").unwrap();
+
+        Mock::given(body_partial_json(
+            serde_json::json!({"Metadata": {"request_id": "req-1"}}),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .named("The request id is in the metadata")
+        .mount(&mock_server)
+        .await;
+
+        // Act
+        let outcome = request_id
+            .scope(async move {
+                crate::request_id::spawn(async move {
+                    email_client
+                        .send_email(subscriber_email, "Subject", "Content", "Content")
+                        .await
+                })
+                .await
+                .unwrap()
+            })
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
 }
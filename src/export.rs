@@ -0,0 +1,345 @@
+//! src/export.rs
+//!
+//! Exporting the subscribers, to back up the list or to load it into other tools
+//!
+//! The subscribers are encoded one at a time, as they are read from the storage,
+//! so a large list is exported in as little memory as a small one.
+//! The CSV export has the columns that `crate::import` reads, so it can be imported back.
+//! Its fields which a spreadsheet would run as a formula are escaped, since they come from
+//! the signup form, and the import takes the escaping off again.
+
+use crate::domain::SubscriptionStatus;
+use crate::repository::{RepositoryError, Subscriber};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::stream::{self, Stream, StreamExt};
+use std::borrow::Cow;
+use uuid::Uuid;
+
+/// The header row of the CSV export
+const CSV_COLUMNS: [&str; 7] = [
+    "id",
+    "email",
+    "name",
+    "status",
+    "subscribed_at",
+    "tags",
+    "review_reason",
+];
+
+/// The format of an export
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// A header row, then a row per subscriber, with the tags separated by `;`
+    #[default]
+    Csv,
+    /// A JSON object per subscriber, one per line
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    fn header(&self) -> Option<Result<Vec<u8>, ExportError>> {
+        match self {
+            ExportFormat::Csv => Some(csv_record(CSV_COLUMNS)),
+            ExportFormat::Ndjson => None,
+        }
+    }
+
+    fn encode(&self, subscriber: &Subscriber) -> Result<Vec<u8>, ExportError> {
+        match self {
+            ExportFormat::Csv => csv_record(
+                [
+                    subscriber.id.to_string().as_str(),
+                    &subscriber.email,
+                    &subscriber.name,
+                    subscriber.status.as_str(),
+                    &subscriber.subscribed_at.to_rfc3339(),
+                    &subscriber.tags.join(";"),
+                    subscriber.review_reason.as_deref().unwrap_or_default(),
+                ]
+                .map(escape_formula)
+                .iter()
+                .map(|field| field.as_ref()),
+            ),
+            ExportFormat::Ndjson => {
+                let mut line = serde_json::to_vec(&ExportedSubscriber::from(subscriber))?;
+                line.push(b'\n');
+                Ok(line)
+            }
+        }
+    }
+}
+
+fn csv_record<'a>(fields: impl IntoIterator<Item = &'a str>) -> Result<Vec<u8>, ExportError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    writer
+        .into_inner()
+        .map_err(|e| ExportError::Csv(e.into_error().into()))
+}
+
+/// The characters that make a spreadsheet read a field as a formula, when it starts with one
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Prefixes `field` with `'` if it starts like a formula, so that spreadsheets show it as text
+fn escape_formula(field: &str) -> Cow<'_, str> {
+    if field.starts_with(FORMULA_PREFIXES) {
+        Cow::Owned(format!("'{}", field))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Takes off the `'` that [`escape_formula`] put in front of `field`
+pub fn unescape_formula(field: &str) -> &str {
+    match field.strip_prefix('\'') {
+        Some(escaped) if escaped.starts_with(FORMULA_PREFIXES) => escaped,
+        _ => field,
+    }
+}
+
+/// A subscriber, as a line of the NDJSON export
+#[derive(serde::Serialize)]
+struct ExportedSubscriber<'a> {
+    id: Uuid,
+    email: &'a str,
+    name: &'a str,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+    tags: &'a [String],
+    review_reason: Option<&'a str>,
+}
+
+impl<'a> From<&'a Subscriber> for ExportedSubscriber<'a> {
+    fn from(subscriber: &'a Subscriber) -> Self {
+        Self {
+            id: subscriber.id,
+            email: &subscriber.email,
+            name: &subscriber.name,
+            status: subscriber.status,
+            subscribed_at: subscriber.subscribed_at,
+            tags: &subscriber.tags,
+            review_reason: subscriber.review_reason.as_deref(),
+        }
+    }
+}
+
+/// Errors which interrupt an export
+#[derive(Debug)]
+pub enum ExportError {
+    Repository(RepositoryError),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+    /// The export ran out of time, for example, because the client reads it too slowly.
+    TimedOut,
+    /// The export was stopped before the end, for example, because the app is shutting down.
+    Aborted,
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Repository(e) => write!(f, "Failed to read the subscribers: {}", e),
+            ExportError::Csv(e) => write!(f, "Failed to encode a subscriber as CSV: {}", e),
+            ExportError::Json(e) => write!(f, "Failed to encode a subscriber as JSON: {}", e),
+            ExportError::TimedOut => write!(f, "The export took too long."),
+            ExportError::Aborted => write!(f, "The export was aborted."),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Repository(e) => Some(e),
+            ExportError::Csv(e) => Some(e),
+            ExportError::Json(e) => Some(e),
+            ExportError::TimedOut | ExportError::Aborted => None,
+        }
+    }
+}
+
+impl From<RepositoryError> for ExportError {
+    fn from(e: RepositoryError) -> Self {
+        Self::Repository(e)
+    }
+}
+
+impl From<csv::Error> for ExportError {
+    fn from(e: csv::Error) -> Self {
+        Self::Csv(e)
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// Encodes `subscribers` in `format`, as a chunk per subscriber, after the header, if any
+pub fn encode(
+    subscribers: impl Stream<Item = Result<Subscriber, RepositoryError>>,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Vec<u8>, ExportError>> {
+    stream::iter(format.header())
+        .chain(subscribers.map(move |subscriber| format.encode(&subscriber?)))
+}
+
+/// Parses a date, such as `2024-01-31`, as its midnight UTC, or an RFC 3339 timestamp
+pub fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        })
+        .map_err(|_| {
+            format!(
+                r#""{}" is neither a date nor an RFC 3339 timestamp."#,
+                value
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claims::assert_err;
+    use futures_util::TryStreamExt;
+    use rstest::rstest;
+
+    fn subscriber(tags: &[&str]) -> Subscriber {
+        Subscriber {
+            id: Uuid::nil(),
+            email: String::from("ursula_le_guin@gmail.com"),
+            name: String::from("le guin, ursula"),
+            status: SubscriptionStatus::Confirmed,
+            subscribed_at: "2024-01-31T12:00:00Z".parse().unwrap(),
+            review_reason: None,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    async fn export(subscribers: Vec<Subscriber>, format: ExportFormat) -> String {
+        let chunks: Vec<Vec<u8>> = encode(stream::iter(subscribers.into_iter().map(Ok)), format)
+            .try_collect()
+            .await
+            .unwrap();
+        String::from_utf8(chunks.concat()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn csv_export_has_a_header_and_a_row_per_subscriber() {
+        let csv = export(
+            vec![subscriber(&["legacy", "weekly"]), subscriber(&[])],
+            ExportFormat::Csv,
+        )
+        .await;
+
+        assert_eq!(
+            "id,email,name,status,subscribed_at,tags,review_reason\n\
+             00000000-0000-0000-0000-000000000000,ursula_le_guin@gmail.com,\"le guin, ursula\",confirmed,2024-01-31T12:00:00+00:00,legacy;weekly,\n\
+             00000000-0000-0000-0000-000000000000,ursula_le_guin@gmail.com,\"le guin, ursula\",confirmed,2024-01-31T12:00:00+00:00,,\n",
+            csv
+        );
+    }
+
+    #[rstest(
+        name,
+        expected,
+        case::equals("=1+1", "'=1+1"),
+        case::plus("+1 555", "'+1 555"),
+        case::minus("-2+3", "'-2+3"),
+        case::at("@SUM(A1)", "'@SUM(A1)"),
+        case::tab("\t=1+1", "'\t=1+1"),
+        case::inside("ursula=le guin", "ursula=le guin")
+    )]
+    #[tokio::test]
+    async fn csv_export_escapes_formulas(name: &str, expected: &str) {
+        let mut subscriber = subscriber(&["-tag"]);
+        subscriber.name = name.to_string();
+
+        let csv = export(vec![subscriber], ExportFormat::Csv).await;
+
+        let row = csv.lines().nth(1).unwrap();
+        assert!(row.contains(&format!(",{},", expected)), "{}", row);
+        assert!(row.contains(",'-tag,"), "{}", row);
+    }
+
+    #[rstest(
+        field,
+        case::minus("-foo"),
+        case::carriage_return("\r=1+1"),
+        case::quoted("'quoted'"),
+        case::plain("ursula")
+    )]
+    fn escaped_fields_are_unescaped(field: &str) {
+        assert_eq!(field, unescape_formula(&escape_formula(field)));
+    }
+
+    #[tokio::test]
+    async fn ndjson_export_has_a_line_per_subscriber() {
+        let ndjson = export(
+            vec![subscriber(&["weekly"]), subscriber(&[])],
+            ExportFormat::Ndjson,
+        )
+        .await;
+
+        let lines: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!("confirmed", lines[0]["status"]);
+        assert_eq!("2024-01-31T12:00:00Z", lines[0]["subscribed_at"]);
+        assert_eq!(serde_json::json!(["weekly"]), lines[0]["tags"]);
+        assert_eq!(serde_json::json!([]), lines[1]["tags"]);
+    }
+
+    #[tokio::test]
+    async fn storage_errors_end_the_export() {
+        let subscribers = stream::iter(vec![
+            Ok(subscriber(&[])),
+            Err(RepositoryError::Unexpected("connection reset".into())),
+        ]);
+
+        let chunks: Vec<_> = encode(subscribers, ExportFormat::Ndjson).collect().await;
+
+        assert_eq!(2, chunks.len());
+        assert!(matches!(chunks[1], Err(ExportError::Repository(_))));
+    }
+
+    #[rstest(
+        value,
+        expected,
+        case::date("2024-01-31", "2024-01-31T00:00:00Z"),
+        case::timestamp("2024-01-31T12:30:00+02:00", "2024-01-31T10:30:00Z")
+    )]
+    fn timestamps_are_parsed(value: &str, expected: &str) {
+        assert_eq!(
+            Ok(expected.parse::<DateTime<Utc>>().unwrap()),
+            parse_timestamp(value)
+        );
+    }
+
+    #[rstest(value, case::american_date("01/31/2024"), case::empty(""))]
+    fn invalid_timestamps_are_rejected(value: &str) {
+        assert_err!(parse_timestamp(value));
+    }
+}
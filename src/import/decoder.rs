@@ -0,0 +1,138 @@
+//! src/import/decoder.rs
+
+use csv::ByteRecord;
+use csv_core::ReadRecordResult;
+
+/// Splits CSV which arrives in chunks, such as a request body, into records
+///
+/// A record can span chunks, and a chunk can end in the middle of a quoted field,
+/// so only the record in progress is buffered, rather than the whole input.
+pub struct CsvDecoder {
+    reader: csv_core::Reader,
+    /// The fields of the record in progress, one after the other
+    output: Vec<u8>,
+    output_len: usize,
+    /// Where each field of the record in progress ends in `output`
+    ends: Vec<usize>,
+    ends_len: usize,
+}
+
+impl Default for CsvDecoder {
+    fn default() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            output: vec![0; 1024],
+            output_len: 0,
+            ends: vec![0; 16],
+            ends_len: 0,
+        }
+    }
+}
+
+impl CsvDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes `chunk`, and returns the records that it completes
+    pub fn decode(&mut self, chunk: &[u8]) -> Vec<ByteRecord> {
+        // An empty input means the end of the input to the reader, which it isn't here
+        if chunk.is_empty() {
+            return Vec::new();
+        }
+        self.read(chunk)
+    }
+
+    /// Returns the last record, if the input doesn't end with a line break
+    pub fn finish(&mut self) -> Vec<ByteRecord> {
+        self.read(&[])
+    }
+
+    fn read(&mut self, mut input: &[u8]) -> Vec<ByteRecord> {
+        let mut records = Vec::new();
+        loop {
+            let (result, read, written, ended) = self.reader.read_record(
+                input,
+                &mut self.output[self.output_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[read..];
+            self.output_len += written;
+            self.ends_len += ended;
+            match result {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => return records,
+                ReadRecordResult::OutputFull => self.output.resize(self.output.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => records.push(self.take_record()),
+            }
+        }
+    }
+
+    fn take_record(&mut self) -> ByteRecord {
+        let mut record = ByteRecord::new();
+        let mut start = 0;
+        for &end in &self.ends[..self.ends_len] {
+            record.push_field(&self.output[start..end]);
+            start = end;
+        }
+        self.output_len = 0;
+        self.ends_len = 0;
+
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    fn decode_in_chunks(input: &str, chunk_size: usize) -> Vec<Vec<String>> {
+        let mut decoder = CsvDecoder::new();
+        let mut records: Vec<ByteRecord> = input
+            .as_bytes()
+            .chunks(chunk_size)
+            .flat_map(|chunk| decoder.decode(chunk))
+            .collect();
+        records.extend(decoder.finish());
+
+        records
+            .iter()
+            .map(|record| {
+                record
+                    .iter()
+                    .map(|field| String::from_utf8(field.to_vec()).unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn records_are_the_same_however_the_input_is_chunked(
+        #[values(1, 2, 7, 4096)] chunk_size: usize,
+    ) {
+        let input = "email,name\r\nursula@example.com,\"le guin, ursula\"\n\"a\"\"b@example.com\",\"two\nlines\"";
+
+        let records = decode_in_chunks(input, chunk_size);
+
+        assert_eq!(
+            vec![
+                vec!["email", "name"],
+                vec!["ursula@example.com", "le guin, ursula"],
+                vec!["a\"b@example.com", "two\nlines"],
+            ],
+            records
+        );
+    }
+
+    #[test]
+    fn long_records_grow_the_buffers() {
+        let fields: Vec<String> = (0..100).map(|i| "x".repeat(i * 10)).collect();
+        let input = format!("{}\n", fields.join(","));
+
+        let records = decode_in_chunks(&input, 100);
+
+        assert_eq!(vec![fields], records);
+    }
+}
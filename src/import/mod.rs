@@ -0,0 +1,508 @@
+//! src/import/mod.rs
+//!
+//! Bulk import of the subscribers of another platform, out of a CSV file.
+//!
+//! The file has a header row, which names the `email` and `name` columns, and optionally
+//! the `tags` column, with tags separated by `;`, and the `subscribed_at` column, with
+//! RFC 3339 timestamps or `YYYY-MM-DD` dates. Other columns are ignored.
+
+mod decoder;
+mod report;
+
+pub use decoder::CsvDecoder;
+pub use report::{ImportReport, ReportRow, RowOutcome};
+
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus};
+use crate::export::{parse_timestamp, unescape_formula};
+use crate::repository::{ImportedSubscriber, RepositoryError, SubscriberRepository};
+use chrono::{DateTime, Utc};
+use csv::ByteRecord;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// How many subscribers are stored at once
+pub const BATCH_SIZE: usize = 500;
+
+/// Longer tags are rejected
+pub const MAX_TAG_LENGTH: usize = 64;
+
+/// How to import the subscribers
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+pub struct ImportOptions {
+    /// Mark the subscribers as confirmed, because they already confirmed on the other platform;
+    /// otherwise they are pending confirmation, like those who sign up
+    #[serde(default)]
+    pub confirmed: bool,
+    /// Queue a confirmation email for each of the subscribers, who are pending confirmation,
+    /// so that they confirm again on this platform; it can't be combined with `confirmed`
+    #[serde(default)]
+    pub reconfirm: bool,
+}
+
+impl ImportOptions {
+    /// Checks that the options can be combined
+    pub fn validate(&self) -> Result<(), String> {
+        match self.confirmed && self.reconfirm {
+            true => Err(String::from(
+                "Subscribers can't be both confirmed and sent a confirmation email.",
+            )),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Errors which stop an import, unlike invalid rows, which are only reported
+#[derive(Debug)]
+pub enum ImportError {
+    /// The header row doesn't name these required columns
+    MissingColumns(Vec<&'static str>),
+    Repository(RepositoryError),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::MissingColumns(columns) => write!(
+                f,
+                "The header row doesn't name the required columns: {}.",
+                columns.join(", ")
+            ),
+            ImportError::Repository(e) => write!(f, "Failed to store the subscribers: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImportError::MissingColumns(_) => None,
+            ImportError::Repository(e) => Some(e),
+        }
+    }
+}
+
+impl From<RepositoryError> for ImportError {
+    fn from(e: RepositoryError) -> Self {
+        ImportError::Repository(e)
+    }
+}
+
+/// Where the columns are, as named by the header row
+struct Columns {
+    email: usize,
+    name: usize,
+    tags: Option<usize>,
+    subscribed_at: Option<usize>,
+}
+
+impl Columns {
+    fn parse(header: &ByteRecord) -> Result<Self, ImportError> {
+        let position = |name: &str| {
+            header.iter().position(|column| {
+                String::from_utf8_lossy(column)
+                    .trim()
+                    .eq_ignore_ascii_case(name)
+            })
+        };
+        match (position("email"), position("name")) {
+            (Some(email), Some(name)) => Ok(Self {
+                email,
+                name,
+                tags: position("tags"),
+                subscribed_at: position("subscribed_at"),
+            }),
+            (email, name) => Err(ImportError::MissingColumns(
+                [("email", email), ("name", name)]
+                    .into_iter()
+                    .filter(|(_, position)| position.is_none())
+                    .map(|(column, _)| column)
+                    .collect(),
+            )),
+        }
+    }
+}
+
+/// An import in progress
+///
+/// The CSV is fed to it chunk by chunk, as it arrives, and the valid rows are stored
+/// in batches of `BATCH_SIZE`, so that large files don't have to be held in memory.
+/// Each batch is stored as a whole, so if the storage fails, the batches before it are stored,
+/// and the rows after it aren't.
+pub struct SubscriberImport {
+    repository: Arc<dyn SubscriberRepository>,
+    status: SubscriptionStatus,
+    reconfirm: bool,
+    decoder: CsvDecoder,
+    columns: Option<Columns>,
+    /// The number of the last row, counting the header as row 1, and skipping blank lines
+    row: usize,
+    /// The email addresses on the rows so far, to tell the duplicates within the file
+    seen: HashSet<String>,
+    /// The valid rows which aren't stored yet, with their row numbers
+    batch: Vec<(usize, ImportedSubscriber)>,
+    report: ImportReport,
+}
+
+impl SubscriberImport {
+    ///
+    /// `options` are expected to be valid; `reconfirm` wins over `confirmed` otherwise.
+    pub fn new(repository: Arc<dyn SubscriberRepository>, options: ImportOptions) -> Self {
+        let status = match options.confirmed && !options.reconfirm {
+            true => SubscriptionStatus::Confirmed,
+            false => SubscriptionStatus::PendingConfirmation,
+        };
+        Self {
+            repository,
+            status,
+            reconfirm: options.reconfirm,
+            decoder: CsvDecoder::new(),
+            columns: None,
+            row: 0,
+            seen: HashSet::new(),
+            batch: Vec::with_capacity(BATCH_SIZE),
+            report: ImportReport::default(),
+        }
+    }
+
+    /// Imports the rows which `chunk` completes
+    pub async fn feed(&mut self, chunk: &[u8]) -> Result<(), ImportError> {
+        for record in self.decoder.decode(chunk) {
+            self.add(record).await?;
+        }
+        Ok(())
+    }
+
+    /// Imports the last rows, and returns the report
+    pub async fn finish(mut self) -> Result<ImportReport, ImportError> {
+        for record in self.decoder.finish() {
+            self.add(record).await?;
+        }
+        if self.columns.is_none() {
+            return Err(ImportError::MissingColumns(vec!["email", "name"]));
+        }
+        self.store_batch().await?;
+        self.report.rows.sort_by_key(|row| row.row);
+
+        Ok(self.report)
+    }
+
+    async fn add(&mut self, record: ByteRecord) -> Result<(), ImportError> {
+        self.row += 1;
+        let Some(columns) = &self.columns else {
+            self.columns = Some(Columns::parse(&record)?);
+            return Ok(());
+        };
+        // Blank lines, such as the one that a file may end with, aren't rows
+        if record.iter().all(|field| field.is_empty()) {
+            return Ok(());
+        }
+
+        let field = |position: Option<usize>| {
+            let value = position
+                .and_then(|position| record.get(position))
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default();
+            unescape_formula(&value).trim().to_owned()
+        };
+        let email = field(Some(columns.email));
+        let parsed = parse_row(
+            email.clone(),
+            field(Some(columns.name)),
+            &field(columns.tags),
+            &field(columns.subscribed_at),
+            self.status,
+            Utc::now(),
+        );
+        let subscriber = match parsed {
+            Ok(subscriber) => ImportedSubscriber {
+                reconfirm: self.reconfirm,
+                ..subscriber
+            },
+            Err(reason) => {
+                self.report(email, RowOutcome::Rejected(reason));
+                return Ok(());
+            }
+        };
+        if !self
+            .seen
+            .insert(subscriber.new_subscriber.email.as_ref().to_owned())
+        {
+            self.report(email, RowOutcome::Duplicate);
+            return Ok(());
+        }
+
+        self.batch.push((self.row, subscriber));
+        if self.batch.len() >= BATCH_SIZE {
+            self.store_batch().await?;
+        }
+        Ok(())
+    }
+
+    async fn store_batch(&mut self) -> Result<(), ImportError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let (rows, subscribers): (Vec<usize>, Vec<ImportedSubscriber>) =
+            self.batch.drain(..).unzip();
+        let stored = self.repository.import(&subscribers).await?;
+        for ((row, subscriber), is_stored) in rows.into_iter().zip(subscribers).zip(stored) {
+            self.report.rows.push(ReportRow {
+                row,
+                email: subscriber.new_subscriber.email.as_ref().to_owned(),
+                outcome: match is_stored {
+                    true => RowOutcome::Accepted,
+                    false => RowOutcome::Duplicate,
+                },
+            });
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, email: String, outcome: RowOutcome) {
+        self.report.rows.push(ReportRow {
+            row: self.row,
+            email,
+            outcome,
+        });
+    }
+}
+
+/// Validates a row, like the signup form is validated, and returns the reason if it is invalid
+fn parse_row(
+    email: String,
+    name: String,
+    tags: &str,
+    subscribed_at: &str,
+    status: SubscriptionStatus,
+    now: DateTime<Utc>,
+) -> Result<ImportedSubscriber, String> {
+    Ok(ImportedSubscriber {
+        new_subscriber: NewSubscriber {
+            email: SubscriberEmail::parse(email)?,
+            name: SubscriberName::parse(name)?,
+        },
+        status,
+        subscribed_at: parse_subscribed_at(subscribed_at, now)?,
+        tags: parse_tags(tags)?,
+        reconfirm: false,
+    })
+}
+
+/// Parses the `;`-separated tags, sorted and without duplicates
+fn parse_tags(tags: &str) -> Result<Vec<String>, String> {
+    let mut parsed = Vec::new();
+    for tag in tags.split(';').map(str::trim).filter(|tag| !tag.is_empty()) {
+        if tag.chars().count() > MAX_TAG_LENGTH || tag.chars().any(char::is_control) {
+            return Err(format!(r#""{}" is not a valid tag."#, tag));
+        }
+        parsed.push(tag.to_owned());
+    }
+    parsed.sort();
+    parsed.dedup();
+
+    Ok(parsed)
+}
+
+/// Parses an RFC 3339 timestamp, or a date, which stands for midnight UTC,
+/// and defaults to `now` if it is empty
+fn parse_subscribed_at(subscribed_at: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if subscribed_at.is_empty() {
+        return Ok(now);
+    }
+    let parsed = parse_timestamp(subscribed_at)
+        .map_err(|_| format!(r#""{}" is not a valid subscription date."#, subscribed_at))?;
+    if parsed > now {
+        return Err(format!(
+            r#""{}" is a subscription date in the future."#,
+            subscribed_at
+        ));
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::repository::InMemorySubscriberRepository;
+    use claims::{assert_err, assert_matches, assert_ok_eq};
+    use rstest::rstest;
+
+    fn now() -> DateTime<Utc> {
+        "2024-01-01T00:00:00Z".parse().unwrap()
+    }
+
+    async fn import(
+        csv: &str,
+        options: ImportOptions,
+    ) -> (ImportReport, Arc<dyn SubscriberRepository>) {
+        let repository: Arc<dyn SubscriberRepository> =
+            Arc::new(InMemorySubscriberRepository::new());
+        let mut import = SubscriberImport::new(repository.clone(), options);
+        // Split the rows across chunks, as a request body would
+        for chunk in csv.as_bytes().chunks(10) {
+            import.feed(chunk).await.unwrap();
+        }
+        (import.finish().await.unwrap(), repository)
+    }
+
+    #[tokio::test]
+    async fn every_row_is_reported_in_the_order_of_the_file() {
+        let csv = "Name,Email,Tags\n\
+                   le guin,ursula_le_guin@gmail.com,weekly;legacy\n\
+                   ,nameless@gmail.com,\n\
+                   \n\
+                   ursula,ursula_le_guin@gmail.com,\n\
+                   tolkien,not-an-email,";
+
+        let (report, repository) = import(csv, ImportOptions::default()).await;
+
+        let outcomes: Vec<_> = report
+            .rows
+            .iter()
+            .map(|row| (row.row, row.email.as_str(), row.outcome.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (2, "ursula_le_guin@gmail.com", "accepted"),
+                (3, "nameless@gmail.com", "rejected"),
+                (4, "ursula_le_guin@gmail.com", "duplicate"),
+                (5, "not-an-email", "rejected"),
+            ],
+            outcomes
+        );
+        let stored = repository
+            .find_by_email("ursula_le_guin@gmail.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("le guin", stored.name);
+        assert_eq!(SubscriptionStatus::PendingConfirmation, stored.status);
+        assert_eq!(vec!["legacy", "weekly"], stored.tags);
+    }
+
+    #[tokio::test]
+    async fn subscribers_can_be_imported_as_confirmed() {
+        let csv = "email,name\nursula_le_guin@gmail.com,le guin\n";
+
+        let options = ImportOptions {
+            confirmed: true,
+            ..ImportOptions::default()
+        };
+
+        let (report, repository) = import(csv, options).await;
+
+        assert_eq!(1, report.accepted());
+        let stored = repository.list(Some(SubscriptionStatus::Confirmed)).await;
+        assert_eq!(1, stored.unwrap().len());
+        assert!(repository.confirmation_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn subscribers_can_be_queued_for_a_confirmation_email() {
+        let csv = "email,name
+\
+                   ursula_le_guin@gmail.com,le guin
+\
+                   ursula_le_guin@gmail.com,ursula
+";
+        let options = ImportOptions {
+            reconfirm: true,
+            ..ImportOptions::default()
+        };
+
+        let (report, repository) = import(csv, options).await;
+
+        assert_eq!((1, 1), (report.accepted(), report.duplicates()));
+        let stored = repository
+            .find_by_email("ursula_le_guin@gmail.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(SubscriptionStatus::PendingConfirmation, stored.status);
+        assert_eq!(
+            vec![stored.id],
+            repository.confirmation_requests().await.unwrap()
+        );
+    }
+
+    #[test]
+    fn subscribers_cannot_be_both_confirmed_and_reconfirmed() {
+        let options = ImportOptions {
+            confirmed: true,
+            reconfirm: true,
+        };
+
+        assert_err!(options.validate());
+    }
+
+    #[tokio::test]
+    async fn stored_email_addresses_are_duplicates() {
+        let repository: Arc<dyn SubscriberRepository> =
+            Arc::new(InMemorySubscriberRepository::new());
+        let csv = "email,name\nursula_le_guin@gmail.com,le guin\n";
+        for _ in 0..2 {
+            let mut import = SubscriberImport::new(repository.clone(), ImportOptions::default());
+            import.feed(csv.as_bytes()).await.unwrap();
+            import.finish().await.unwrap();
+        }
+        let mut import = SubscriberImport::new(repository.clone(), ImportOptions::default());
+        import.feed(csv.as_bytes()).await.unwrap();
+
+        let report = import.finish().await.unwrap();
+
+        assert_eq!((0, 1), (report.accepted(), report.duplicates()));
+    }
+
+    #[tokio::test]
+    async fn the_header_must_name_the_email_and_name_columns() {
+        let mut import = SubscriberImport::new(
+            Arc::new(InMemorySubscriberRepository::new()),
+            ImportOptions::default(),
+        );
+        import
+            .feed(b"e-mail,name\nursula_le_guin@gmail.com,le guin\n")
+            .await
+            .unwrap_err();
+        let empty = SubscriberImport::new(
+            Arc::new(InMemorySubscriberRepository::new()),
+            ImportOptions::default(),
+        );
+
+        assert_matches!(
+            empty.finish().await,
+            Err(ImportError::MissingColumns(columns)) if columns == vec!["email", "name"]
+        );
+    }
+
+    #[test]
+    fn tags_are_trimmed_sorted_and_deduplicated() {
+        assert_ok_eq!(
+            parse_tags(" weekly ;legacy;; weekly"),
+            vec![String::from("legacy"), String::from("weekly")]
+        );
+        assert_ok_eq!(parse_tags(""), Vec::<String>::new());
+        assert_err!(parse_tags(&"x".repeat(MAX_TAG_LENGTH + 1)));
+        assert_err!(parse_tags("new\u{7}line"));
+    }
+
+    #[rstest]
+    #[case::timestamp("2019-05-01T12:30:00+02:00", "2019-05-01T10:30:00Z")]
+    #[case::date("2019-05-01", "2019-05-01T00:00:00Z")]
+    #[case::missing("", "2024-01-01T00:00:00Z")]
+    fn subscription_dates_are_parsed(#[case] subscribed_at: &str, #[case] expected: &str) {
+        assert_ok_eq!(
+            parse_subscribed_at(subscribed_at, now()),
+            expected.parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case::not_a_date("last spring")]
+    #[case::american_date("05/01/2019")]
+    #[case::future("2030-01-01")]
+    fn invalid_subscription_dates_are_rejected(#[case] subscribed_at: &str) {
+        assert_err!(parse_subscribed_at(subscribed_at, now()));
+    }
+}
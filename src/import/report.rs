@@ -0,0 +1,116 @@
+//! src/import/report.rs
+
+/// What became of a row of the import
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RowOutcome {
+    /// The subscriber is stored
+    Accepted,
+    /// The email address is already stored, or it is on an earlier row
+    Duplicate,
+    /// The row is invalid, for the given reason
+    Rejected(String),
+}
+
+impl RowOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RowOutcome::Accepted => "accepted",
+            RowOutcome::Duplicate => "duplicate",
+            RowOutcome::Rejected(_) => "rejected",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportRow {
+    /// The number of the row in the file, counting the header as row 1, and skipping blank lines
+    pub row: usize,
+    /// The email address, as it is in the file
+    pub email: String,
+    pub outcome: RowOutcome,
+}
+
+/// The outcome of every row of an import, in the order of the file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub rows: Vec<ReportRow>,
+}
+
+impl ImportReport {
+    pub fn accepted(&self) -> usize {
+        self.count(|outcome| *outcome == RowOutcome::Accepted)
+    }
+
+    pub fn duplicates(&self) -> usize {
+        self.count(|outcome| *outcome == RowOutcome::Duplicate)
+    }
+
+    pub fn rejected(&self) -> usize {
+        self.count(|outcome| matches!(outcome, RowOutcome::Rejected(_)))
+    }
+
+    fn count(&self, predicate: impl Fn(&RowOutcome) -> bool) -> usize {
+        self.rows
+            .iter()
+            .filter(|row| predicate(&row.outcome))
+            .count()
+    }
+
+    /// Writes the report as CSV, with the `row`, `email`, `outcome` and `reason` columns
+    pub fn write_csv(&self, writer: impl std::io::Write) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["row", "email", "outcome", "reason"])?;
+        for row in &self.rows {
+            let reason = match &row.outcome {
+                RowOutcome::Rejected(reason) => reason.as_str(),
+                _ => "",
+            };
+            writer.write_record([
+                row.row.to_string().as_str(),
+                &row.email,
+                row.outcome.as_str(),
+                reason,
+            ])?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_report_is_written_as_csv_with_the_reasons() {
+        let report = ImportReport {
+            rows: vec![
+                ReportRow {
+                    row: 2,
+                    email: String::from("ursula@example.com"),
+                    outcome: RowOutcome::Accepted,
+                },
+                ReportRow {
+                    row: 3,
+                    email: String::from("not-an-email"),
+                    outcome: RowOutcome::Rejected(String::from("Invalid email, \"quoted\"")),
+                },
+            ],
+        };
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+
+        assert_eq!(
+            "row,email,outcome,reason\n\
+             2,ursula@example.com,accepted,\n\
+             3,not-an-email,rejected,\"Invalid email, \"\"quoted\"\"\"\n",
+            String::from_utf8(csv).unwrap()
+        );
+        assert_eq!(
+            (1, 0, 1),
+            (report.accepted(), report.duplicates(), report.rejected())
+        );
+    }
+}
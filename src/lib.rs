@@ -1,9 +1,25 @@
 //! src/lib.rs
 
+pub mod abuse_protection;
+pub mod cli;
 pub mod configuration;
 pub mod consts;
+pub mod database;
 pub mod domain;
+pub mod domain_verifier;
 pub mod email_client;
+pub mod export;
+pub mod import;
+pub mod maintenance;
+pub mod metrics;
+pub mod redaction;
+pub mod reload;
+pub mod repository;
+pub mod request_id;
 pub mod routes;
+pub mod secrets;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
+pub mod tls;
+pub mod users;
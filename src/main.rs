@@ -1,40 +1,87 @@
 //! src/main.rs
 
-use sqlx::postgres::PgPoolOptions;
-use std::net::TcpListener;
+use clap::Parser;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use zero2prod::cli::{self, Cli, Command};
 use zero2prod::configuration::get_configuration;
-use zero2prod::email_client::EmailClient;
-use zero2prod::startup::run;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::redaction;
+use zero2prod::shutdown::shutdown_signal;
+use zero2prod::startup::Application;
+use zero2prod::telemetry::{get_subscriber, init_subscriber, init_tracer_provider};
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
-    let subscriber = get_subscriber("zero2prod", "info", std::io::stdout);
-    init_subscriber(subscriber);
+    // Invalid arguments exit with the usage, and the code 2
+    let command = Cli::parse().into_command();
 
-    let configuration = get_configuration().expect("Failed to read configuration.");
+    let configuration = match get_configuration() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Command::Config { .. } = command {
+        if let Err(e) = cli::run(command, configuration).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    let address = format!(
-        "{}:{}",
-        configuration.application.host, configuration.application.port
-    );
-    let listener = TcpListener::bind(address)?;
-
-    let db_pool = PgPoolOptions::new().connect_lazy_with(configuration.database.with_db());
-
-    let sender_email = configuration
-        .email_client
-        .get_sender()
-        .expect("Invalid sender email address.");
-    let timeout = configuration.email_client.get_timeout();
-    let email_client = EmailClient::new(
-        configuration.email_client.base_url,
-        sender_email,
-        configuration.email_client.authorization_token,
-        timeout,
+    let tracer_provider = configuration
+        .opentelemetry
+        .get_tracer_provider("zero2prod")
+        .expect("Failed to build the tracer provider.");
+    let tracer = init_tracer_provider("zero2prod", tracer_provider);
+    // Only the app logs to stdout; the other commands print their output there
+    let sink = match command {
+        Command::Serve => BoxMakeWriter::new(std::io::stdout),
+        _ => BoxMakeWriter::new(std::io::stderr),
+    };
+    let (subscriber, log_filter_handle) = get_subscriber(
+        "zero2prod",
+        &configuration.logging.level,
+        configuration.logging.format,
+        sink,
+        tracer,
     );
+    init_subscriber(subscriber);
+    configuration.logging.redaction.set_current();
+    if let Some(key) = &configuration.logging.redaction_key {
+        redaction::set_hash_key(key);
+    }
+
+    if !matches!(command, Command::Serve) {
+        let outcome = cli::run(command, configuration).await;
+        tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await?;
+        if let Err(e) = outcome {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let application = match Application::build(configuration, log_filter_handle).await {
+        Ok(application) => application,
+        Err(e) => {
+            tracing::error!("{}", e);
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let shutdown = application.shutdown();
+    tokio::spawn(async move {
+        match shutdown_signal().await {
+            Ok(()) => shutdown.trigger(),
+            Err(e) => tracing::error!("Failed to listen for the shutdown signals: {}", e),
+        }
+    });
+    application.run_until_stopped().await?;
 
-    run(listener, db_pool, email_client)?.await?;
+    // Export the spans that are still buffered; it blocks, so keep it off the async workers
+    tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await?;
 
     Ok(())
 }
@@ -0,0 +1,175 @@
+//! src/maintenance.rs
+
+use crate::configuration::Environment;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgConnectOptions;
+use sqlx::{Connection, Executor, PgConnection};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// The environments whose databases can be garbage-collected without `--any-environment`
+const TEST_ENVIRONMENTS: [&str; 2] = ["local", "test"];
+
+/// Starts the comment with which the integration tests mark their databases,
+/// which is followed by their creation time
+const TEST_DATABASE_COMMENT: &str = "zero2prod test database, created at ";
+
+/// The statement which marks `database_name` as a test database, created at `created_at`
+///
+/// The integration tests run it right after they create their databases,
+/// so that `drop_test_databases` can tell how old they are.
+pub fn mark_test_database(database_name: &str, created_at: DateTime<Utc>) -> String {
+    format!(
+        r#"COMMENT ON DATABASE "{}" IS '{}{}';"#,
+        database_name,
+        TEST_DATABASE_COMMENT,
+        created_at.to_rfc3339()
+    )
+}
+
+/// Refuses to collect the databases of an environment which isn't meant for tests,
+/// unless `any_environment` is set
+pub fn check_environment(environment: &Environment, any_environment: bool) -> Result<(), String> {
+    if any_environment || TEST_ENVIRONMENTS.contains(&environment.as_str()) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Refusing to drop databases in the '{}' environment, which isn't {}; \
+            pass --any-environment if that's what you want.",
+            environment.as_str(),
+            TEST_ENVIRONMENTS.join(" nor ")
+        ))
+    }
+}
+
+/// Drops the databases that the integration tests left behind, if they are older than `older_than`
+///
+/// The tests name their databases with a UUID, and drop them when they are done,
+/// unless they are killed first. Only such databases are dropped, and only if nobody
+/// is connected to them; the others are logged and skipped.
+///
+/// Their age comes from the comment that `mark_test_database` puts on them. The databases
+/// without one, which older tests left behind, are as old as their files, which only
+/// a superuser, or a member of `pg_read_server_files`, can read; if that fails,
+/// they are skipped too.
+///
+/// Returns the names of the dropped databases.
+pub async fn drop_test_databases(
+    connection_options: &PgConnectOptions,
+    older_than: Duration,
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut connection = PgConnection::connect_with(connection_options).await?;
+    let databases: Vec<(String, Option<String>)> = sqlx::query_as(
+        "SELECT datname, shobj_description(oid, 'pg_database') FROM pg_database \
+        WHERE NOT datistemplate",
+    )
+    .fetch_all(&mut connection)
+    .await?;
+    let cutoff = Utc::now()
+        - chrono::Duration::from_std(older_than).unwrap_or_else(|_| chrono::Duration::max_value());
+
+    let mut dropped = Vec::new();
+    for (database_name, comment) in databases {
+        if !is_test_database(&database_name) {
+            continue;
+        }
+        let created_at = match comment.as_deref().and_then(parse_creation_time) {
+            Some(created_at) => created_at,
+            None => match files_modified_at(&mut connection, &database_name).await {
+                Ok(modified_at) => modified_at,
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to tell the age of the database '{}': {}",
+                        database_name,
+                        e
+                    );
+                    continue;
+                }
+            },
+        };
+        if created_at >= cutoff {
+            continue;
+        }
+
+        let statement = format!(r#"DROP DATABASE "{}";"#, database_name);
+        match connection.execute(statement.as_str()).await {
+            Ok(_) => dropped.push(database_name),
+            Err(e) => tracing::warn!("Failed to drop the database '{}': {}", database_name, e),
+        }
+    }
+
+    Ok(dropped)
+}
+
+/// Reads the creation time out of the comment that `mark_test_database` puts on a database
+fn parse_creation_time(comment: &str) -> Option<DateTime<Utc>> {
+    let created_at = comment.strip_prefix(TEST_DATABASE_COMMENT)?;
+    DateTime::parse_from_rfc3339(created_at)
+        .ok()
+        .map(|created_at| created_at.with_timezone(&Utc))
+}
+
+/// When the files of the database were last modified, for the databases without a comment
+async fn files_modified_at(
+    connection: &mut PgConnection,
+    database_name: &str,
+) -> Result<DateTime<Utc>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT (pg_stat_file('base/' || oid || '/PG_VERSION')).modification \
+        FROM pg_database WHERE datname = $1",
+    )
+    .bind(database_name)
+    .fetch_one(connection)
+    .await
+}
+
+/// Whether `name` is the name of a test database, that is, a hyphenated UUID
+fn is_test_database(name: &str) -> bool {
+    name.len() == 36 && Uuid::parse_str(name).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::uuid("0b9e7c4e-8d1a-4a5e-9a43-7f6f2a4c1d2e", true)]
+    #[case::uuid_without_hyphens("0b9e7c4e8d1a4a5e9a437f6f2a4c1d2e", false)]
+    #[case::application_database("newsletter", false)]
+    #[case::system_database("postgres", false)]
+    fn only_uuid_named_databases_are_test_databases(#[case] name: &str, #[case] expected: bool) {
+        assert_eq!(expected, is_test_database(name));
+    }
+
+    #[test]
+    fn the_creation_time_is_read_from_the_comment() {
+        let created_at = "2024-01-31T12:00:00Z".parse().unwrap();
+        let statement = mark_test_database("0b9e7c4e-8d1a-4a5e-9a43-7f6f2a4c1d2e", created_at);
+        let comment = statement
+            .split_once(" IS '")
+            .and_then(|(_, comment)| comment.strip_suffix("';"))
+            .unwrap();
+
+        assert_eq!(Some(created_at), parse_creation_time(comment));
+        assert_eq!(None, parse_creation_time("Our application database"));
+    }
+
+    #[rstest]
+    #[case::local(Environment::Local, false, true)]
+    #[case::test(Environment::Other(String::from("test")), false, true)]
+    #[case::production(Environment::Production, false, false)]
+    #[case::staging(Environment::Other(String::from("staging")), false, false)]
+    #[case::production_on_purpose(Environment::Production, true, true)]
+    fn only_test_environments_are_collected_by_default(
+        #[case] environment: Environment,
+        #[case] any_environment: bool,
+        #[case] allowed: bool,
+    ) {
+        assert_eq!(
+            allowed,
+            check_environment(&environment, any_environment).is_ok()
+        );
+    }
+}
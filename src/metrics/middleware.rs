@@ -0,0 +1,113 @@
+//! src/metrics/middleware.rs
+
+use crate::metrics::Metrics;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Middleware which counts HTTP requests and measures their latency
+///
+/// Requests are labelled with the route pattern, such as `/subscriptions`, and not with
+/// the actual path, so that made-up paths don't blow up the number of time series.
+/// Requests that don't match any route are labelled as `unmatched`.
+///
+/// Errors returned by inner middleware, such as `IpRateLimit`, are counted with their status.
+pub struct RequestMetrics {
+    metrics: Metrics,
+}
+
+impl RequestMetrics {
+    pub fn new(metrics: Metrics) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+    metrics: Metrics,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let method = req.method().to_string();
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| String::from("unmatched"));
+        let service = self.service.clone();
+        let metrics = self.metrics.clone();
+
+        Box::pin(async move {
+            let start = Instant::now();
+            let outcome = service.call(req).await;
+            let status = match &outcome {
+                Ok(response) => response.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            metrics.observe_http_request(&method, &route, status.as_u16(), start.elapsed());
+
+            outcome
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    #[actix_web::test]
+    async fn middleware_counts_requests_by_route_pattern_and_status() {
+        let metrics = Metrics::new();
+        let app = init_service(
+            App::new()
+                .wrap(RequestMetrics::new(metrics.clone()))
+                .route("/items/{id}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for uri in ["/items/1", "/items/2", "/nowhere"] {
+            call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+        }
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            r#"zero2prod_http_requests_total{method="GET",route="/items/{id}",status="200"} 2"#
+        ));
+        assert!(rendered.contains(
+            r#"zero2prod_http_requests_total{method="GET",route="unmatched",status="404"} 1"#
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+//! src/metrics/mod.rs
+
+mod middleware;
+
+pub use middleware::*;
+
+use crate::repository::PoolUsage;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::time::Duration;
+
+/// Steps in the subscription funnel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubscriptionEvent {
+    SignedUp,
+    /// Added by an import, rather than by signing up
+    Imported,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriptionEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionEvent::SignedUp => "signed_up",
+            SubscriptionEvent::Imported => "imported",
+            SubscriptionEvent::Confirmed => "confirmed",
+            SubscriptionEvent::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+/// Our Prometheus metrics
+///
+/// Every instance has its own registry, instead of using the global one, so that tests
+/// which run in parallel don't count each other's requests. Clones share the same metrics.
+///
+/// It consists of:
+///  - HTTP request counters and latency histograms, by method, route and status;
+///  - database connection pool gauges, which are updated on every scrape;
+///  - email send counters and latency histograms, by transport and outcome;
+///  - subscription funnel counters, by event.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    emails_sent: IntCounterVec,
+    email_send_duration: HistogramVec,
+    subscriptions: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some(String::from("zero2prod")), None)
+            .expect("Failed to create a metrics registry.");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests"),
+            &["method", "route", "status"],
+        )
+        .expect("Failed to create a metric.");
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency in seconds",
+            ),
+            &["method", "route", "status"],
+        )
+        .expect("Failed to create a metric.");
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Database pool connections, by state: in use, idle, and the maximum",
+            ),
+            &["state"],
+        )
+        .expect("Failed to create a metric.");
+        let emails_sent = IntCounterVec::new(
+            Opts::new("emails_sent_total", "Number of attempts to send an email"),
+            &["transport", "outcome"],
+        )
+        .expect("Failed to create a metric.");
+        let email_send_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "email_send_duration_seconds",
+                "Email send latency in seconds",
+            ),
+            &["transport", "outcome"],
+        )
+        .expect("Failed to create a metric.");
+        let subscriptions = IntCounterVec::new(
+            Opts::new("subscriptions_total", "Subscription funnel events"),
+            &["event"],
+        )
+        .expect("Failed to create a metric.");
+
+        registry
+            .register(Box::new(http_requests.clone()))
+            .expect("Failed to register a metric.");
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .expect("Failed to register a metric.");
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .expect("Failed to register a metric.");
+        registry
+            .register(Box::new(emails_sent.clone()))
+            .expect("Failed to register a metric.");
+        registry
+            .register(Box::new(email_send_duration.clone()))
+            .expect("Failed to register a metric.");
+        registry
+            .register(Box::new(subscriptions.clone()))
+            .expect("Failed to register a metric.");
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            emails_sent,
+            email_send_duration,
+            subscriptions,
+        }
+    }
+
+    pub fn observe_http_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_email_sent(&self, transport: &str, is_success: bool, elapsed: Duration) {
+        let outcome = if is_success { "success" } else { "failure" };
+        let labels = [transport, outcome];
+        self.emails_sent.with_label_values(&labels).inc();
+        self.email_send_duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn count_subscription(&self, event: SubscriptionEvent) {
+        self.subscriptions
+            .with_label_values(&[event.as_str()])
+            .inc();
+    }
+
+    pub fn set_db_pool(&self, pool: PoolUsage) {
+        for (state, value) in [
+            ("in_use", pool.in_use),
+            ("idle", pool.idle),
+            ("max", pool.max_size),
+        ] {
+            self.db_pool_connections
+                .with_label_values(&[state])
+                .set(value.into());
+        }
+    }
+
+    /// Encodes all the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Failed to encode the metrics.");
+
+        String::from_utf8(buffer).expect("The metrics are not valid UTF-8.")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_exposes_the_observed_values() {
+        let metrics = Metrics::new();
+
+        metrics.observe_http_request("GET", "/health_check", 200, Duration::from_millis(5));
+        metrics.observe_email_sent("postmark", false, Duration::from_millis(5));
+        metrics.count_subscription(SubscriptionEvent::SignedUp);
+        metrics.count_subscription(SubscriptionEvent::SignedUp);
+        metrics.set_db_pool(PoolUsage::new(3, 1, 10));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            r#"zero2prod_http_requests_total{method="GET",route="/health_check",status="200"} 1"#
+        ));
+        assert!(rendered.contains(
+            r#"zero2prod_http_request_duration_seconds_count{method="GET",route="/health_check",status="200"} 1"#
+        ));
+        assert!(rendered
+            .contains(r#"zero2prod_emails_sent_total{outcome="failure",transport="postmark"} 1"#));
+        assert!(rendered.contains(r#"zero2prod_subscriptions_total{event="signed_up"} 2"#));
+        assert!(rendered.contains(r#"zero2prod_db_pool_connections{state="in_use"} 2"#));
+        assert!(rendered.contains(r#"zero2prod_db_pool_connections{state="max"} 10"#));
+    }
+
+    #[test]
+    fn instances_dont_share_metrics() {
+        let first = Metrics::new();
+        let second = Metrics::new();
+
+        first.count_subscription(SubscriptionEvent::Confirmed);
+
+        assert!(!second.render().contains("confirmed"));
+        assert!(first.clone().render().contains("confirmed"));
+    }
+}
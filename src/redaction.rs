@@ -0,0 +1,235 @@
+//! src/redaction.rs
+
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::RwLock;
+
+/// How personal data, such as subscriber emails and names, is written to logs and spans
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionPolicy {
+    /// Replaced with `[REDACTED]`
+    Full,
+    /// Replaced with a short keyed hash, so that the log lines about the same person can be matched
+    #[default]
+    Hashed,
+    /// Mostly hidden, such as `u***@g***.com`
+    Masked,
+    /// Written as is, which is only meant for local development
+    None,
+}
+
+/// The policy in effect, as the `u8` value of a `RedactionPolicy`
+///
+/// It is global, like the `tracing` subscriber, because personal data is formatted
+/// wherever it is logged, far away from the configuration.
+static POLICY: AtomicU8 = AtomicU8::new(RedactionPolicy::Hashed as u8);
+
+/// The HMAC which hashes personal data, keyed with `set_hash_key`, or else a random key
+static HASH_KEY: RwLock<Option<Hmac<Sha256>>> = RwLock::new(None);
+
+impl RedactionPolicy {
+    /// Gets the policy in effect, which is `Hashed` until another one is set
+    pub fn current() -> Self {
+        match POLICY.load(Ordering::Relaxed) {
+            0 => RedactionPolicy::Full,
+            2 => RedactionPolicy::Masked,
+            3 => RedactionPolicy::None,
+            _ => RedactionPolicy::Hashed,
+        }
+    }
+
+    /// Puts this policy in effect for the whole application
+    pub fn set_current(self) {
+        POLICY.store(self as u8, Ordering::Relaxed);
+    }
+
+    /// Redacts `value` according to this policy
+    pub fn redact(&self, value: &str, kind: PersonalData) -> String {
+        match self {
+            RedactionPolicy::Full => String::from("[REDACTED]"),
+            RedactionPolicy::Hashed => hash(value),
+            RedactionPolicy::Masked => match kind {
+                PersonalData::Email => mask_email(value),
+                PersonalData::Name => mask(value),
+            },
+            RedactionPolicy::None => value.to_string(),
+        }
+    }
+}
+
+/// Kinds of personal data, which are masked differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PersonalData {
+    Email,
+    Name,
+}
+
+/// Personal data which is redacted, according to the current policy, when it is formatted
+///
+/// Use it to record personal data in spans and events, such as
+/// `subscriber_email = %Redacted::email(&form.email)`.
+/// `Debug` and `Display` print the same thing, so neither of them leaks the raw value.
+pub struct Redacted<'a> {
+    value: &'a str,
+    kind: PersonalData,
+}
+
+impl<'a> Redacted<'a> {
+    pub fn email(value: &'a str) -> Self {
+        Self {
+            value,
+            kind: PersonalData::Email,
+        }
+    }
+
+    pub fn name(value: &'a str) -> Self {
+        Self {
+            value,
+            kind: PersonalData::Name,
+        }
+    }
+}
+
+impl std::fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&RedactionPolicy::current().redact(self.value, self.kind))
+    }
+}
+
+impl std::fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+/// Sets the key of the hashes, for the whole application
+///
+/// Support can find the log lines about a person by hashing their email with the same key.
+/// Without a key, a random one is made up, so the hashes only match within one process.
+pub fn set_hash_key(key: &Secret<String>) {
+    let mac = Hmac::<Sha256>::new_from_slice(key.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length.");
+    *HASH_KEY.write().expect("Hash key lock is poisoned.") = Some(mac);
+}
+
+/// Hashes `value` with HMAC-SHA256, and keeps the first 8 bytes of the hash, as hex
+///
+/// That's plenty to tell people apart in the logs. The hash is keyed, as a plain hash
+/// of an email address can be reversed by hashing a list of known addresses.
+fn hash(value: &str) -> String {
+    let mut mac = hash_key();
+    mac.update(value.as_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    format!("hmac:{}", hex::encode(&digest[..8]))
+}
+
+fn hash_key() -> Hmac<Sha256> {
+    if let Some(mac) = HASH_KEY
+        .read()
+        .expect("Hash key lock is poisoned.")
+        .as_ref()
+    {
+        return mac.clone();
+    }
+    HASH_KEY
+        .write()
+        .expect("Hash key lock is poisoned.")
+        .get_or_insert_with(|| {
+            let key = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()];
+            Hmac::<Sha256>::new_from_slice(&key.map(|uuid| uuid.into_bytes()).concat())
+                .expect("HMAC accepts keys of any length.")
+        })
+        .clone()
+}
+
+/// Keeps the first character, and hides the rest
+fn mask(value: &str) -> String {
+    match value.chars().next() {
+        Some(first) => format!("{}***", first),
+        None => String::new(),
+    }
+}
+
+/// Masks the local part and the domain name of an email address, but keeps the top-level domain
+fn mask_email(value: &str) -> String {
+    let Some((local_part, domain)) = value.rsplit_once('@') else {
+        return mask(value);
+    };
+
+    match domain.rsplit_once('.') {
+        Some((name, tld)) => format!("{}@{}.{}", mask(local_part), mask(name), tld),
+        None => format!("{}@{}", mask(local_part), mask(domain)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::{fixture, rstest};
+
+    /// Every test that needs stable hashes sets the same key
+    #[fixture]
+    fn hash_key() {
+        set_hash_key(&Secret::new(String::from("redaction-key")));
+    }
+
+    #[rstest]
+    #[case::full(RedactionPolicy::Full, "[REDACTED]", "[REDACTED]")]
+    #[case::hashed(
+        RedactionPolicy::Hashed,
+        "hmac:d734fecaf1f310f0",
+        "hmac:27b967955064fcc0"
+    )]
+    #[case::masked(RedactionPolicy::Masked, "u***@g***.com", "U***")]
+    #[case::none(RedactionPolicy::None, "ursula@gmail.com", "Ursula")]
+    fn personal_data_is_redacted_according_to_the_policy(
+        _hash_key: (),
+        #[case] policy: RedactionPolicy,
+        #[case] email: &str,
+        #[case] name: &str,
+    ) {
+        assert_eq!(
+            email,
+            policy.redact("ursula@gmail.com", PersonalData::Email)
+        );
+        assert_eq!(name, policy.redact("Ursula", PersonalData::Name));
+    }
+
+    #[rstest]
+    fn hashes_are_stable_and_tell_values_apart(_hash_key: ()) {
+        let policy = RedactionPolicy::Hashed;
+
+        let first = policy.redact("ursula@gmail.com", PersonalData::Email);
+        let again = policy.redact("ursula@gmail.com", PersonalData::Email);
+        let other = policy.redact("ursula@yahoo.com", PersonalData::Email);
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+    }
+
+    #[rstest]
+    #[case::no_at_sign("definitely-not-an-email", "d***")]
+    #[case::no_tld("ursula@localhost", "u***@l***")]
+    #[case::empty_local_part("@gmail.com", "@g***.com")]
+    #[case::empty("", "")]
+    fn malformed_emails_are_still_masked(#[case] email: &str, #[case] expected: &str) {
+        assert_eq!(
+            expected,
+            RedactionPolicy::Masked.redact(email, PersonalData::Email)
+        );
+    }
+
+    #[test]
+    fn redacted_values_are_not_leaked_by_debug() {
+        // No test changes the current policy, so it is `Hashed`
+        let email = Redacted::email("ursula@gmail.com");
+
+        assert!(format!("{:?}", email).starts_with("hmac:"));
+        assert_eq!(format!("{}", email), format!("{:?}", email));
+    }
+}
@@ -0,0 +1,396 @@
+//! src/reload.rs
+
+use crate::abuse_protection::RateLimiter;
+use crate::configuration::{get_configuration, ConfigurationError, Settings};
+use crate::telemetry::LogFilterHandle;
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use sha2::Sha256;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+/// The settings which can be changed without a restart, by reloading the configuration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReloadableSettings {
+    pub email_client_timeout: Duration,
+    /// The maximum number of requests, and the window
+    pub ip_rate_limit: (u32, Duration),
+    pub domain_rate_limit: (u32, Duration),
+    pub log_level: String,
+}
+
+/// The settings paths of the reloadable settings, as in the configuration files
+const RELOADABLE_SETTINGS: [&str; 4] = [
+    "email_client.timeout_millis",
+    "abuse_protection.ip_rate_limit",
+    "abuse_protection.domain_rate_limit",
+    "logging.level",
+];
+
+impl From<&Settings> for ReloadableSettings {
+    fn from(settings: &Settings) -> Self {
+        let rate_limit = |limit: &crate::configuration::RateLimitSettings| {
+            (limit.max_requests, Duration::from_secs(limit.window_secs))
+        };
+
+        Self {
+            email_client_timeout: settings.email_client.get_timeout(),
+            ip_rate_limit: rate_limit(&settings.abuse_protection.ip_rate_limit),
+            domain_rate_limit: rate_limit(&settings.abuse_protection.domain_rate_limit),
+            log_level: settings.logging.level.clone(),
+        }
+    }
+}
+
+/// A shared handle to the current `ReloadableSettings`
+///
+/// Readers get a snapshot, which a reload never changes under their feet;
+/// a reload swaps the whole snapshot at once. It is cheap to clone.
+#[derive(Clone, Debug)]
+pub struct LiveSettings(Arc<RwLock<Arc<ReloadableSettings>>>);
+
+impl LiveSettings {
+    pub fn new(settings: ReloadableSettings) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(settings))))
+    }
+
+    pub fn current(&self) -> Arc<ReloadableSettings> {
+        self.0
+            .read()
+            .expect("Live settings lock is poisoned.")
+            .clone()
+    }
+
+    fn replace(&self, settings: ReloadableSettings) {
+        *self.0.write().expect("Live settings lock is poisoned.") = Arc::new(settings);
+    }
+}
+
+/// Reloads the configuration, and applies the reloadable settings to the running app
+///
+/// Changes to any other setting, such as the port, are ignored with a warning,
+/// because they need a restart. An invalid configuration is rejected as a whole,
+/// and the current settings stay in effect.
+///
+/// A log filter set at runtime, through `RUST_LOG` or `/admin/log_filter`,
+/// is kept when `logging.level` changes.
+pub struct ConfigReloader {
+    /// The settings that were loaded last, on start or by a reload, to tell which ones changed
+    last_loaded: Mutex<serde_json::Value>,
+    /// The random key of the hashes which stand for the secrets in `last_loaded`
+    secrets_key: Hmac<Sha256>,
+    live: LiveSettings,
+    ip_rate_limiter: Arc<RateLimiter>,
+    domain_rate_limiter: Arc<RateLimiter>,
+    log_filter: LogFilterHandle,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        settings: &Settings,
+        live: LiveSettings,
+        ip_rate_limiter: Arc<RateLimiter>,
+        domain_rate_limiter: Arc<RateLimiter>,
+        log_filter: LogFilterHandle,
+    ) -> Self {
+        let secrets_key = Hmac::<Sha256>::new_from_slice(uuid::Uuid::new_v4().as_bytes())
+            .expect("HMAC accepts keys of any length.");
+        Self {
+            last_loaded: Mutex::new(snapshot(settings, &secrets_key)),
+            secrets_key,
+            live,
+            ip_rate_limiter,
+            domain_rate_limiter,
+            log_filter,
+        }
+    }
+
+    /// Loads the configuration again, from the files and the environment, and applies it
+    pub fn reload(&self) -> Result<(), ConfigurationError> {
+        self.apply(get_configuration())
+    }
+
+    /// Applies the reloadable settings of `reloaded`, unless it is an error
+    #[tracing::instrument(name = "Reloading the configuration", skip(self, reloaded))]
+    pub fn apply(
+        &self,
+        reloaded: Result<Settings, ConfigurationError>,
+    ) -> Result<(), ConfigurationError> {
+        let reloaded = reloaded.map_err(|e| {
+            tracing::error!("Rejected the reloaded configuration: {}", e);
+            e
+        })?;
+
+        for setting in self.unreloadable_changes(&reloaded) {
+            tracing::warn!(
+                "The setting {} has changed, but it can't be reloaded; restart the app to apply it.",
+                setting
+            );
+        }
+
+        let current = self.live.current();
+        let reloaded = ReloadableSettings::from(&reloaded);
+        // The filter in effect differs from the configured one if it was set at runtime
+        let is_configured_filter =
+            self.log_filter.directives().ok() == normalize_filter(&current.log_level);
+        if reloaded.log_level != current.log_level && !is_configured_filter {
+            tracing::warn!(
+                "The setting logging.level has changed, but the log filter was set at runtime; keeping it."
+            );
+        } else if reloaded.log_level != current.log_level {
+            // It has been validated, so this only fails if the subscriber is gone
+            if let Err(e) = self.log_filter.set_directives(&reloaded.log_level) {
+                tracing::error!("Failed to change the log filter: {}", e);
+            }
+        }
+        let (max_requests, window) = reloaded.ip_rate_limit;
+        self.ip_rate_limiter.set_limits(max_requests, window);
+        let (max_requests, window) = reloaded.domain_rate_limit;
+        self.domain_rate_limiter.set_limits(max_requests, window);
+        self.live.replace(reloaded);
+
+        tracing::info!("The configuration is reloaded.");
+        Ok(())
+    }
+
+    /// Lists the settings which can't be reloaded and differ from the ones loaded last,
+    /// then remembers `reloaded`, so that each change is only reported once
+    fn unreloadable_changes(&self, reloaded: &Settings) -> Vec<String> {
+        let reloaded = snapshot(reloaded, &self.secrets_key);
+        let mut last_loaded = self
+            .last_loaded
+            .lock()
+            .expect("Last loaded settings lock is poisoned.");
+        let mut changed = Vec::new();
+        changed_settings(String::new(), &last_loaded, &reloaded, &mut changed);
+        *last_loaded = reloaded;
+
+        changed.retain(|setting| !is_reloadable(setting));
+        changed
+    }
+}
+
+/// Serializes `settings` to compare them, with a hash of each secret, keyed by `key`,
+/// instead of `[REDACTED]`, so that a rotated secret counts as a change
+fn snapshot(settings: &Settings, key: &Hmac<Sha256>) -> serde_json::Value {
+    let mut snapshot = serde_json::to_value(settings).expect("Failed to serialize the settings.");
+    for (setting, secret) in settings.secrets() {
+        let pointer = format!("/{}", setting.replace('.', "/"));
+        if let (Some(value), Some(secret)) = (snapshot.pointer_mut(&pointer), secret) {
+            let mut mac = key.clone();
+            mac.update(secret.expose_secret().as_bytes());
+            *value = serde_json::Value::String(hex::encode(mac.finalize().into_bytes()));
+        }
+    }
+    snapshot
+}
+
+/// Formats filter directives the way `LogFilterHandle::directives` does, so that they compare
+fn normalize_filter(directives: &str) -> Option<String> {
+    EnvFilter::try_new(directives)
+        .ok()
+        .map(|filter| filter.to_string())
+}
+
+/// Reloads the configuration every time the process receives `SIGHUP`
+#[cfg(unix)]
+pub async fn reload_on_hangup(reloader: ConfigReloader) -> Result<(), std::io::Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        // Errors are logged, and there is nothing else to do about them
+        let _ = reloader.reload();
+    }
+
+    Ok(())
+}
+
+fn is_reloadable(setting: &str) -> bool {
+    RELOADABLE_SETTINGS.iter().any(|reloadable| {
+        setting == *reloadable || setting.starts_with(&format!("{}.", reloadable))
+    })
+}
+
+/// Collects the paths of the settings whose values differ between `old` and `new`
+fn changed_settings(
+    path: String,
+    old: &serde_json::Value,
+    new: &serde_json::Value,
+    changed: &mut Vec<String>,
+) {
+    match (old, new) {
+        (serde_json::Value::Object(old), serde_json::Value::Object(new)) => {
+            for (key, old_value) in old {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                let new_value = new.get(key).unwrap_or(&serde_json::Value::Null);
+                changed_settings(path, old_value, new_value, changed);
+            }
+        }
+        (old, new) if old != new => changed.push(path),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::configuration::local_test_settings;
+    use crate::telemetry::{get_subscriber, get_tracer_provider, LogFormat};
+    use claims::assert_err;
+    use opentelemetry::trace::TracerProvider as _;
+    use secrecy::Secret;
+
+    struct Arrange {
+        settings: Settings,
+        live: LiveSettings,
+        ip_rate_limiter: Arc<RateLimiter>,
+        log_filter: LogFilterHandle,
+        reloader: ConfigReloader,
+    }
+
+    fn arrange() -> Arrange {
+        let settings = local_test_settings();
+        let live = LiveSettings::new(ReloadableSettings::from(&settings));
+        let ip_rate_limiter = Arc::new(settings.abuse_protection.ip_rate_limit.get_limiter());
+        let domain_rate_limiter =
+            Arc::new(settings.abuse_protection.domain_rate_limit.get_limiter());
+        let tracer_provider = get_tracer_provider("test", None, Duration::from_secs(1)).unwrap();
+        let (subscriber, log_filter) = get_subscriber(
+            "test",
+            &settings.logging.level,
+            LogFormat::Bunyan,
+            std::io::sink,
+            tracer_provider.tracer("test"),
+        );
+        // The filter can only be changed while its subscriber is alive
+        std::mem::forget(subscriber);
+        let reloader = ConfigReloader::new(
+            &settings,
+            live.clone(),
+            ip_rate_limiter.clone(),
+            domain_rate_limiter,
+            log_filter.clone(),
+        );
+
+        Arrange {
+            settings,
+            live,
+            ip_rate_limiter,
+            log_filter,
+            reloader,
+        }
+    }
+
+    #[test]
+    fn reloadable_settings_are_applied() {
+        let arrange = arrange();
+        let mut reloaded = local_test_settings();
+        reloaded.abuse_protection.ip_rate_limit.max_requests = 1;
+        reloaded.logging.level = String::from("debug");
+
+        arrange.reloader.apply(Ok(reloaded)).unwrap();
+
+        assert_eq!(1, arrange.live.current().ip_rate_limit.0);
+        assert_eq!("debug", arrange.live.current().log_level);
+        assert_eq!("debug", arrange.log_filter.directives().unwrap());
+        assert!(arrange.ip_rate_limiter.check("a"));
+        assert!(!arrange.ip_rate_limiter.check("a"));
+    }
+
+    #[test]
+    fn a_log_filter_set_at_runtime_is_kept() {
+        let arrange = arrange();
+        arrange.log_filter.set_directives("warn").unwrap();
+        let mut reloaded = local_test_settings();
+        reloaded.logging.level = String::from("debug");
+
+        arrange.reloader.apply(Ok(reloaded)).unwrap();
+
+        assert_eq!("debug", arrange.live.current().log_level);
+        assert_eq!("warn", arrange.log_filter.directives().unwrap());
+    }
+
+    #[test]
+    fn unreloadable_changes_are_only_reported_once() {
+        let arrange = arrange();
+        let mut reloaded = local_test_settings();
+        reloaded.application.port += 1;
+
+        let first = arrange.reloader.unreloadable_changes(&reloaded);
+        let second = arrange.reloader.unreloadable_changes(&reloaded);
+
+        assert_eq!(vec!["application.port"], first);
+        assert!(second.is_empty(), "{:?}", second);
+    }
+
+    #[test]
+    fn changed_secrets_are_reported() {
+        let arrange = arrange();
+        let mut reloaded = local_test_settings();
+        reloaded.email_client.authorization_token = Secret::new(String::from("rotated-token"));
+        reloaded.logging.admin_token = Some(Secret::new(String::from("s3cr3t")));
+
+        let changed = arrange.reloader.unreloadable_changes(&reloaded);
+
+        assert_eq!(
+            vec!["email_client.authorization_token", "logging.admin_token"],
+            changed
+        );
+        assert!(arrange
+            .reloader
+            .unreloadable_changes(&local_test_settings())
+            .contains(&String::from("logging.admin_token")));
+    }
+
+    #[test]
+    fn an_invalid_configuration_is_rejected_and_the_current_one_stays() {
+        let arrange = arrange();
+        let before = arrange.live.current();
+        let mut reloaded = local_test_settings();
+        reloaded.email_client.base_url = String::from("not a url");
+        let invalid = reloaded
+            .validate(&crate::configuration::Environment::Local)
+            .map(|_| reloaded);
+
+        assert_err!(arrange.reloader.apply(invalid.map_err(Into::into)));
+
+        assert_eq!(before, arrange.live.current());
+        assert_eq!(
+            arrange.settings.logging.level,
+            arrange.log_filter.directives().unwrap()
+        );
+    }
+
+    #[test]
+    fn only_the_changed_settings_are_reported() {
+        let settings = local_test_settings();
+        let mut reloaded = local_test_settings();
+        reloaded.application.port += 1;
+        reloaded.abuse_protection.ip_rate_limit.max_requests += 1;
+        let mut changed = Vec::new();
+
+        changed_settings(
+            String::new(),
+            &serde_json::to_value(&settings).unwrap(),
+            &serde_json::to_value(&reloaded).unwrap(),
+            &mut changed,
+        );
+
+        assert_eq!(
+            vec![
+                "abuse_protection.ip_rate_limit.max_requests",
+                "application.port"
+            ],
+            changed
+        );
+        assert!(is_reloadable(&changed[0]));
+        assert!(!is_reloadable(&changed[1]));
+    }
+}
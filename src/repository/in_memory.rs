@@ -0,0 +1,307 @@
+//! src/repository/in_memory.rs
+
+use crate::domain::{NewSubscriber, SubscriptionStatus};
+use crate::repository::{
+    ImportedSubscriber, PoolUsage, RepositoryError, StorageHealth, Subscriber, SubscriberFilter,
+    SubscriberRepository,
+};
+use chrono::Utc;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// A `SubscriberRepository` which keeps subscribers in memory
+///
+/// Nothing survives a restart, so it is meant for tests, where it lets us
+/// exercise request handlers without spinning up a database.
+/// Email addresses are unique, like in the database.
+#[derive(Default)]
+pub struct InMemorySubscriberRepository {
+    subscribers: Mutex<Vec<Subscriber>>,
+    confirmation_requests: Mutex<Vec<Uuid>>,
+}
+
+impl InMemorySubscriberRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn set_status(&self, id: Uuid, status: SubscriptionStatus) -> bool {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .expect("Repository lock is poisoned.");
+        match subscribers
+            .iter_mut()
+            .find(|subscriber| subscriber.id == id)
+        {
+            Some(subscriber) => {
+                subscriber.status = status;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SubscriberRepository for InMemorySubscriberRepository {
+    async fn insert(
+        &self,
+        new_subscriber: &NewSubscriber,
+        review_reason: Option<&str>,
+    ) -> Result<Uuid, RepositoryError> {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .expect("Repository lock is poisoned.");
+        let email = new_subscriber.email.as_ref();
+        if subscribers
+            .iter()
+            .any(|subscriber| subscriber.email == email)
+        {
+            return Err(RepositoryError::DuplicateEmail);
+        }
+
+        let id = Uuid::new_v4();
+        subscribers.push(Subscriber {
+            id,
+            email: email.to_string(),
+            name: new_subscriber.name.as_ref().to_string(),
+            status: SubscriptionStatus::PendingConfirmation,
+            subscribed_at: Utc::now(),
+            review_reason: review_reason.map(String::from),
+            tags: Vec::new(),
+        });
+
+        Ok(id)
+    }
+
+    async fn import(&self, imported: &[ImportedSubscriber]) -> Result<Vec<bool>, RepositoryError> {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .expect("Repository lock is poisoned.");
+        let mut confirmation_requests = self
+            .confirmation_requests
+            .lock()
+            .expect("Repository lock is poisoned.");
+        let mut stored = Vec::with_capacity(imported.len());
+        for subscriber in imported {
+            let email = subscriber.new_subscriber.email.as_ref();
+            if subscribers.iter().any(|existing| existing.email == email) {
+                stored.push(false);
+                continue;
+            }
+            let mut tags = subscriber.tags.clone();
+            tags.sort();
+            tags.dedup();
+            let id = Uuid::new_v4();
+            if subscriber.reconfirm {
+                confirmation_requests.push(id);
+            }
+            subscribers.push(Subscriber {
+                id,
+                email: email.to_string(),
+                name: subscriber.new_subscriber.name.as_ref().to_string(),
+                status: subscriber.status,
+                subscribed_at: subscriber.subscribed_at,
+                review_reason: None,
+                tags,
+            });
+            stored.push(true);
+        }
+
+        Ok(stored)
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<Subscriber>, RepositoryError> {
+        let subscribers = self
+            .subscribers
+            .lock()
+            .expect("Repository lock is poisoned.");
+        Ok(subscribers
+            .iter()
+            .find(|subscriber| subscriber.email == email)
+            .cloned())
+    }
+
+    async fn confirmation_requests(&self) -> Result<Vec<Uuid>, RepositoryError> {
+        Ok(self
+            .confirmation_requests
+            .lock()
+            .expect("Repository lock is poisoned.")
+            .clone())
+    }
+
+    async fn confirm(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        Ok(self.set_status(id, SubscriptionStatus::Confirmed))
+    }
+
+    async fn unsubscribe(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        Ok(self.set_status(id, SubscriptionStatus::Unsubscribed))
+    }
+
+    async fn list(
+        &self,
+        status: Option<SubscriptionStatus>,
+    ) -> Result<Vec<Subscriber>, RepositoryError> {
+        let subscribers = self
+            .subscribers
+            .lock()
+            .expect("Repository lock is poisoned.");
+        Ok(subscribers
+            .iter()
+            .filter(|subscriber| status.is_none() || status == Some(subscriber.status))
+            .cloned()
+            .collect())
+    }
+
+    fn export(
+        &self,
+        filter: SubscriberFilter,
+    ) -> BoxStream<'static, Result<Subscriber, RepositoryError>> {
+        let subscribers = self
+            .subscribers
+            .lock()
+            .expect("Repository lock is poisoned.");
+        let mut exported: Vec<_> = subscribers
+            .iter()
+            .filter(|subscriber| filter.matches(subscriber))
+            .cloned()
+            .collect();
+        exported.sort_by_key(|subscriber| subscriber.subscribed_at);
+        stream::iter(exported.into_iter().map(Ok)).boxed()
+    }
+
+    async fn check_health(&self) -> Result<StorageHealth, RepositoryError> {
+        Ok(StorageHealth {
+            pool: None,
+            migrations_current: true,
+        })
+    }
+
+    fn pool_usage(&self) -> Option<PoolUsage> {
+        None
+    }
+
+    async fn close(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::domain::{SubscriberEmail, SubscriberName};
+    use claims::{assert_matches, assert_none, assert_ok_eq};
+
+    fn new_subscriber(email: &str) -> NewSubscriber {
+        NewSubscriber {
+            email: SubscriberEmail::parse(email.to_string()).unwrap(),
+            name: SubscriberName::parse(String::from("John Doe")).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn inserted_subscriber_can_be_found_by_email() {
+        let repository = InMemorySubscriberRepository::new();
+
+        let id = repository
+            .insert(&new_subscriber("john.doe@domain.yq"), Some("flagged"))
+            .await
+            .unwrap();
+
+        let subscriber = repository
+            .find_by_email("john.doe@domain.yq")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(id, subscriber.id);
+        assert_eq!("John Doe", subscriber.name);
+        assert_eq!(SubscriptionStatus::PendingConfirmation, subscriber.status);
+        assert_eq!(Some("flagged"), subscriber.review_reason.as_deref());
+        assert_none!(repository.find_by_email("jane@domain.yq").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn insert_rejects_duplicate_emails() {
+        let repository = InMemorySubscriberRepository::new();
+        let new_subscriber = new_subscriber("john.doe@domain.yq");
+
+        repository.insert(&new_subscriber, None).await.unwrap();
+
+        assert_matches!(
+            repository.insert(&new_subscriber, None).await,
+            Err(RepositoryError::DuplicateEmail)
+        );
+    }
+
+    #[tokio::test]
+    async fn confirm_and_unsubscribe_change_the_status() {
+        let repository = InMemorySubscriberRepository::new();
+        let john = repository
+            .insert(&new_subscriber("john.doe@domain.yq"), None)
+            .await
+            .unwrap();
+        let jane = repository
+            .insert(&new_subscriber("jane.doe@domain.yq"), None)
+            .await
+            .unwrap();
+
+        assert_ok_eq!(repository.confirm(john).await, true);
+        assert_ok_eq!(repository.unsubscribe(jane).await, true);
+        assert_ok_eq!(repository.confirm(Uuid::new_v4()).await, false);
+
+        let confirmed = repository
+            .list(Some(SubscriptionStatus::Confirmed))
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![john],
+            confirmed.iter().map(|s| s.id).collect::<Vec<_>>()
+        );
+        assert_eq!(2, repository.list(None).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn import_skips_the_stored_email_addresses() {
+        let repository = InMemorySubscriberRepository::new();
+        repository
+            .insert(&new_subscriber("john.doe@domain.yq"), None)
+            .await
+            .unwrap();
+        let subscribed_at = "2019-05-01T12:00:00Z".parse().unwrap();
+        let imported = |email: &str| ImportedSubscriber {
+            new_subscriber: new_subscriber(email),
+            status: SubscriptionStatus::Confirmed,
+            subscribed_at,
+            tags: vec![String::from("weekly"), String::from("legacy")],
+            reconfirm: false,
+        };
+
+        let stored = repository
+            .import(&[
+                imported("john.doe@domain.yq"),
+                imported("jane.doe@domain.yq"),
+            ])
+            .await;
+
+        assert_ok_eq!(stored, vec![false, true]);
+        let john = repository
+            .find_by_email("john.doe@domain.yq")
+            .await
+            .unwrap();
+        assert_eq!(
+            SubscriptionStatus::PendingConfirmation,
+            john.unwrap().status
+        );
+        let jane = repository
+            .find_by_email("jane.doe@domain.yq")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(SubscriptionStatus::Confirmed, jane.status);
+        assert_eq!(subscribed_at, jane.subscribed_at);
+        assert_eq!(vec!["legacy", "weekly"], jane.tags);
+    }
+}
@@ -0,0 +1,135 @@
+//! src/repository/metered.rs
+
+use crate::domain::{NewSubscriber, SubscriptionStatus};
+use crate::metrics::{Metrics, SubscriptionEvent};
+use crate::repository::{
+    ImportedSubscriber, PoolUsage, RepositoryError, StorageHealth, Subscriber, SubscriberFilter,
+    SubscriberRepository,
+};
+use futures_util::stream::BoxStream;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A `SubscriberRepository` which wraps another one and counts the subscription funnel events
+///
+/// Only changes that actually happened are counted: a confirmation of an unknown id isn't.
+/// Wrapping the repository, instead of counting in the request handlers, means that
+/// no code path which changes a subscription can forget to count it.
+pub struct MeteredSubscriberRepository {
+    inner: Arc<dyn SubscriberRepository>,
+    metrics: Metrics,
+}
+
+impl MeteredSubscriberRepository {
+    pub fn new(inner: Arc<dyn SubscriberRepository>, metrics: Metrics) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+#[async_trait::async_trait]
+impl SubscriberRepository for MeteredSubscriberRepository {
+    async fn insert(
+        &self,
+        new_subscriber: &NewSubscriber,
+        review_reason: Option<&str>,
+    ) -> Result<Uuid, RepositoryError> {
+        let id = self.inner.insert(new_subscriber, review_reason).await?;
+        self.metrics.count_subscription(SubscriptionEvent::SignedUp);
+
+        Ok(id)
+    }
+
+    async fn import(&self, imported: &[ImportedSubscriber]) -> Result<Vec<bool>, RepositoryError> {
+        let stored = self.inner.import(imported).await?;
+        for _ in stored.iter().filter(|is_stored| **is_stored) {
+            self.metrics.count_subscription(SubscriptionEvent::Imported);
+        }
+
+        Ok(stored)
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<Subscriber>, RepositoryError> {
+        self.inner.find_by_email(email).await
+    }
+
+    async fn confirmation_requests(&self) -> Result<Vec<Uuid>, RepositoryError> {
+        self.inner.confirmation_requests().await
+    }
+
+    async fn confirm(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        let is_confirmed = self.inner.confirm(id).await?;
+        if is_confirmed {
+            self.metrics
+                .count_subscription(SubscriptionEvent::Confirmed);
+        }
+
+        Ok(is_confirmed)
+    }
+
+    async fn unsubscribe(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        let is_unsubscribed = self.inner.unsubscribe(id).await?;
+        if is_unsubscribed {
+            self.metrics
+                .count_subscription(SubscriptionEvent::Unsubscribed);
+        }
+
+        Ok(is_unsubscribed)
+    }
+
+    async fn list(
+        &self,
+        status: Option<SubscriptionStatus>,
+    ) -> Result<Vec<Subscriber>, RepositoryError> {
+        self.inner.list(status).await
+    }
+
+    fn export(
+        &self,
+        filter: SubscriberFilter,
+    ) -> BoxStream<'static, Result<Subscriber, RepositoryError>> {
+        self.inner.export(filter)
+    }
+
+    async fn check_health(&self) -> Result<StorageHealth, RepositoryError> {
+        self.inner.check_health().await
+    }
+
+    fn pool_usage(&self) -> Option<PoolUsage> {
+        self.inner.pool_usage()
+    }
+
+    async fn close(&self) {
+        self.inner.close().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::domain::{SubscriberEmail, SubscriberName};
+    use crate::repository::InMemorySubscriberRepository;
+
+    #[tokio::test]
+    async fn only_successful_changes_are_counted() {
+        let metrics = Metrics::new();
+        let repository = MeteredSubscriberRepository::new(
+            Arc::new(InMemorySubscriberRepository::new()),
+            metrics.clone(),
+        );
+        let new_subscriber = NewSubscriber {
+            email: SubscriberEmail::parse(String::from("john.doe@domain.yq")).unwrap(),
+            name: SubscriberName::parse(String::from("John Doe")).unwrap(),
+        };
+
+        let id = repository.insert(&new_subscriber, None).await.unwrap();
+        assert!(repository.insert(&new_subscriber, None).await.is_err());
+        repository.confirm(id).await.unwrap();
+        repository.confirm(Uuid::new_v4()).await.unwrap();
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(r#"zero2prod_subscriptions_total{event="signed_up"} 1"#));
+        assert!(rendered.contains(r#"zero2prod_subscriptions_total{event="confirmed"} 1"#));
+        assert!(!rendered.contains("unsubscribed"));
+    }
+}
@@ -0,0 +1,312 @@
+//! src/repository/mod.rs
+
+mod in_memory;
+mod metered;
+mod postgres;
+mod sqlite;
+
+pub use in_memory::InMemorySubscriberRepository;
+pub use metered::MeteredSubscriberRepository;
+pub use postgres::PostgresSubscriberRepository;
+pub use sqlite::SqliteSubscriberRepository;
+
+use crate::domain::{NewSubscriber, SubscriptionStatus};
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use sqlx::migrate::{AppliedMigration, Migration};
+use uuid::Uuid;
+
+/// A subscriber, as stored in a `SubscriberRepository`
+///
+/// `review_reason` is set for subscribers who were flagged by the `EmailPolicy`.
+/// `tags` are sorted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    pub subscribed_at: DateTime<Utc>,
+    pub review_reason: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// A subscriber who is imported from another platform, rather than signing up
+///
+/// They keep the date at which they subscribed there, and their tags.
+/// With `reconfirm`, they are queued for a confirmation email, see `confirmation_requests`.
+pub struct ImportedSubscriber {
+    pub new_subscriber: NewSubscriber,
+    pub status: SubscriptionStatus,
+    pub subscribed_at: DateTime<Utc>,
+    pub tags: Vec<String>,
+    pub reconfirm: bool,
+}
+
+/// Which subscribers to export, see `SubscriberRepository::export`
+///
+/// The subscription dates are a half-open range: `subscribed_since` is included,
+/// and `subscribed_before` isn't.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SubscriberFilter {
+    pub status: Option<SubscriptionStatus>,
+    pub subscribed_since: Option<DateTime<Utc>>,
+    pub subscribed_before: Option<DateTime<Utc>>,
+}
+
+impl SubscriberFilter {
+    pub fn matches(&self, subscriber: &Subscriber) -> bool {
+        self.status.is_none_or(|status| status == subscriber.status)
+            && self
+                .subscribed_since
+                .is_none_or(|since| subscriber.subscribed_at >= since)
+            && self
+                .subscribed_before
+                .is_none_or(|before| subscriber.subscribed_at < before)
+    }
+}
+
+/// The state of the storage, as reported by `SubscriberRepository::check_health`
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct StorageHealth {
+    /// Usage of the connection pool, if the storage is behind one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<PoolUsage>,
+    /// Whether all the migrations that the application was built with have been applied
+    pub migrations_current: bool,
+}
+
+/// A snapshot of a connection pool's usage
+///
+/// The pool is saturated when all of its connections are in use,
+/// so new requests have to wait for a connection to be returned.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub struct PoolUsage {
+    pub in_use: u32,
+    pub idle: u32,
+    pub max_size: u32,
+    pub saturated: bool,
+}
+
+impl PoolUsage {
+    /// Builds the snapshot out of the pool's current `size`, number of `idle` connections,
+    /// and its `max_size`
+    pub fn new(size: u32, idle: usize, max_size: u32) -> Self {
+        let idle = idle as u32;
+        let in_use = size.saturating_sub(idle);
+        Self {
+            in_use,
+            idle,
+            max_size,
+            saturated: in_use >= max_size,
+        }
+    }
+}
+
+/// Checks that every up migration in `migrations` has been applied, and hasn't been changed since
+fn migrations_are_current<'a>(
+    migrations: impl IntoIterator<Item = &'a Migration>,
+    applied: &[AppliedMigration],
+) -> bool {
+    migrations
+        .into_iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .all(|migration| {
+            applied.iter().any(|applied| {
+                applied.version == migration.version && applied.checksum == migration.checksum
+            })
+        })
+}
+
+/// Errors that a `SubscriberRepository` can return
+#[derive(Debug)]
+pub enum RepositoryError {
+    /// A subscriber with the same email address is already stored.
+    DuplicateEmail,
+    /// The storage failed, for example, because the database is unreachable.
+    Unexpected(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl std::fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepositoryError::DuplicateEmail => write!(f, "The email address is already stored."),
+            RepositoryError::Unexpected(e) => write!(f, "Failed to access the storage: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RepositoryError::DuplicateEmail => None,
+            RepositoryError::Unexpected(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+/// Our data access layer (DAL) for subscribers
+///
+/// Request handlers only know about this trait, and not about the storage behind it,
+/// which is a Postgres or a SQLite database, depending on configuration,
+/// and can be an in-memory store in tests.
+///
+/// `confirm` and `unsubscribe` return `Ok(false)` if there is no subscriber with the given id.
+#[async_trait::async_trait]
+pub trait SubscriberRepository: Send + Sync {
+    /// Stores a new subscriber, pending confirmation, and returns its id
+    async fn insert(
+        &self,
+        new_subscriber: &NewSubscriber,
+        review_reason: Option<&str>,
+    ) -> Result<Uuid, RepositoryError>;
+
+    /// Stores a batch of imported subscribers at once, except those whose email address
+    /// is already stored, which are left as they are
+    ///
+    /// The stored ones with `reconfirm` are queued for a confirmation email.
+    /// Returns whether each of them was stored, in order.
+    async fn import(
+        &self,
+        subscribers: &[ImportedSubscriber],
+    ) -> Result<Vec<bool>, RepositoryError>;
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<Subscriber>, RepositoryError>;
+
+    /// Lists the ids of the subscribers who are queued for a confirmation email,
+    /// oldest request first, for the email flow to send them
+    async fn confirmation_requests(&self) -> Result<Vec<Uuid>, RepositoryError>;
+
+    async fn confirm(&self, id: Uuid) -> Result<bool, RepositoryError>;
+
+    async fn unsubscribe(&self, id: Uuid) -> Result<bool, RepositoryError>;
+
+    /// Lists subscribers, optionally only those with the given status, oldest first
+    async fn list(
+        &self,
+        status: Option<SubscriptionStatus>,
+    ) -> Result<Vec<Subscriber>, RepositoryError>;
+
+    /// Streams the subscribers who match `filter`, oldest first
+    ///
+    /// Unlike `list`, the subscribers are read from the storage as the stream is polled,
+    /// rather than all at once, so that large lists can be exported in flat memory.
+    /// The stream holds a connection until it is done, or dropped.
+    fn export(
+        &self,
+        filter: SubscriberFilter,
+    ) -> BoxStream<'static, Result<Subscriber, RepositoryError>>;
+
+    /// Makes a round trip to the storage, for the readiness probe
+    ///
+    /// Returns an error if the storage can't be reached.
+    async fn check_health(&self) -> Result<StorageHealth, RepositoryError>;
+
+    /// Gets the current usage of the connection pool, if the storage is behind one
+    fn pool_usage(&self) -> Option<PoolUsage>;
+
+    /// Closes the storage's connections, once the queries in flight are done,
+    /// so that their locks are released before the app exits
+    async fn close(&self);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sqlx::migrate::MigrationType;
+
+    fn migration(version: i64, sql: &'static str) -> Migration {
+        Migration::new(
+            version,
+            "migration".into(),
+            MigrationType::Simple,
+            sql.into(),
+        )
+    }
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            checksum: migration.checksum.clone(),
+        }
+    }
+
+    #[test]
+    fn migrations_are_current_when_all_of_them_are_applied() {
+        let migrations = [migration(1, "SELECT 1;"), migration(2, "SELECT 2;")];
+        let applied: Vec<_> = migrations.iter().map(applied).collect();
+
+        assert!(migrations_are_current(&migrations, &applied));
+    }
+
+    #[test]
+    fn migrations_are_not_current_when_one_is_missing() {
+        let migrations = [migration(1, "SELECT 1;"), migration(2, "SELECT 2;")];
+        let applied = [applied(&migrations[0])];
+
+        assert!(!migrations_are_current(&migrations, &applied));
+    }
+
+    #[test]
+    fn migrations_are_not_current_when_an_applied_one_was_changed() {
+        let migrations = [migration(1, "SELECT 1;")];
+        let applied = [applied(&migration(1, "SELECT 'changed';"))];
+
+        assert!(!migrations_are_current(&migrations, &applied));
+    }
+
+    #[test]
+    fn filter_keeps_the_subscribers_in_its_status_and_date_range() {
+        let subscriber = |status, subscribed_at: &str| Subscriber {
+            id: Uuid::new_v4(),
+            email: String::from("ursula_le_guin@gmail.com"),
+            name: String::from("le guin"),
+            status,
+            subscribed_at: subscribed_at.parse().unwrap(),
+            review_reason: None,
+            tags: Vec::new(),
+        };
+        let filter = SubscriberFilter {
+            status: Some(SubscriptionStatus::Confirmed),
+            subscribed_since: Some("2024-01-01T00:00:00Z".parse().unwrap()),
+            subscribed_before: Some("2024-02-01T00:00:00Z".parse().unwrap()),
+        };
+
+        assert!(filter.matches(&subscriber(
+            SubscriptionStatus::Confirmed,
+            "2024-01-01T00:00:00Z"
+        )));
+        assert!(!filter.matches(&subscriber(
+            SubscriptionStatus::PendingConfirmation,
+            "2024-01-15T00:00:00Z"
+        )));
+        assert!(!filter.matches(&subscriber(
+            SubscriptionStatus::Confirmed,
+            "2023-12-31T23:59:59Z"
+        )));
+        assert!(!filter.matches(&subscriber(
+            SubscriptionStatus::Confirmed,
+            "2024-02-01T00:00:00Z"
+        )));
+        assert!(SubscriberFilter::default().matches(&subscriber(
+            SubscriptionStatus::Unsubscribed,
+            "2024-02-01T00:00:00Z"
+        )));
+    }
+
+    #[test]
+    fn pool_is_saturated_when_all_connections_are_in_use() {
+        assert!(!PoolUsage::new(10, 1, 10).saturated);
+        assert!(!PoolUsage::new(3, 0, 10).saturated);
+        assert_eq!(
+            PoolUsage {
+                in_use: 10,
+                idle: 0,
+                max_size: 10,
+                saturated: true,
+            },
+            PoolUsage::new(10, 0, 10)
+        );
+    }
+}
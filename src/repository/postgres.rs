@@ -0,0 +1,378 @@
+//! src/repository/postgres.rs
+
+use crate::database::POSTGRES_MIGRATOR;
+use crate::domain::{NewSubscriber, SubscriptionStatus};
+use crate::repository::{
+    migrations_are_current, ImportedSubscriber, PoolUsage, RepositoryError, StorageHealth,
+    Subscriber, SubscriberFilter, SubscriberRepository,
+};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+use sqlx::migrate::Migrate;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// A `SubscriberRepository` which stores subscribers in a Postgres database
+///
+/// It doesn't depend, nor is aware, of a potentially surrounding (web) framework,
+/// which is good. It just executes DB queries.
+/// Request handlers don't know about it either, they only know about the
+/// `SubscriberRepository` trait, so the storage can be swapped without touching them.
+#[derive(Clone, Debug)]
+pub struct PostgresSubscriberRepository {
+    pool: PgPool,
+}
+
+impl PostgresSubscriberRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Gets the underlying connection pool
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    async fn set_status(&self, id: Uuid, status: SubscriptionStatus) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"UPDATE subscriptions SET status = $1 WHERE id = $2"#,
+            status.as_str(),
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Logs the query error and converts it into a `RepositoryError`
+fn to_repository_error(e: sqlx::Error) -> RepositoryError {
+    match e {
+        sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => {
+            RepositoryError::DuplicateEmail
+        }
+        e => {
+            // Not `Debug`, because the details of database errors may contain the row values
+            tracing::error!("Failed to execute query: '{}'.", e);
+            RepositoryError::Unexpected(Box::new(e))
+        }
+    }
+}
+
+/// Builds a `Subscriber` out of the raw column values
+fn to_subscriber(
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: chrono::DateTime<Utc>,
+    review_reason: Option<String>,
+    tags: Vec<String>,
+) -> Result<Subscriber, RepositoryError> {
+    Ok(Subscriber {
+        id,
+        email,
+        name,
+        status: SubscriptionStatus::try_from(status)
+            .map_err(|e| RepositoryError::Unexpected(e.into()))?,
+        subscribed_at,
+        review_reason,
+        tags,
+    })
+}
+
+#[async_trait::async_trait]
+impl SubscriberRepository for PostgresSubscriberRepository {
+    #[tracing::instrument(
+        name = "Saving the new subscriber details in the database",
+        skip(self, new_subscriber)
+    )]
+    async fn insert(
+        &self,
+        new_subscriber: &NewSubscriber,
+        review_reason: Option<&str>,
+    ) -> Result<Uuid, RepositoryError> {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+                INSERT INTO subscriptions (id, email, name, subscribed_at, review_reason, status)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            id,
+            new_subscriber.email.as_ref(),
+            new_subscriber.name.as_ref(),
+            Utc::now(),
+            review_reason,
+            SubscriptionStatus::PendingConfirmation.as_str()
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(to_repository_error)?;
+
+        Ok(id)
+    }
+
+    #[tracing::instrument(
+        name = "Importing subscribers into the database",
+        skip(self, imported),
+        fields(count = imported.len())
+    )]
+    async fn import(&self, imported: &[ImportedSubscriber]) -> Result<Vec<bool>, RepositoryError> {
+        let ids: Vec<Uuid> = imported.iter().map(|_| Uuid::new_v4()).collect();
+        let emails: Vec<String> = imported
+            .iter()
+            .map(|subscriber| subscriber.new_subscriber.email.as_ref().to_owned())
+            .collect();
+        let names: Vec<String> = imported
+            .iter()
+            .map(|subscriber| subscriber.new_subscriber.name.as_ref().to_owned())
+            .collect();
+        let statuses: Vec<String> = imported
+            .iter()
+            .map(|subscriber| subscriber.status.as_str().to_owned())
+            .collect();
+        let subscribed_ats: Vec<DateTime<Utc>> = imported
+            .iter()
+            .map(|subscriber| subscriber.subscribed_at)
+            .collect();
+
+        // The whole batch takes a single round trip, and is stored, or not, as a whole
+        let mut transaction = self.pool.begin().await.map_err(to_repository_error)?;
+        let stored: HashSet<Uuid> = sqlx::query_scalar!(
+            r#"
+                INSERT INTO subscriptions (id, email, name, status, subscribed_at)
+                SELECT * FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[], $5::timestamptz[])
+                ON CONFLICT (email) DO NOTHING
+                RETURNING id
+            "#,
+            &ids,
+            &emails,
+            &names,
+            &statuses,
+            &subscribed_ats
+        )
+        .fetch_all(&mut *transaction)
+        .await
+        .map_err(to_repository_error)?
+        .into_iter()
+        .collect();
+
+        let (tag_ids, tags): (Vec<Uuid>, Vec<String>) = ids
+            .iter()
+            .zip(imported)
+            .filter(|(id, _)| stored.contains(id))
+            .flat_map(|(id, subscriber)| subscriber.tags.iter().map(|tag| (*id, tag.clone())))
+            .unzip();
+        sqlx::query!(
+            r#"
+                INSERT INTO subscriber_tags (subscriber_id, tag)
+                SELECT * FROM UNNEST($1::uuid[], $2::text[])
+                ON CONFLICT DO NOTHING
+            "#,
+            &tag_ids,
+            &tags
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(to_repository_error)?;
+
+        let reconfirm_ids: Vec<Uuid> = ids
+            .iter()
+            .zip(imported)
+            .filter(|(id, subscriber)| subscriber.reconfirm && stored.contains(id))
+            .map(|(id, _)| *id)
+            .collect();
+        sqlx::query!(
+            r#"
+                INSERT INTO confirmation_requests (subscriber_id, requested_at)
+                SELECT subscriber_id, now() FROM UNNEST($1::uuid[]) AS subscriber_id
+                ON CONFLICT DO NOTHING
+            "#,
+            &reconfirm_ids
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(to_repository_error)?;
+        transaction.commit().await.map_err(to_repository_error)?;
+
+        Ok(ids.iter().map(|id| stored.contains(id)).collect())
+    }
+
+    #[tracing::instrument(
+        name = "Finding a subscriber by email in the database",
+        skip(self, email)
+    )]
+    async fn find_by_email(&self, email: &str) -> Result<Option<Subscriber>, RepositoryError> {
+        let row = sqlx::query!(
+            r#"
+                SELECT id, email, name, status, subscribed_at, review_reason,
+                    ARRAY(
+                        SELECT tag FROM subscriber_tags
+                        WHERE subscriber_id = subscriptions.id
+                        ORDER BY tag
+                    ) AS "tags!"
+                FROM subscriptions
+                WHERE email = $1
+            "#,
+            email
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(to_repository_error)?;
+
+        row.map(|r| {
+            to_subscriber(
+                r.id,
+                r.email,
+                r.name,
+                r.status,
+                r.subscribed_at,
+                r.review_reason,
+                r.tags,
+            )
+        })
+        .transpose()
+    }
+
+    #[tracing::instrument(name = "Listing the confirmation requests in the database", skip(self))]
+    async fn confirmation_requests(&self) -> Result<Vec<Uuid>, RepositoryError> {
+        sqlx::query_scalar!(
+            r#"SELECT subscriber_id FROM confirmation_requests ORDER BY requested_at, subscriber_id"#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(to_repository_error)
+    }
+
+    #[tracing::instrument(name = "Confirming a subscriber in the database", skip(self))]
+    async fn confirm(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        self.set_status(id, SubscriptionStatus::Confirmed)
+            .await
+            .map_err(to_repository_error)
+    }
+
+    #[tracing::instrument(name = "Unsubscribing a subscriber in the database", skip(self))]
+    async fn unsubscribe(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        self.set_status(id, SubscriptionStatus::Unsubscribed)
+            .await
+            .map_err(to_repository_error)
+    }
+
+    #[tracing::instrument(name = "Listing subscribers in the database", skip(self))]
+    async fn list(
+        &self,
+        status: Option<SubscriptionStatus>,
+    ) -> Result<Vec<Subscriber>, RepositoryError> {
+        let rows = sqlx::query!(
+            r#"
+                SELECT id, email, name, status, subscribed_at, review_reason,
+                    ARRAY(
+                        SELECT tag FROM subscriber_tags
+                        WHERE subscriber_id = subscriptions.id
+                        ORDER BY tag
+                    ) AS "tags!"
+                FROM subscriptions
+                WHERE $1::text IS NULL OR status = $1
+                ORDER BY subscribed_at
+            "#,
+            status.map(|status| status.as_str())
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(to_repository_error)?;
+
+        rows.into_iter()
+            .map(|r| {
+                to_subscriber(
+                    r.id,
+                    r.email,
+                    r.name,
+                    r.status,
+                    r.subscribed_at,
+                    r.review_reason,
+                    r.tags,
+                )
+            })
+            .collect()
+    }
+
+    fn export(
+        &self,
+        filter: SubscriberFilter,
+    ) -> BoxStream<'static, Result<Subscriber, RepositoryError>> {
+        let pool = self.pool.clone();
+        async_stream::try_stream! {
+            let mut rows = sqlx::query!(
+                r#"
+                    SELECT id, email, name, status, subscribed_at, review_reason,
+                        ARRAY(
+                            SELECT tag FROM subscriber_tags
+                            WHERE subscriber_id = subscriptions.id
+                            ORDER BY tag
+                        ) AS "tags!"
+                    FROM subscriptions
+                    WHERE ($1::text IS NULL OR status = $1)
+                        AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+                        AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+                    ORDER BY subscribed_at
+                "#,
+                filter.status.map(|status| status.as_str()),
+                filter.subscribed_since,
+                filter.subscribed_before,
+            )
+            .fetch(&pool);
+            while let Some(r) = rows.try_next().await.map_err(to_repository_error)? {
+                yield to_subscriber(
+                    r.id,
+                    r.email,
+                    r.name,
+                    r.status,
+                    r.subscribed_at,
+                    r.review_reason,
+                    r.tags,
+                )?;
+            }
+        }
+        .boxed()
+    }
+
+    #[tracing::instrument(name = "Checking the health of the database", skip(self))]
+    async fn check_health(&self) -> Result<StorageHealth, RepositoryError> {
+        // Take the snapshot before we borrow a connection ourselves
+        let pool = self.pool_usage();
+
+        let mut connection = self.pool.acquire().await.map_err(to_repository_error)?;
+        let has_migrations_table: bool =
+            sqlx::query_scalar(r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL"#)
+                .fetch_one(&mut *connection)
+                .await
+                .map_err(to_repository_error)?;
+        let migrations_current = has_migrations_table
+            && migrations_are_current(
+                POSTGRES_MIGRATOR.iter(),
+                &connection
+                    .list_applied_migrations()
+                    .await
+                    .map_err(|e| RepositoryError::Unexpected(Box::new(e)))?,
+            );
+
+        Ok(StorageHealth {
+            pool,
+            migrations_current,
+        })
+    }
+
+    fn pool_usage(&self) -> Option<PoolUsage> {
+        Some(PoolUsage::new(
+            self.pool.size(),
+            self.pool.num_idle(),
+            self.pool.options().get_max_connections(),
+        ))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
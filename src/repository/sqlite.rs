@@ -0,0 +1,333 @@
+//! src/repository/sqlite.rs
+
+use crate::database::SQLITE_MIGRATOR;
+use crate::domain::{NewSubscriber, SubscriptionStatus};
+use crate::repository::{
+    migrations_are_current, ImportedSubscriber, PoolUsage, RepositoryError, StorageHealth,
+    Subscriber, SubscriberFilter, SubscriberRepository,
+};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+use sqlx::migrate::Migrate;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::Row;
+use uuid::Uuid;
+
+/// A `SubscriberRepository` which stores subscribers in a SQLite database file
+///
+/// It is meant for small self-hosted installs and for CI, which shouldn't need Postgres.
+///
+/// The `sqlx::query!` macros check queries against a single database at compile time,
+/// and that database is Postgres, so the queries here are checked at runtime instead.
+/// SQLite has no native UUID and timestamp types, so they are stored as TEXT.
+#[derive(Clone, Debug)]
+pub struct SqliteSubscriberRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteSubscriberRepository {
+    /// Opens the database, creating the file if it doesn't exist,
+    /// and applies the embedded SQLite migrations
+    ///
+    /// Unlike with Postgres, there is no separate database server to migrate by hand,
+    /// so the schema is always brought up to date on connect.
+    pub async fn connect(options: SqliteConnectOptions) -> Result<Self, sqlx::Error> {
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options.create_if_missing(true))
+            .await?;
+        SQLITE_MIGRATOR.run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Gets the underlying connection pool
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    async fn set_status(&self, id: Uuid, status: SubscriptionStatus) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(r#"UPDATE subscriptions SET status = ?1 WHERE id = ?2"#)
+            .bind(status.as_str())
+            .bind(id.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Logs the query error and converts it into a `RepositoryError`
+fn to_repository_error(e: sqlx::Error) -> RepositoryError {
+    match e {
+        sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => {
+            RepositoryError::DuplicateEmail
+        }
+        e => {
+            // Not `Debug`, because the details of database errors may contain the row values
+            tracing::error!("Failed to execute query: '{}'.", e);
+            RepositoryError::Unexpected(Box::new(e))
+        }
+    }
+}
+
+/// The columns of the `subscriptions` table, and the subscriber's tags, as a JSON array
+const SUBSCRIBER_COLUMNS: &str = r#"
+    id, email, name, status, subscribed_at, review_reason,
+    (
+        SELECT json_group_array(tag) FROM (
+            SELECT tag FROM subscriber_tags
+            WHERE subscriber_id = subscriptions.id
+            ORDER BY tag
+        )
+    ) AS tags
+"#;
+
+/// Builds a `Subscriber` out of a row with the `SUBSCRIBER_COLUMNS`
+fn to_subscriber(row: SqliteRow) -> Result<Subscriber, RepositoryError> {
+    let id: String = row.try_get("id").map_err(to_repository_error)?;
+    let status: String = row.try_get("status").map_err(to_repository_error)?;
+    let tags: String = row.try_get("tags").map_err(to_repository_error)?;
+    let subscribed_at: DateTime<Utc> = row.try_get("subscribed_at").map_err(to_repository_error)?;
+
+    Ok(Subscriber {
+        id: Uuid::parse_str(&id).map_err(|e| RepositoryError::Unexpected(Box::new(e)))?,
+        email: row.try_get("email").map_err(to_repository_error)?,
+        name: row.try_get("name").map_err(to_repository_error)?,
+        status: SubscriptionStatus::try_from(status)
+            .map_err(|e| RepositoryError::Unexpected(e.into()))?,
+        subscribed_at,
+        review_reason: row.try_get("review_reason").map_err(to_repository_error)?,
+        tags: serde_json::from_str(&tags).map_err(|e| RepositoryError::Unexpected(Box::new(e)))?,
+    })
+}
+
+#[async_trait::async_trait]
+impl SubscriberRepository for SqliteSubscriberRepository {
+    #[tracing::instrument(
+        name = "Saving the new subscriber details in the database",
+        skip(self, new_subscriber)
+    )]
+    async fn insert(
+        &self,
+        new_subscriber: &NewSubscriber,
+        review_reason: Option<&str>,
+    ) -> Result<Uuid, RepositoryError> {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            r#"
+                INSERT INTO subscriptions (id, email, name, subscribed_at, review_reason, status)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )
+        .bind(id.to_string())
+        .bind(new_subscriber.email.as_ref())
+        .bind(new_subscriber.name.as_ref())
+        .bind(Utc::now())
+        .bind(review_reason)
+        .bind(SubscriptionStatus::PendingConfirmation.as_str())
+        .execute(&self.pool)
+        .await
+        .map_err(to_repository_error)?;
+
+        Ok(id)
+    }
+
+    #[tracing::instrument(
+        name = "Importing subscribers into the database",
+        skip(self, imported),
+        fields(count = imported.len())
+    )]
+    async fn import(&self, imported: &[ImportedSubscriber]) -> Result<Vec<bool>, RepositoryError> {
+        // The whole batch is stored, or not, as a whole, and with a single write to the disk
+        let mut transaction = self.pool.begin().await.map_err(to_repository_error)?;
+        let requested_at = Utc::now();
+        let mut stored = Vec::with_capacity(imported.len());
+        for subscriber in imported {
+            let id = Uuid::new_v4().to_string();
+            let result = sqlx::query(
+                r#"
+                    INSERT INTO subscriptions (id, email, name, status, subscribed_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5)
+                    ON CONFLICT (email) DO NOTHING
+                "#,
+            )
+            .bind(&id)
+            .bind(subscriber.new_subscriber.email.as_ref())
+            .bind(subscriber.new_subscriber.name.as_ref())
+            .bind(subscriber.status.as_str())
+            .bind(subscriber.subscribed_at)
+            .execute(&mut *transaction)
+            .await
+            .map_err(to_repository_error)?;
+            let is_stored = result.rows_affected() > 0;
+            if is_stored && subscriber.reconfirm {
+                sqlx::query(
+                    r#"INSERT INTO confirmation_requests (subscriber_id, requested_at) VALUES (?1, ?2)"#,
+                )
+                .bind(&id)
+                .bind(requested_at)
+                .execute(&mut *transaction)
+                .await
+                .map_err(to_repository_error)?;
+            }
+            if is_stored {
+                for tag in &subscriber.tags {
+                    sqlx::query(
+                        r#"INSERT OR IGNORE INTO subscriber_tags (subscriber_id, tag) VALUES (?1, ?2)"#,
+                    )
+                    .bind(&id)
+                    .bind(tag)
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(to_repository_error)?;
+                }
+            }
+            stored.push(is_stored);
+        }
+        transaction.commit().await.map_err(to_repository_error)?;
+
+        Ok(stored)
+    }
+
+    #[tracing::instrument(
+        name = "Finding a subscriber by email in the database",
+        skip(self, email)
+    )]
+    async fn find_by_email(&self, email: &str) -> Result<Option<Subscriber>, RepositoryError> {
+        sqlx::query(&format!(
+            r#"
+                SELECT {}
+                FROM subscriptions
+                WHERE email = ?1
+            "#,
+            SUBSCRIBER_COLUMNS
+        ))
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(to_repository_error)?
+        .map(to_subscriber)
+        .transpose()
+    }
+
+    #[tracing::instrument(name = "Listing the confirmation requests in the database", skip(self))]
+    async fn confirmation_requests(&self) -> Result<Vec<Uuid>, RepositoryError> {
+        let ids: Vec<String> = sqlx::query_scalar(
+            r#"SELECT subscriber_id FROM confirmation_requests ORDER BY requested_at, subscriber_id"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(to_repository_error)?;
+
+        ids.iter()
+            .map(|id| Uuid::parse_str(id).map_err(|e| RepositoryError::Unexpected(Box::new(e))))
+            .collect()
+    }
+
+    #[tracing::instrument(name = "Confirming a subscriber in the database", skip(self))]
+    async fn confirm(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        self.set_status(id, SubscriptionStatus::Confirmed)
+            .await
+            .map_err(to_repository_error)
+    }
+
+    #[tracing::instrument(name = "Unsubscribing a subscriber in the database", skip(self))]
+    async fn unsubscribe(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        self.set_status(id, SubscriptionStatus::Unsubscribed)
+            .await
+            .map_err(to_repository_error)
+    }
+
+    #[tracing::instrument(name = "Listing subscribers in the database", skip(self))]
+    async fn list(
+        &self,
+        status: Option<SubscriptionStatus>,
+    ) -> Result<Vec<Subscriber>, RepositoryError> {
+        sqlx::query(&format!(
+            r#"
+                SELECT {}
+                FROM subscriptions
+                WHERE ?1 IS NULL OR status = ?1
+                ORDER BY subscribed_at
+            "#,
+            SUBSCRIBER_COLUMNS
+        ))
+        .bind(status.map(|status| status.as_str()))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(to_repository_error)?
+        .into_iter()
+        .map(to_subscriber)
+        .collect()
+    }
+
+    fn export(
+        &self,
+        filter: SubscriberFilter,
+    ) -> BoxStream<'static, Result<Subscriber, RepositoryError>> {
+        let pool = self.pool.clone();
+        // The timestamps are compared as Julian days, rather than as text,
+        // which would depend on how many digits their fractions of a second have
+        let query = format!(
+            r#"
+                SELECT {}
+                FROM subscriptions
+                WHERE (?1 IS NULL OR status = ?1)
+                    AND (?2 IS NULL OR julianday(subscribed_at) >= julianday(?2))
+                    AND (?3 IS NULL OR julianday(subscribed_at) < julianday(?3))
+                ORDER BY subscribed_at
+            "#,
+            SUBSCRIBER_COLUMNS
+        );
+        async_stream::try_stream! {
+            let mut rows = sqlx::query(&query)
+                .bind(filter.status.map(|status| status.as_str()))
+                .bind(filter.subscribed_since)
+                .bind(filter.subscribed_before)
+                .fetch(&pool);
+            while let Some(row) = rows.try_next().await.map_err(to_repository_error)? {
+                yield to_subscriber(row)?;
+            }
+        }
+        .boxed()
+    }
+
+    #[tracing::instrument(name = "Checking the health of the database", skip(self))]
+    async fn check_health(&self) -> Result<StorageHealth, RepositoryError> {
+        // Take the snapshot before we borrow a connection ourselves
+        let pool = self.pool_usage();
+
+        let mut connection = self.pool.acquire().await.map_err(to_repository_error)?;
+        let has_migrations_table: bool = sqlx::query_scalar(
+            r#"SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'"#,
+        )
+        .fetch_one(&mut *connection)
+        .await
+        .map_err(to_repository_error)?;
+        let migrations_current = has_migrations_table
+            && migrations_are_current(
+                SQLITE_MIGRATOR.iter(),
+                &connection
+                    .list_applied_migrations()
+                    .await
+                    .map_err(|e| RepositoryError::Unexpected(Box::new(e)))?,
+            );
+
+        Ok(StorageHealth {
+            pool,
+            migrations_current,
+        })
+    }
+
+    fn pool_usage(&self) -> Option<PoolUsage> {
+        Some(PoolUsage::new(
+            self.pool.size(),
+            self.pool.num_idle(),
+            self.pool.options().get_max_connections(),
+        ))
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
@@ -0,0 +1,267 @@
+//! src/request_id/middleware.rs
+
+use crate::request_id::{RequestId, REQUEST_ID_HEADER};
+use actix_web::body::{to_bytes, EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::{HttpMessage, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+/// Middleware which gives every request a `RequestId`, and returns it to the client
+///
+/// The id is taken from the `X-Request-Id` header if `trust_incoming` is set and it is
+/// well-formed, which is only safe behind a proxy that sets the header itself.
+/// Otherwise, a new one is generated.
+///
+/// It is echoed in the `X-Request-Id` response header, and added to the body of error responses,
+/// which become JSON objects: `{"error": "Bad Request", "request_id": "..."}`,
+/// or, if they already were JSON objects, just get the `request_id` field.
+///
+/// Wrap it around `TracingLogger`, so that the root span is built with the request id.
+#[derive(Clone, Copy, Debug)]
+pub struct PropagateRequestId {
+    trust_incoming: bool,
+}
+
+impl PropagateRequestId {
+    pub fn new(trust_incoming: bool) -> Self {
+        Self { trust_incoming }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for PropagateRequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = PropagateRequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PropagateRequestIdMiddleware {
+            service: Rc::new(service),
+            trust_incoming: self.trust_incoming,
+        }))
+    }
+}
+
+pub struct PropagateRequestIdMiddleware<S> {
+    service: Rc<S>,
+    trust_incoming: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for PropagateRequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let incoming = self
+            .trust_incoming
+            .then(|| req.headers().get(REQUEST_ID_HEADER))
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .and_then(RequestId::parse);
+        let request_id = incoming.unwrap_or_else(RequestId::generate);
+        req.extensions_mut().insert(request_id.clone());
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            match request_id.clone().scope(service.call(req)).await {
+                Ok(response) if !is_error(response.status()) => {
+                    let mut response = response.map_into_left_body();
+                    insert_header(response.headers_mut(), &request_id);
+                    Ok(response)
+                }
+                Ok(response) => {
+                    let (request, response) = response.into_parts();
+                    let response = with_request_id(response, &request_id).await;
+                    Ok(ServiceResponse::new(request, response).map_into_right_body())
+                }
+                // Errors of inner middleware, such as `IpRateLimit`, are turned into responses
+                // further up, where we can't touch them, so we set the response they turn into
+                Err(e) => {
+                    let response = with_request_id(e.error_response(), &request_id).await;
+                    Err(InternalError::from_response(e, response).into())
+                }
+            }
+        })
+    }
+}
+
+/// Adds the request id to an error response, in the header and in the body,
+/// which is rewritten into a JSON object
+async fn with_request_id<B: MessageBody>(
+    response: HttpResponse<B>,
+    request_id: &RequestId,
+) -> HttpResponse {
+    let status = response.status();
+    let (mut response, body) = response.into_parts();
+    let body = to_bytes(body).await.unwrap_or_default();
+
+    let mut error = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(serde_json::Value::Object(error)) => error,
+        _ => {
+            let message = match std::str::from_utf8(&body) {
+                Ok(message) if !message.trim().is_empty() => message.trim().to_string(),
+                _ => canonical_reason(status),
+            };
+            serde_json::Map::from_iter([(String::from("error"), message.into())])
+        }
+    };
+    error.insert(
+        String::from("request_id"),
+        request_id.as_str().to_string().into(),
+    );
+
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    insert_header(response.headers_mut(), request_id);
+
+    response
+        .set_body(serde_json::Value::Object(error).to_string())
+        .map_into_boxed_body()
+}
+
+fn insert_header(headers: &mut HeaderMap, request_id: &RequestId) {
+    headers.insert(
+        HeaderName::from_static(REQUEST_ID_HEADER),
+        HeaderValue::from_str(request_id.as_str())
+            .expect("A well-formed request id is a valid header value."),
+    );
+}
+
+fn is_error(status: StatusCode) -> bool {
+    status.is_client_error() || status.is_server_error()
+}
+
+fn canonical_reason(status: StatusCode) -> String {
+    status.canonical_reason().unwrap_or("Error").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    #[actix_web::test]
+    async fn error_responses_get_the_request_id_in_their_body() {
+        let app = init_service(
+            App::new()
+                .wrap(PropagateRequestId::new(true))
+                .route("/empty", web::get().to(HttpResponse::BadRequest))
+                .route(
+                    "/text",
+                    web::get().to(|| async { HttpResponse::BadRequest().body("Invalid input") }),
+                )
+                .route(
+                    "/json",
+                    web::get().to(|| async {
+                        HttpResponse::ServiceUnavailable()
+                            .json(serde_json::json!({"status": "down"}))
+                    }),
+                ),
+        )
+        .await;
+
+        let mut bodies = Vec::new();
+        for uri in ["/empty", "/text", "/json"] {
+            let request = TestRequest::get()
+                .uri(uri)
+                .insert_header((REQUEST_ID_HEADER, "req-1"))
+                .to_request();
+            let response = call_service(&app, request).await;
+            assert_eq!("req-1", response.headers().get(REQUEST_ID_HEADER).unwrap());
+            let body: serde_json::Value = read_body_json(response).await;
+            bodies.push(body);
+        }
+
+        assert_eq!(
+            serde_json::json!({"error": "Bad Request", "request_id": "req-1"}),
+            bodies[0]
+        );
+        assert_eq!(
+            serde_json::json!({"error": "Invalid input", "request_id": "req-1"}),
+            bodies[1]
+        );
+        assert_eq!(
+            serde_json::json!({"status": "down", "request_id": "req-1"}),
+            bodies[2]
+        );
+    }
+
+    #[actix_web::test]
+    async fn the_request_id_is_available_to_handlers() {
+        let app = init_service(App::new().wrap(PropagateRequestId::new(false)).route(
+            "/",
+            web::get().to(|request_id: RequestId| async move {
+                assert_eq!(Some(&request_id), RequestId::current().as_ref());
+                HttpResponse::Ok().body(request_id.to_string())
+            }),
+        ))
+        .await;
+        let request = TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "req-1"))
+            .to_request();
+
+        let response = call_service(&app, request).await;
+
+        // The incoming id isn't trusted, so a new one is generated
+        let echoed = response
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_ne!("req-1", echoed);
+        assert_eq!(echoed, actix_web::test::read_body(response).await);
+    }
+
+    #[actix_web::test]
+    async fn errors_of_inner_middleware_get_the_request_id() {
+        let app = init_service(
+            App::new()
+                .wrap_fn(|_, _| async {
+                    Err::<ServiceResponse, _>(actix_web::error::ErrorTooManyRequests("Slow down"))
+                })
+                .wrap(PropagateRequestId::new(true))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = TestRequest::get()
+            .uri("/")
+            .insert_header((REQUEST_ID_HEADER, "req-1"))
+            .to_request();
+
+        let error = app.call(request).await.unwrap_err();
+
+        let response = error.error_response();
+        assert_eq!(429, response.status().as_u16());
+        assert_eq!("req-1", response.headers().get(REQUEST_ID_HEADER).unwrap());
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::json!({"error": "Slow down", "request_id": "req-1"}),
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! src/request_id/mod.rs
+
+mod middleware;
+
+pub use middleware::*;
+
+use crate::telemetry::set_remote_parent;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use std::future::{ready, Future, Ready};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span};
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use uuid::Uuid;
+
+/// The header in which we receive and return the request id
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The longest incoming request id that we accept
+const MAX_LEN: usize = 64;
+
+tokio::task_local! {
+    /// The id of the request that the current task is handling
+    static CURRENT: RequestId;
+}
+
+/// The id of a request, which we share with the client, so that support can find it in the logs
+///
+/// It is a random UUID, unless the client sent a well-formed one of its own, and we trust it.
+/// Get it in a request handler with the `RequestId` extractor, or with `RequestId::current()`
+/// anywhere within the request, including the background jobs started with `spawn`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// Accepts an incoming request id if it is well-formed
+    ///
+    /// It has to be 1 to 64 ASCII letters, digits, `-`, `_` or `.`,
+    /// so that it is safe to log and to echo back in a header.
+    pub fn parse(value: &str) -> Option<Self> {
+        let is_well_formed = !value.is_empty()
+            && value.len() <= MAX_LEN
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+        is_well_formed.then(|| Self(value.to_string()))
+    }
+
+    /// Gets the id of the request that the current task is handling, if any
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|request_id| request_id.clone()).ok()
+    }
+
+    /// Runs `future` with `self` as the current request id
+    pub(crate) async fn scope<F: Future>(self, future: F) -> F::Output {
+        CURRENT.scope(self, future).await
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<RequestId>().cloned().ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError("The request id middleware is missing.")
+        }))
+    }
+}
+
+/// Spawns a background job which keeps the request id and the tracing span of the current request
+///
+/// Use it instead of `tokio::spawn` for work which outlives the request, such as sending
+/// confirmation emails, so that its logs, and the emails, can be traced back to the request.
+/// Use `Shutdown::spawn` instead for jobs which the app should wait for before it exits.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(in_current_request(future))
+}
+
+/// Wraps `future` so that it keeps the request id and the tracing span of the current request,
+/// wherever it is run
+pub(crate) fn in_current_request<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let future = future.instrument(Span::current());
+    let request_id = RequestId::current();
+
+    async move {
+        match request_id {
+            Some(request_id) => request_id.scope(future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// Builds the root span of a request like `DefaultRootSpanBuilder` does,
+/// but its `request_id` is our `RequestId`, which the client sees,
+/// and not the one that `TracingLogger` generates.
+///
+/// It requires the `PropagateRequestId` middleware to be wrapped around `TracingLogger`.
+pub struct RequestIdRootSpanBuilder;
+
+impl RootSpanBuilder for RequestIdRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(RequestId::generate);
+        let route = request
+            .match_pattern()
+            .unwrap_or_else(|| String::from("default"));
+        let user_agent = request
+            .headers()
+            .get("User-Agent")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        let connection_info = request.connection_info();
+
+        let span = tracing::info_span!(
+            "HTTP request",
+            http.method = %request.method(),
+            http.route = %route,
+            http.scheme = %connection_info.scheme(),
+            http.host = %connection_info.host(),
+            http.client_ip = %connection_info.realip_remote_addr().unwrap_or(""),
+            http.user_agent = %user_agent,
+            http.target = %request.uri().path_and_query().map(|p| p.as_str()).unwrap_or(""),
+            http.status_code = tracing::field::Empty,
+            otel.name = %format!("HTTP {} {}", request.method(), route),
+            otel.kind = "server",
+            otel.status_code = tracing::field::Empty,
+            trace_id = tracing::field::Empty,
+            request_id = %request_id,
+            exception.message = tracing::field::Empty,
+            exception.details = tracing::field::Empty,
+        );
+        set_remote_parent(&span, request.headers());
+
+        span
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::uuid("4bf92f35-77b3-4a6a-a3ce-929d0e0e4736")]
+    #[case::opaque("req_01H.abc-DEF")]
+    #[case::longest(&"a".repeat(64))]
+    fn well_formed_request_ids_are_accepted(#[case] value: &str) {
+        assert_eq!(
+            Some(value),
+            RequestId::parse(value).as_ref().map(RequestId::as_str)
+        );
+    }
+
+    #[rstest]
+    #[case::empty("")]
+    #[case::too_long(&"a".repeat(65))]
+    #[case::whitespace("req 1")]
+    #[case::line_break("req\r\nX-Injected: 1")]
+    #[case::non_ascii("réq")]
+    fn malformed_request_ids_are_rejected(#[case] value: &str) {
+        assert_eq!(None, RequestId::parse(value));
+    }
+
+    #[tokio::test]
+    async fn background_jobs_keep_the_request_id() {
+        let request_id = RequestId::parse("req-1").unwrap();
+
+        let in_job = request_id
+            .clone()
+            .scope(async { spawn(async { RequestId::current() }).await.unwrap() })
+            .await;
+
+        assert_eq!(Some(request_id), in_job);
+        assert_eq!(None, spawn(async { RequestId::current() }).await.unwrap());
+    }
+}
@@ -0,0 +1,51 @@
+//! src/routes/admin.rs
+
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+
+/// The token which the admin endpoints require
+///
+/// The endpoints are disabled if there is no token; otherwise, requests have to carry it
+/// as a bearer token in the `Authorization` header.
+#[derive(Clone)]
+pub struct AdminToken(pub Option<Secret<String>>);
+
+impl AdminToken {
+    /// Checks the request's bearer token, and returns the response to send if it is refused
+    pub fn authorize(&self, req: &HttpRequest) -> Result<(), HttpResponse> {
+        let Some(token) = &self.0 else {
+            return Err(HttpResponse::NotFound().finish());
+        };
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        match bearer {
+            Some(bearer)
+                if constant_time_eq(bearer.as_bytes(), token.expose_secret().as_bytes()) =>
+            {
+                Ok(())
+            }
+            _ => Err(HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .finish()),
+        }
+    }
+}
+
+/// The state of the subscriber import and export endpoints, which handlers get as
+/// `Data<SubscribersAdmin>`
+///
+/// Their token isn't the log filter's, since they read and write the whole list of subscribers.
+#[derive(Clone)]
+pub struct SubscribersAdmin {
+    pub token: AdminToken,
+}
+
+/// Compares the secrets in constant time, so that response times don't leak them
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,235 @@
+//! src/routes/export_subscribers.rs
+
+use crate::domain::SubscriptionStatus;
+use crate::export::{encode, parse_timestamp, ExportError, ExportFormat};
+use crate::repository::{SubscriberFilter, SubscriberRepository};
+use crate::routes::SubscribersAdmin;
+use crate::shutdown::Shutdown;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// How many exports can run at once, since each of them holds a database connection
+const MAX_CONCURRENT_EXPORTS: usize = 2;
+/// How long an export can take, so that a client which reads it slowly, or not at all,
+/// doesn't hold a database connection indefinitely
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How many encoded subscribers are read ahead of the client
+const BUFFERED_CHUNKS: usize = 64;
+
+/// The limits on the exports, which handlers get as `Data<ExportLimits>`
+///
+/// It is shared by all the workers of a server.
+#[derive(Clone)]
+pub struct ExportLimits {
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+    shutdown: Shutdown,
+}
+
+impl ExportLimits {
+    /// Exports are also aborted once `shutdown` is triggered
+    pub fn new(shutdown: Shutdown) -> Self {
+        Self::with_limits(MAX_CONCURRENT_EXPORTS, EXPORT_TIMEOUT, shutdown)
+    }
+
+    fn with_limits(max_concurrent: usize, timeout: Duration, shutdown: Shutdown) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            timeout,
+            shutdown,
+        }
+    }
+
+    /// Returns `None` if as many exports as allowed are already running
+    fn start(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().try_acquire_owned().ok()
+    }
+
+    /// Reads `chunks` in a background job, which ends when the time runs out, or the app
+    /// shuts down, whether or not the client is still reading, and releases `permit`
+    ///
+    /// The client reads the returned stream, which ends with the error that aborted the job, if any.
+    fn run<S>(
+        &self,
+        chunks: S,
+        permit: OwnedSemaphorePermit,
+    ) -> impl Stream<Item = Result<Vec<u8>, ExportError>>
+    where
+        S: Stream<Item = Result<Vec<u8>, ExportError>> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+        let deadline = Instant::now() + self.timeout;
+        let shutdown = self.shutdown.clone();
+        let job = self.shutdown.spawn(async move {
+            let _permit = permit;
+            let forward = async {
+                let mut chunks = std::pin::pin!(chunks);
+                while let Some(chunk) = chunks.next().await {
+                    let is_error = chunk.is_err();
+                    // The client is gone if the receiver is dropped
+                    if sender.send(chunk).await.is_err() || is_error {
+                        break;
+                    }
+                }
+            };
+            tokio::select! {
+                outcome = tokio::time::timeout_at(deadline, forward) => {
+                    outcome.map_err(|_| ExportError::TimedOut)
+                }
+                _ = shutdown.requested() => Err(ExportError::Aborted),
+            }
+        });
+
+        let received = stream::unfold(receiver, |mut receiver| async {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        });
+        let outcome = stream::once(async move {
+            match job.await {
+                Ok(outcome) => outcome.err(),
+                Err(_) => Some(ExportError::Aborted),
+            }
+        })
+        .filter_map(|error| async move { error.map(Err) });
+        received.chain(outcome)
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    status: Option<SubscriptionStatus>,
+    subscribed_since: Option<String>,
+    subscribed_before: Option<String>,
+}
+
+impl ExportQuery {
+    fn filter(&self) -> Result<SubscriberFilter, String> {
+        let parse = |value: &Option<String>| value.as_deref().map(parse_timestamp).transpose();
+        Ok(SubscriberFilter {
+            status: self.status,
+            subscribed_since: parse(&self.subscribed_since)?,
+            subscribed_before: parse(&self.subscribed_before)?,
+        })
+    }
+}
+
+/// Export the subscribers, to back up the list or to load it into other tools
+///
+/// This is a request handler for the `GET /admin/subscribers/export` endpoint.
+///
+/// The `format` query parameter is `csv`, the default, or `ndjson`. The subscribers can be
+/// filtered by `status`, and by the `subscribed_since` and `subscribed_before` dates,
+/// or RFC 3339 timestamps.
+///
+/// The subscribers are streamed into the response body as they are read from the database.
+/// Once it has started, a failure can only cut the response short, so it isn't chunked to
+/// the end, and clients can tell that the export is incomplete.
+///
+/// Only a couple of exports run at once, and each of them is cut short after ten minutes,
+/// or when the app shuts down. Responds with a `503 Service Unavailable` if too many are running.
+#[tracing::instrument(name = "Exporting subscribers", skip(req, repository, admin, limits))]
+pub async fn export_subscribers(
+    req: HttpRequest,
+    repository: web::Data<dyn SubscriberRepository>,
+    admin: web::Data<SubscribersAdmin>,
+    limits: web::Data<ExportLimits>,
+) -> HttpResponse {
+    if let Err(response) = admin.token.authorize(&req) {
+        return response;
+    }
+    // Parsed once authorized, so that callers without the token can't probe the parameters
+    let query = match web::Query::<ExportQuery>::from_query(req.query_string()) {
+        Ok(query) => query.into_inner(),
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let Some(permit) = limits.start() else {
+        return HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, 60))
+            .body("Too many exports are running, try again later.");
+    };
+
+    let mut subscribers = repository.export(filter).peekable();
+    // Wait for the first subscriber, so that an unreachable database is still an error response
+    if let Some(Err(_)) = Pin::new(&mut subscribers).peek().await {
+        return HttpResponse::InternalServerError().finish();
+    }
+    let format = query.format;
+    let body = limits
+        .run(encode(subscribers, format), permit)
+        .map_ok(web::Bytes::from)
+        .inspect_err(|e| tracing::error!("Failed to export the subscribers: {}", e));
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                r#"attachment; filename="subscribers.{}""#,
+                format.extension()
+            ),
+        ))
+        .streaming(body.boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures_util::stream::BoxStream;
+
+    fn chunks(count: usize) -> BoxStream<'static, Result<Vec<u8>, ExportError>> {
+        stream::iter((0..count).map(|i| Ok(vec![i as u8]))).boxed()
+    }
+
+    #[tokio::test]
+    async fn the_chunks_are_forwarded_and_the_permit_released() {
+        let limits = ExportLimits::with_limits(1, EXPORT_TIMEOUT, Shutdown::new(Duration::ZERO));
+        let permit = limits.start().unwrap();
+        assert!(limits.start().is_none());
+
+        let exported: Vec<_> = limits.run(chunks(3), permit).try_collect().await.unwrap();
+
+        assert_eq!(vec![vec![0], vec![1], vec![2]], exported);
+        assert!(limits.start().is_some());
+    }
+
+    #[tokio::test]
+    async fn an_export_which_is_not_read_is_cut_short_and_releases_its_permit() {
+        let limits =
+            ExportLimits::with_limits(1, Duration::from_millis(50), Shutdown::new(Duration::ZERO));
+        let permit = limits.start().unwrap();
+        // More chunks than are buffered, so the job waits for the client
+        let mut exported = Box::pin(limits.run(chunks(BUFFERED_CHUNKS + 10), permit));
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert!(limits.start().is_some());
+        let rest: Vec<_> = exported.by_ref().collect().await;
+        assert_eq!(BUFFERED_CHUNKS + 1, rest.len());
+        assert!(matches!(rest.last(), Some(Err(ExportError::TimedOut))));
+    }
+
+    #[tokio::test]
+    async fn exports_are_aborted_on_shutdown() {
+        let shutdown = Shutdown::new(Duration::ZERO);
+        let limits = ExportLimits::with_limits(1, EXPORT_TIMEOUT, shutdown.clone());
+        let permit = limits.start().unwrap();
+        let exported = limits.run(stream::pending().boxed(), permit);
+
+        shutdown.trigger();
+        let exported: Vec<_> = exported.collect().await;
+
+        assert_eq!(1, exported.len());
+        assert!(matches!(exported[0], Err(ExportError::Aborted)));
+    }
+}
@@ -1,11 +1,159 @@
 //! src/routes/health_check.rs
 
-use actix_web::HttpResponse;
+use crate::email_client::EmailClient;
+use crate::repository::{StorageHealth, SubscriberRepository};
+use actix_web::{web, HttpResponse};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
 
 /// Health check
 ///
 /// This is a request handler for the `GET /health_check` endpoint.
+///
+/// It is our *liveness* probe: it only tells that the process is up and serving requests,
+/// regardless of the state of our dependencies, so that the orchestrator doesn't restart
+/// the app just because, say, the database is briefly unreachable.
 pub async fn health_check() -> HttpResponse {
     tracing::debug!("Health check is working!");
     HttpResponse::Ok().finish()
 }
+
+/// What the readiness probe checks
+///
+/// Every dependency has to respond within `timeout`.
+/// The email provider is only probed if `probe_email_transport` is set,
+/// because it is an external service which we may not want to hit that often.
+#[derive(Clone, Debug)]
+pub struct ReadinessCheck {
+    pub timeout: Duration,
+    pub probe_email_transport: bool,
+}
+
+/// The body of a `GET /ready` response
+#[derive(Debug, serde::Serialize)]
+pub struct ReadinessReport {
+    pub status: ReadinessStatus,
+    pub components: BTreeMap<&'static str, ComponentReport>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessStatus {
+    Ready,
+    NotReady,
+}
+
+/// The state of a single dependency
+///
+/// `error` tells why the component is down, and `storage` holds the details about the database.
+#[derive(Debug, serde::Serialize)]
+pub struct ComponentReport {
+    pub status: ComponentStatus,
+    pub latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<StorageHealth>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    Up,
+    Down,
+}
+
+/// Readiness check
+///
+/// This is a request handler for the `GET /ready` endpoint.
+///
+/// It is our *readiness* probe: it checks that the database is reachable and that its schema
+/// is up to date, and optionally that the email provider is reachable.
+/// Returns `200 OK` if all of them are, and `503 Service Unavailable` otherwise,
+/// so that the load balancer stops sending us traffic until they recover.
+/// Either way, the JSON body reports the status and latency of every component.
+#[tracing::instrument(name = "Checking readiness", skip(repository, email_client, readiness))]
+pub async fn ready(
+    repository: web::Data<dyn SubscriberRepository>,
+    email_client: web::Data<EmailClient>,
+    readiness: web::Data<ReadinessCheck>,
+) -> HttpResponse {
+    let mut components = BTreeMap::new();
+
+    let database = timed(readiness.timeout, repository.check_health()).await;
+    let database = match database {
+        Ok((Ok(health), latency_ms)) => ComponentReport {
+            status: if health.migrations_current {
+                ComponentStatus::Up
+            } else {
+                ComponentStatus::Down
+            },
+            latency_ms,
+            error: (!health.migrations_current)
+                .then(|| String::from("the database migrations are not up to date")),
+            storage: Some(health),
+        },
+        Ok((Err(e), latency_ms)) => {
+            tracing::error!("The database is not ready: {}", e);
+            down(latency_ms, "the database is unreachable")
+        }
+        Err(latency_ms) => down(latency_ms, "the database didn't respond in time"),
+    };
+    components.insert("database", database);
+
+    if readiness.probe_email_transport {
+        let email = match timed(readiness.timeout, email_client.probe()).await {
+            Ok((Ok(()), latency_ms)) => ComponentReport {
+                status: ComponentStatus::Up,
+                latency_ms,
+                error: None,
+                storage: None,
+            },
+            Ok((Err(e), latency_ms)) => {
+                tracing::error!("The email provider is not ready: {}", e);
+                down(latency_ms, "the email provider is unreachable")
+            }
+            Err(latency_ms) => down(latency_ms, "the email provider didn't respond in time"),
+        };
+        components.insert("email", email);
+    }
+
+    let is_ready = components
+        .values()
+        .all(|component| component.status == ComponentStatus::Up);
+    if is_ready {
+        HttpResponse::Ok().json(ReadinessReport {
+            status: ReadinessStatus::Ready,
+            components,
+        })
+    } else {
+        tracing::warn!("The app is not ready.");
+        HttpResponse::ServiceUnavailable().json(ReadinessReport {
+            status: ReadinessStatus::NotReady,
+            components,
+        })
+    }
+}
+
+/// Awaits `check` for up to `timeout`
+///
+/// Returns the output with the latency in milliseconds, or just the latency if it timed out.
+async fn timed<T>(timeout: Duration, check: impl Future<Output = T>) -> Result<(T, u128), u128> {
+    let start = Instant::now();
+    let output = tokio::time::timeout(timeout, check).await;
+    let latency_ms = start.elapsed().as_millis();
+
+    output
+        .map(|output| (output, latency_ms))
+        .map_err(|_| latency_ms)
+}
+
+fn down(latency_ms: u128, error: &str) -> ComponentReport {
+    ComponentReport {
+        status: ComponentStatus::Down,
+        latency_ms,
+        error: Some(error.to_string()),
+        storage: None,
+    }
+}
@@ -0,0 +1,83 @@
+//! src/routes/import_subscribers.rs
+
+use crate::import::{ImportError, ImportOptions, SubscriberImport};
+use crate::repository::SubscriberRepository;
+use crate::routes::SubscribersAdmin;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
+
+/// Import subscribers from another platform
+///
+/// This is a request handler for the `POST /admin/subscribers/import` endpoint.
+///
+/// The body is a CSV file, as described in `crate::import`, which is imported as it arrives.
+/// The subscribers are pending confirmation, unless the `confirmed=true` query parameter is set.
+/// With `reconfirm=true`, they are also queued for a confirmation email.
+///
+/// Responds with the report of every row, as a CSV attachment. The numbers of accepted,
+/// duplicate and rejected rows are in the `X-Import-Accepted`, `X-Import-Duplicates`
+/// and `X-Import-Rejected` headers.
+#[tracing::instrument(name = "Importing subscribers", skip(req, body, repository, admin))]
+pub async fn import_subscribers(
+    req: HttpRequest,
+    mut body: web::Payload,
+    repository: web::Data<dyn SubscriberRepository>,
+    admin: web::Data<SubscribersAdmin>,
+) -> HttpResponse {
+    if let Err(response) = admin.token.authorize(&req) {
+        return response;
+    }
+    // Parsed once authorized, so that callers without the token can't probe the options
+    let options = match web::Query::<ImportOptions>::from_query(req.query_string()) {
+        Ok(options) => options.into_inner(),
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    if let Err(e) = options.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let mut import = SubscriberImport::new(repository.into_inner(), options);
+    while let Some(chunk) = body.next().await {
+        let outcome = match chunk {
+            Ok(chunk) => import.feed(&chunk).await,
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        };
+        if let Err(e) = outcome {
+            return import_error_response(e);
+        }
+    }
+    let report = match import.finish().await {
+        Ok(report) => report,
+        Err(e) => return import_error_response(e),
+    };
+    tracing::info!(
+        accepted = report.accepted(),
+        duplicates = report.duplicates(),
+        rejected = report.rejected(),
+        "Imported subscribers."
+    );
+
+    let mut csv = Vec::new();
+    if let Err(e) = report.write_csv(&mut csv) {
+        tracing::error!("Failed to write the import report: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            r#"attachment; filename="import-report.csv""#,
+        ))
+        .insert_header(("X-Import-Accepted", report.accepted()))
+        .insert_header(("X-Import-Duplicates", report.duplicates()))
+        .insert_header(("X-Import-Rejected", report.rejected()))
+        .body(csv)
+}
+
+fn import_error_response(e: ImportError) -> HttpResponse {
+    match e {
+        ImportError::MissingColumns(_) => HttpResponse::BadRequest().body(e.to_string()),
+        ImportError::Repository(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
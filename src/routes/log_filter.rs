@@ -0,0 +1,56 @@
+//! src/routes/log_filter.rs
+
+use crate::routes::AdminToken;
+use crate::telemetry::LogFilterHandle;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// What the log filter admin endpoints need
+pub struct LogFilterAdmin {
+    pub handle: LogFilterHandle,
+    pub token: AdminToken,
+}
+
+/// Get the log filter
+///
+/// This is a request handler for the `GET /admin/log_filter` endpoint.
+///
+/// Responds with the current filter directives as plain text.
+pub async fn get_log_filter(req: HttpRequest, admin: web::Data<LogFilterAdmin>) -> HttpResponse {
+    if let Err(response) = admin.token.authorize(&req) {
+        return response;
+    }
+
+    match admin.handle.directives() {
+        Ok(directives) => HttpResponse::Ok().body(directives),
+        Err(e) => {
+            tracing::error!("Failed to read the log filter: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Change the log filter
+///
+/// This is a request handler for the `PUT /admin/log_filter` endpoint.
+///
+/// The body holds the new filter directives as plain text, in the `RUST_LOG` syntax,
+/// such as `info,zero2prod::routes=debug`, so we can debug one module in production
+/// without a restart. The change is lost on restart, though.
+#[tracing::instrument(name = "Changing the log filter", skip(req, admin))]
+pub async fn set_log_filter(
+    req: HttpRequest,
+    directives: String,
+    admin: web::Data<LogFilterAdmin>,
+) -> HttpResponse {
+    if let Err(response) = admin.token.authorize(&req) {
+        return response;
+    }
+
+    match admin.handle.set_directives(directives.trim()) {
+        Ok(()) => {
+            tracing::warn!("The log filter is changed.");
+            HttpResponse::Ok().finish()
+        }
+        Err(e) => HttpResponse::BadRequest().body(e),
+    }
+}
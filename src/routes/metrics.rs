@@ -0,0 +1,25 @@
+//! src/routes/metrics.rs
+
+use crate::metrics::Metrics;
+use crate::repository::SubscriberRepository;
+use actix_web::{web, HttpResponse};
+
+/// Prometheus metrics
+///
+/// This is a request handler for the `GET /metrics` endpoint.
+///
+/// It is served on the application port, or on a separate admin port if one is configured,
+/// so that the metrics don't have to be exposed to the public.
+/// The database pool gauges are updated on every scrape, as nothing else changes them.
+pub async fn export_metrics(
+    metrics: web::Data<Metrics>,
+    repository: web::Data<dyn SubscriberRepository>,
+) -> HttpResponse {
+    if let Some(pool) = repository.pool_usage() {
+        metrics.set_db_pool(pool);
+    }
+
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics.render())
+}
@@ -1,7 +1,19 @@
 //! src/routes/mod.rs
 
+mod admin;
+mod export_subscribers;
 mod health_check;
+mod import_subscribers;
+mod log_filter;
+mod metrics;
+mod redirect;
 mod subscriptions;
 
+pub use admin::*;
+pub use export_subscribers::*;
 pub use health_check::*;
+pub use import_subscribers::*;
+pub use log_filter::*;
+pub use metrics::*;
+pub use redirect::*;
 pub use subscriptions::*;
@@ -0,0 +1,68 @@
+//! src/routes/redirect.rs
+
+use actix_web::http::header::LOCATION;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// The port that plain HTTP requests are redirected to, for `redirect_to_https`
+#[derive(Clone, Copy, Debug)]
+pub struct HttpsPort(pub u16);
+
+/// Redirects any request to the same host and path, over HTTPS
+///
+/// It is permanent, and keeps the method and the body, with `308 Permanent Redirect`.
+pub async fn redirect_to_https(
+    request: HttpRequest,
+    https_port: web::Data<HttpsPort>,
+) -> HttpResponse {
+    HttpResponse::PermanentRedirect()
+        .insert_header((LOCATION, https_location(&request, https_port.0)))
+        .finish()
+}
+
+fn https_location(request: &HttpRequest, https_port: u16) -> String {
+    let connection_info = request.connection_info();
+    let host = connection_info.host();
+    // Strip the port of the plain HTTP listener, but not a part of an IPv6 address
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(':') && port.chars().all(|c| c.is_ascii_digit()) => {
+            name
+        }
+        _ => host,
+    };
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    match https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test::TestRequest;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::default_port("example.com:80", 443, "https://example.com/subscriptions?a=1")]
+    #[case::other_port("example.com:8080", 8443, "https://example.com:8443/subscriptions?a=1")]
+    #[case::no_port("example.com", 443, "https://example.com/subscriptions?a=1")]
+    #[case::ipv6("[::1]:8080", 8443, "https://[::1]:8443/subscriptions?a=1")]
+    fn requests_are_redirected_to_the_same_path_over_https(
+        #[case] host: &str,
+        #[case] https_port: u16,
+        #[case] expected: &str,
+    ) {
+        let request = TestRequest::post()
+            .uri("/subscriptions?a=1")
+            .insert_header(("Host", host))
+            .to_http_request();
+
+        assert_eq!(expected, https_location(&request, https_port));
+    }
+}
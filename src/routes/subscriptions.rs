@@ -1,15 +1,42 @@
 //! src/routes/subscriptions.rs
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use actix_web::{web, HttpResponse};
+use crate::abuse_protection::{client_ip, SignupProtection};
+use crate::domain::{EmailPolicy, NewSubscriber, PolicyVerdict, SubscriberEmail, SubscriberName};
+use crate::domain_verifier::DomainVerifier;
+use crate::redaction::Redacted;
+use crate::repository::SubscriberRepository;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
 use chrono::Utc;
-use sqlx::PgPool;
-use uuid::Uuid;
 
+/// The signup form
+///
+/// Besides the subscriber details, it contains fields that help us tell bots from humans:
+///  - `website` - a *honeypot* field, which is hidden from humans, so only bots fill it in;
+///  - `form_token` - the token that `form_token` issued when the form was shown;
+///  - `challenge_response` - the response to a CAPTCHA or proof-of-work challenge.
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    #[serde(default)]
+    website: String,
+    form_token: Option<String>,
+    challenge_response: Option<String>,
+}
+
+/// Issue a token which tells when the signup form was rendered
+///
+/// This is a request handler for the `GET /subscriptions/form_token` endpoint.
+///
+/// The page with the signup form fetches a token when it shows the form,
+/// and sends it back in the `form_token` field, so that we can tell how long it took
+/// to fill the form in. The token is signed, so it can't be forged or backdated.
+pub async fn form_token(protection: web::Data<SignupProtection>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .content_type("text/plain; charset=utf-8")
+        .body(protection.form_tokens.issue(Utc::now()))
 }
 
 /// Subscribe a new member
@@ -18,20 +45,44 @@ pub struct FormData {
 ///
 /// An orchestrator function which calls the required routines and translates their output
 /// into a proper HTTP response to the incoming HTTP request.
-/// We retrieve a connection from the application state (which is defined at startup).
+/// We retrieve the subscriber repository from the application state (which is defined at startup).
 #[allow(clippy::async_yields_async)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool),
+    skip(form, req, repository, email_policy, domain_verifier, protection),
     fields(
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = %Redacted::email(&form.email),
+        subscriber_name = %Redacted::name(&form.name)
     )
 )]
 pub async fn subscribe(
     web::Form(form): web::Form<FormData>,
-    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    repository: web::Data<dyn SubscriberRepository>,
+    email_policy: web::Data<EmailPolicy>,
+    domain_verifier: web::Data<dyn DomainVerifier>,
+    protection: web::Data<SignupProtection>,
 ) -> HttpResponse {
+    // Pretend that all went well when a bot is caught, so it doesn't learn to avoid the trap
+    if let Some(reason) = protection.detect_bot(&form.website, form.form_token.as_deref()) {
+        tracing::warn!("Dropped an automated signup: {}.", reason);
+        return HttpResponse::Ok().finish();
+    }
+
+    let ip = client_ip(&req, protection.use_forwarded_ip);
+    match protection
+        .challenge_verifier
+        .verify(form.challenge_response.as_deref(), &ip)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::Forbidden().finish(),
+        Err(e) => {
+            tracing::error!("Failed to verify the challenge response: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
     // Try to convert the `FormData` type into the `NewSubscriber` type
     let new_subscriber = match NewSubscriber::try_from(form) {
         Ok(new_subscriber) => new_subscriber,
@@ -40,7 +91,44 @@ pub async fn subscribe(
         Err(_) => return HttpResponse::BadRequest().finish(),
     };
 
-    match insert_subscriber(&new_subscriber, &pool).await {
+    if !protection
+        .domain_rate_limiter
+        .check(&new_subscriber.email.domain().to_lowercase())
+    {
+        tracing::warn!("Too many signups from the subscriber's email domain.");
+        return HttpResponse::TooManyRequests().finish();
+    }
+
+    // Check the email address against our signup policy
+    let review_reason = match email_policy.evaluate(&new_subscriber.email) {
+        PolicyVerdict::Accept => None,
+        PolicyVerdict::Flag(reason) => {
+            tracing::info!("The new subscriber is flagged for review: {}.", reason);
+            Some(reason)
+        }
+        PolicyVerdict::Reject(reason) => {
+            tracing::info!(
+                "The new subscriber is rejected by the email policy: {}.",
+                reason
+            );
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+
+    // Make sure that the domain can receive emails, but don't turn people away if we can't tell
+    match domain_verifier.verify(new_subscriber.email.domain()).await {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::info!("The new subscriber's email domain can't receive emails.");
+            return HttpResponse::BadRequest().finish();
+        }
+        Err(e) => tracing::warn!("Failed to verify the email domain: {}", e),
+    }
+
+    match repository
+        .insert(&new_subscriber, review_reason.as_deref())
+        .await
+    {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
@@ -63,43 +151,136 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
-/// Insert the new subscriber details in a Postgres database
-///
-/// This function doesn't depend, nor is aware, of a potentially surrounding (web) framework,
-/// which is good. The input parameters are not necessarily of a web-type.
-/// This function just executes a DB query.
-/// This is a quasi-DAL, although still not fully-independent (not fully-abstract).
-/// It is specialized for the Postgres database, though, so still not 100% generic,
-/// but at least it only knows about a DB and only works with a DB.
-/// Sure enough, if it were fully-abstract, it would be abstracted away from a DB as well,
-/// because data in general do not necessarily have to be persisted in a DB.
-/// So, there is room for improvement, for even better abstraction and separation of concerns,
-/// for even looser coupling, but is a step in the right direction.
-/// We could add a true DAL, because this is more of a concrete data-layer implementation than a DAL.
-#[tracing::instrument(
-    name = "Saving the new subscriber details in the database",
-    skip(new_subscriber, pool)
-)]
-async fn insert_subscriber(
-    new_subscriber: &NewSubscriber,
-    pool: &PgPool,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"
-            INSERT INTO subscriptions (id, email, name, subscribed_at)
-            VALUES ($1, $2, $3, $4)
-        "#,
-        Uuid::new_v4(),
-        new_subscriber.email.as_ref(),
-        new_subscriber.name.as_ref(),
-        Utc::now()
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: '{:?}'.", e);
-        e
-    })?;
-
-    Ok(())
+/// Handler tests which use an in-memory repository, so they don't need a database
+#[cfg(test)]
+mod tests {
+    use super::subscribe;
+
+    use crate::abuse_protection::{
+        FormTokens, NoopChallengeVerifier, RateLimiter, SignupProtection,
+    };
+    use crate::domain::{EmailPolicy, PolicyAction, PolicyRule, SubscriptionStatus};
+    use crate::domain_verifier::{DomainVerifier, NoopDomainVerifier};
+    use crate::repository::{InMemorySubscriberRepository, SubscriberRepository};
+    use crate::telemetry::{get_subscriber, get_tracer_provider, LogFormat, SharedBuffer};
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::web::{self, Data};
+    use actix_web::App;
+    use opentelemetry::trace::TracerProvider as _;
+    use rstest::rstest;
+    use secrecy::Secret;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Posts the form to a fresh instance of the handler and returns the response status
+    async fn post_form(repository: Arc<InMemorySubscriberRepository>, body: &str) -> u16 {
+        let email_policy = EmailPolicy {
+            allowed_domains: HashSet::new(),
+            denied_domains: PolicyRule::new(["denied.yq"], PolicyAction::Reject),
+            disposable_domains: PolicyRule::disposable_domains(None, PolicyAction::Reject),
+            role_local_parts: PolicyRule::new(["info"], PolicyAction::Flag),
+        };
+        let protection = SignupProtection {
+            ip_rate_limiter: Arc::new(RateLimiter::new(100, Duration::from_secs(60))),
+            domain_rate_limiter: Arc::new(RateLimiter::new(100, Duration::from_secs(60))),
+            use_forwarded_ip: false,
+            min_fill_time: Duration::ZERO,
+            form_tokens: FormTokens::new(Secret::new(String::from("form-token-key"))),
+            challenge_verifier: Arc::new(NoopChallengeVerifier),
+        };
+        let repository: Arc<dyn SubscriberRepository> = repository;
+        let domain_verifier: Arc<dyn DomainVerifier> = Arc::new(NoopDomainVerifier);
+
+        let app = init_service(
+            App::new()
+                .route("/subscriptions", web::post().to(subscribe))
+                .app_data(Data::from(repository))
+                .app_data(Data::new(email_policy))
+                .app_data(Data::from(domain_verifier))
+                .app_data(Data::new(protection)),
+        )
+        .await;
+        let request = TestRequest::post()
+            .uri("/subscriptions")
+            .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+            .set_payload(body.to_string())
+            .to_request();
+
+        call_service(&app, request).await.status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn subscribe_stores_a_valid_subscriber_as_pending_confirmation() {
+        let repository = Arc::new(InMemorySubscriberRepository::new());
+
+        let status = post_form(
+            repository.clone(),
+            "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        )
+        .await;
+
+        assert_eq!(200, status);
+        let subscriber = repository
+            .find_by_email("ursula_le_guin@gmail.com")
+            .await
+            .unwrap()
+            .expect("The subscriber wasn't stored.");
+        assert_eq!("le guin", subscriber.name);
+        assert_eq!(SubscriptionStatus::PendingConfirmation, subscriber.status);
+        assert_eq!(None, subscriber.review_reason);
+    }
+
+    #[tokio::test]
+    async fn subscribe_stores_the_review_reason_of_flagged_subscribers() {
+        let repository = Arc::new(InMemorySubscriberRepository::new());
+
+        let status = post_form(repository.clone(), "name=le%20guin&email=info%40gmail.com").await;
+
+        assert_eq!(200, status);
+        let subscriber = repository
+            .find_by_email("info@gmail.com")
+            .await
+            .unwrap()
+            .expect("The subscriber wasn't stored.");
+        assert!(subscriber.review_reason.is_some());
+    }
+
+    #[rstest]
+    #[case::invalid_email("name=le%20guin&email=definitely-not-an-email")]
+    #[case::denied_domain("name=le%20guin&email=ursula%40denied.yq")]
+    #[case::disposable_domain("name=le%20guin&email=ursula%40mailinator.com")]
+    #[tokio::test]
+    async fn subscribe_returns_400_and_stores_nothing_for_rejected_signups(#[case] body: &str) {
+        let repository = Arc::new(InMemorySubscriberRepository::new());
+
+        let status = post_form(repository.clone(), body).await;
+
+        assert_eq!(400, status);
+        assert!(repository.list(None).await.unwrap().is_empty());
+    }
+
+    #[rstest]
+    #[case::accepted("name=le%20guin&email=ursula_le_guin%40gmail.com")]
+    #[case::rejected("name=le%20guin&email=ursula_le_guin%40mailinator.com")]
+    #[tokio::test]
+    async fn subscribe_does_not_log_the_raw_email_or_name(#[case] body: &str) {
+        let buffer = SharedBuffer::default();
+        let tracer_provider = get_tracer_provider("test", None, Duration::from_secs(1)).unwrap();
+        let (subscriber, _) = get_subscriber(
+            "test",
+            "trace",
+            LogFormat::Bunyan,
+            buffer.clone(),
+            tracer_provider.tracer("test"),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        post_form(Arc::new(InMemorySubscriberRepository::new()), body).await;
+
+        let logs = buffer.contents();
+        assert!(logs.contains("subscriber_email"), "{}", logs);
+        assert!(!logs.contains("ursula_le_guin"), "{}", logs);
+        assert!(!logs.contains("le guin"), "{}", logs);
+    }
 }
@@ -0,0 +1,83 @@
+//! src/secrets.rs
+
+use secrecy::Secret;
+use std::path::PathBuf;
+
+/// Where secret settings, such as the database password, are fetched from
+///
+/// A secret setting is fetched from the provider when the configuration has the setting's
+/// `_file` counterpart instead, such as `database.password_file`, or
+/// `APP_DATABASE__PASSWORD_FILE` in the environment. Its value is the reference that
+/// the provider understands: a path for `FileSecretProvider`, or a key for a secret manager.
+///
+/// Errors must not contain the secret itself.
+pub trait SecretProvider {
+    fn fetch(&self, reference: &str) -> Result<Secret<String>, String>;
+}
+
+/// Reads secrets from files, such as Docker and Kubernetes secrets mounted in `/run/secrets`
+///
+/// Relative paths are resolved against `directory`, if set, and against the current
+/// directory otherwise. A trailing newline, which editors like to add, is not a part of the secret.
+#[derive(Clone, Debug, Default)]
+pub struct FileSecretProvider {
+    directory: Option<PathBuf>,
+}
+
+impl FileSecretProvider {
+    /// A provider which resolves relative paths against `directory`
+    ///
+    /// Tests use it with a temporary directory, to keep their secrets out of the configuration.
+    pub fn in_directory(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: Some(directory.into()),
+        }
+    }
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn fetch(&self, reference: &str) -> Result<Secret<String>, String> {
+        let path = match &self.directory {
+            Some(directory) => directory.join(reference),
+            None => PathBuf::from(reference),
+        };
+        let secret = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+
+        Ok(Secret::new(
+            secret.trim_end_matches(['\r', '\n']).to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claims::assert_err;
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn secrets_are_read_from_files_without_the_trailing_newline() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        std::fs::write(directory.join("db_password"), "s3cr3t\n").unwrap();
+        let provider = FileSecretProvider::in_directory(&directory);
+
+        let secret = provider.fetch("db_password").unwrap();
+        let absolute = FileSecretProvider::default()
+            .fetch(directory.join("db_password").to_str().unwrap())
+            .unwrap();
+
+        assert_eq!("s3cr3t", secret.expose_secret());
+        assert_eq!("s3cr3t", absolute.expose_secret());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_missing_file_is_an_error() {
+        let provider = FileSecretProvider::in_directory(std::env::temp_dir());
+
+        assert_err!(provider.fetch(&uuid::Uuid::new_v4().to_string()));
+    }
+}
@@ -0,0 +1,68 @@
+//! src/shutdown/middleware.rs
+
+use crate::shutdown::Shutdown;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+/// Middleware which counts the requests in flight, so that shutdown can wait for them
+///
+/// Wrap it around everything else, so that a request counts until its response is ready.
+pub struct TrackRequests {
+    shutdown: Shutdown,
+}
+
+impl TrackRequests {
+    pub fn new(shutdown: Shutdown) -> Self {
+        Self { shutdown }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for TrackRequests
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = TrackRequestsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TrackRequestsMiddleware {
+            service: Rc::new(service),
+            shutdown: self.shutdown.clone(),
+        }))
+    }
+}
+
+pub struct TrackRequestsMiddleware<S> {
+    service: Rc<S>,
+    shutdown: Shutdown,
+}
+
+impl<S, B> Service<ServiceRequest> for TrackRequestsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let in_flight = self.shutdown.requests.token();
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let outcome = service.call(req).await;
+            drop(in_flight);
+
+            outcome
+        })
+    }
+}
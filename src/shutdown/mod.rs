@@ -0,0 +1,214 @@
+//! src/shutdown/mod.rs
+
+mod middleware;
+
+pub use middleware::*;
+
+use crate::request_id::in_current_request;
+use actix_web::dev::ServerHandle;
+use futures_util::future::join_all;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// Coordinates a graceful shutdown of the app
+///
+/// When shutdown is triggered with `stop_servers`, the HTTP servers stop accepting
+/// new connections, and the requests in flight get to finish. Then, `drain_jobs` waits
+/// for the background jobs spawned with `spawn`. All of that has to be done within
+/// `grace_period` of the trigger: the phases share one deadline, rather than each getting
+/// a grace period of its own.
+///
+/// Long-running workers should wait on `requested` between their tasks, and stop,
+/// releasing their database locks, instead of starting another one.
+///
+/// Handlers get it as `Data<Shutdown>`. It is cheap to clone, and all the clones are linked.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    grace_period: Duration,
+    /// Set when the shutdown starts, by the first `trigger` or `drain_jobs`
+    deadline: Arc<OnceLock<Instant>>,
+    token: CancellationToken,
+    /// The requests in flight, which `TrackRequests` counts
+    requests: TaskTracker,
+    jobs: TaskTracker,
+}
+
+impl Shutdown {
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            grace_period,
+            deadline: Arc::new(OnceLock::new()),
+            token: CancellationToken::new(),
+            requests: TaskTracker::new(),
+            jobs: TaskTracker::new(),
+        }
+    }
+
+    /// How long the requests and the background jobs, together, get to finish
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// When the grace period runs out; the clock starts the first time it is asked for
+    pub fn deadline(&self) -> Instant {
+        *self
+            .deadline
+            .get_or_init(|| Instant::now() + self.grace_period)
+    }
+
+    /// Spawns a background job which shutdown waits for, for up to the grace period
+    ///
+    /// Like `request_id::spawn`, it keeps the request id and the tracing span of the current request.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.jobs.spawn(in_current_request(future))
+    }
+
+    /// Starts the shutdown, and the grace period; it can be called any number of times
+    pub fn trigger(&self) {
+        self.deadline();
+        self.token.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Completes once the shutdown is triggered
+    pub async fn requested(&self) {
+        self.token.cancelled().await;
+    }
+
+    /// Triggers the shutdown, and stops `servers` once their requests in flight are done,
+    /// or the grace period runs out
+    ///
+    /// The servers have to be running, that is, awaited, for this to complete.
+    /// Returns `false` if some requests, or connections, were still open when the grace period ran out.
+    pub async fn stop_servers(&self, servers: &[ServerHandle]) -> bool {
+        self.trigger();
+        join_all(servers.iter().map(|server| server.pause())).await;
+
+        // We wait for the requests ourselves, instead of leaving it to a graceful `stop`,
+        // because that can drop the connections before their workers are told to finish them
+        self.requests.close();
+        let is_drained = tokio::time::timeout_at(self.deadline(), self.requests.wait())
+            .await
+            .is_ok();
+        // A graceful stop still waits for the responses being sent, up to its own timeout
+        let stop = join_all(servers.iter().map(|server| server.stop(true)));
+        let is_stopped = tokio::time::timeout_at(self.deadline(), stop).await.is_ok();
+
+        is_drained && is_stopped
+    }
+
+    /// Waits for the background jobs to finish, until the grace period runs out
+    ///
+    /// Returns `false` if some of them were still running when the grace period ran out.
+    pub async fn drain_jobs(&self) -> bool {
+        self.jobs.close();
+        tokio::time::timeout_at(self.deadline(), self.jobs.wait())
+            .await
+            .is_ok()
+    }
+}
+
+/// Completes when the process is asked to stop, with `SIGTERM` or `Ctrl+C`
+pub async fn shutdown_signal() -> Result<(), std::io::Error> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = terminate.recv() => Ok(()),
+            outcome = tokio::signal::ctrl_c() => outcome,
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn shutdown_waits_for_the_background_jobs_to_finish() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let finished = Arc::new(AtomicBool::new(false));
+        let job_finished = finished.clone();
+        shutdown.spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            job_finished.store(true, Ordering::SeqCst);
+        });
+
+        shutdown.trigger();
+        let drained = shutdown.drain_jobs().await;
+
+        assert!(drained);
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn jobs_which_outlast_the_grace_period_are_reported() {
+        let shutdown = Shutdown::new(Duration::from_millis(50));
+        shutdown.spawn(tokio::time::sleep(Duration::from_secs(60)));
+
+        shutdown.trigger();
+
+        assert!(!shutdown.drain_jobs().await);
+    }
+
+    #[tokio::test]
+    async fn the_grace_period_is_shared_by_all_the_phases() {
+        let shutdown = Shutdown::new(Duration::from_millis(100));
+        shutdown.spawn(tokio::time::sleep(Duration::from_secs(60)));
+
+        shutdown.trigger();
+        // Stands in for the requests, which took most of the grace period
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        let started_at = Instant::now();
+        let drained = shutdown.drain_jobs().await;
+
+        assert!(!drained);
+        assert!(
+            started_at.elapsed() < Duration::from_millis(60),
+            "The jobs got a grace period of their own: {:?}.",
+            started_at.elapsed()
+        );
+    }
+
+    #[tokio::test]
+    async fn workers_are_told_to_stop_between_their_tasks() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let worker_shutdown = shutdown.clone();
+        let worker = shutdown.spawn(async move {
+            let mut tasks = 0;
+            while !worker_shutdown.is_triggered() {
+                tasks += 1;
+                tokio::select! {
+                    _ = worker_shutdown.requested() => {}
+                    _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+                }
+            }
+            tasks
+        });
+
+        tokio::time::sleep(Duration::from_millis(35)).await;
+        shutdown.trigger();
+
+        assert!(shutdown.drain_jobs().await);
+        assert!(worker.await.unwrap() > 0);
+    }
+}
@@ -1,36 +1,588 @@
 //! src/startup.rs
 
+use crate::abuse_protection::{ChallengeVerifier, IpRateLimit, SignupProtection};
+use crate::configuration::Settings;
+use crate::database::MigrationError;
+use crate::domain::EmailPolicy;
+use crate::domain_verifier::DomainVerifier;
 use crate::email_client::EmailClient;
-use crate::routes::{health_check, subscribe};
-use actix_web::dev::Server;
+use crate::metrics::{Metrics, RequestMetrics};
+use crate::reload::{LiveSettings, ReloadableSettings};
+use crate::repository::{MeteredSubscriberRepository, SubscriberRepository};
+use crate::request_id::{PropagateRequestId, RequestIdRootSpanBuilder};
+use crate::routes::{
+    export_metrics, export_subscribers, form_token, get_log_filter, health_check,
+    import_subscribers, ready, redirect_to_https, set_log_filter, subscribe, AdminToken,
+    ExportLimits, HttpsPort, LogFilterAdmin, ReadinessCheck, SubscribersAdmin,
+};
+use crate::shutdown::{Shutdown, TrackRequests};
+use crate::telemetry::LogFilterHandle;
+use crate::tls::{Hsts, Tls};
+use actix_web::dev::{Server, ServerHandle};
+use actix_web::middleware::Condition;
 use actix_web::web::{self, Data};
 use actix_web::{App, HttpServer};
-use sqlx::PgPool;
+use futures_util::future::try_join_all;
 use std::net::TcpListener;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
 use tracing_actix_web::TracingLogger;
 
+/// The whole app, wired from `Settings`: its web servers, its storage and its background workers
+///
+/// The servers listen as soon as it is built, so the ports are known, even when they are 0,
+/// which asks the OS for a free one. They serve requests once `run_until_stopped` is awaited.
+pub struct Application {
+    port: u16,
+    admin_port: Option<u16>,
+    redirect_port: Option<u16>,
+    repository: Arc<dyn SubscriberRepository>,
+    shutdown: Shutdown,
+    servers: Vec<Server>,
+    /// Such as the configuration and certificate reloaders, which are stopped with the servers
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// Builds an `Application`, with some of its components swapped for others, such as fakes in tests
+pub struct ApplicationBuilder {
+    configuration: Settings,
+    log_filter_handle: LogFilterHandle,
+    repository: Option<Arc<dyn SubscriberRepository>>,
+    domain_verifier: Option<Arc<dyn DomainVerifier>>,
+    challenge_verifier: Option<Arc<dyn ChallengeVerifier>>,
+}
+
+impl Application {
+    /// Builds the app from `configuration`; `log_filter_handle` changes the log filter of
+    /// the `tracing` subscriber, which has to be initialized first
+    pub async fn build(
+        configuration: Settings,
+        log_filter_handle: LogFilterHandle,
+    ) -> Result<Self, StartupError> {
+        Self::builder(configuration, log_filter_handle)
+            .build()
+            .await
+    }
+
+    pub fn builder(
+        configuration: Settings,
+        log_filter_handle: LogFilterHandle,
+    ) -> ApplicationBuilder {
+        ApplicationBuilder {
+            configuration,
+            log_filter_handle,
+            repository: None,
+            domain_verifier: None,
+            challenge_verifier: None,
+        }
+    }
+
+    /// The port that the app listens on
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The port of the admin server, if the configuration asks for one
+    pub fn admin_port(&self) -> Option<u16> {
+        self.admin_port
+    }
+
+    /// The port of the server which redirects to HTTPS, if the configuration asks for one
+    pub fn redirect_port(&self) -> Option<u16> {
+        self.redirect_port
+    }
+
+    pub fn repository(&self) -> Arc<dyn SubscriberRepository> {
+        self.repository.clone()
+    }
+
+    /// Stops the app, gracefully, when it is triggered
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Serves requests until the servers stop, or `shutdown` is triggered
+    ///
+    /// On shutdown, the requests in flight, and then the background jobs, get to finish,
+    /// before the workers are stopped and the database connections are closed.
+    /// That cleanup happens even if a server fails, and then its error is returned.
+    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+        let server_handles: Vec<ServerHandle> = self.servers.iter().map(Server::handle).collect();
+        let servers = try_join_all(self.servers);
+        tokio::pin!(servers);
+
+        let outcome = tokio::select! {
+            outcome = &mut servers => {
+                // The jobs are told to stop too, as there is nothing left to serve
+                self.shutdown.trigger();
+                outcome.map(|_| ())
+            }
+            _ = self.shutdown.requested() => {
+                tracing::info!("Shutting down; the requests and jobs in flight get to finish.");
+                // The servers have to keep running while they are being stopped,
+                // but they aren't waited for past the grace period
+                let deadline = self.shutdown.deadline();
+                let (is_drained, outcome) = tokio::join!(
+                    self.shutdown.stop_servers(&server_handles),
+                    tokio::time::timeout_at(deadline, &mut servers)
+                );
+                if !is_drained {
+                    tracing::warn!("Some requests didn't finish within the grace period.");
+                }
+                outcome.unwrap_or(Ok(Vec::new())).map(|_| ())
+            }
+        };
+        if let Err(e) = &outcome {
+            tracing::error!("A server failed: {}", e);
+        }
+
+        if !self.shutdown.drain_jobs().await {
+            tracing::warn!("Some background jobs didn't finish within the grace period.");
+        }
+        for worker in self.workers {
+            worker.abort();
+        }
+        self.repository.close().await;
+
+        outcome
+    }
+}
+
+impl ApplicationBuilder {
+    /// Uses `repository`, instead of connecting to the configured database
+    pub fn repository(mut self, repository: Arc<dyn SubscriberRepository>) -> Self {
+        self.repository = Some(repository);
+        self
+    }
+
+    pub fn domain_verifier(mut self, domain_verifier: Arc<dyn DomainVerifier>) -> Self {
+        self.domain_verifier = Some(domain_verifier);
+        self
+    }
+
+    pub fn challenge_verifier(mut self, challenge_verifier: Arc<dyn ChallengeVerifier>) -> Self {
+        self.challenge_verifier = Some(challenge_verifier);
+        self
+    }
+
+    /// Connects to the database, binds the listeners and starts the servers and the workers
+    pub async fn build(self) -> Result<Application, StartupError> {
+        let configuration = self.configuration;
+        let log_filter_handle = self.log_filter_handle;
+
+        let repository = match self.repository {
+            Some(repository) => repository,
+            None => {
+                if configuration.database.migrate_on_boot {
+                    migrate(&configuration).await?;
+                }
+                configuration.database.get_repository().await?
+            }
+        };
+
+        let metrics = Metrics::new();
+        let live_settings = LiveSettings::new(ReloadableSettings::from(&configuration));
+        let email_client = configuration
+            .email_client
+            .get_client(metrics.clone())
+            .map_err(|e| StartupError::Component("email_client.sender_email", e))?
+            .with_live_settings(live_settings.clone());
+        let email_policy = configuration
+            .email_policy
+            .get_policy()
+            .map_err(|e| StartupError::Component("email_policy", e.to_string()))?;
+        let domain_verifier = match self.domain_verifier {
+            Some(domain_verifier) => domain_verifier,
+            None => configuration
+                .domain_verification
+                .get_verifier()
+                .map_err(|e| StartupError::Component("domain_verification", e))?,
+        };
+        let mut signup_protection = configuration.abuse_protection.get_protection();
+        if let Some(challenge_verifier) = self.challenge_verifier {
+            signup_protection.challenge_verifier = challenge_verifier;
+        }
+        let readiness = configuration.readiness.get_check();
+        let tls = configuration
+            .application
+            .tls
+            .get_tls()
+            .map_err(|e| StartupError::Component("application.tls", e.to_string()))?;
+        let shutdown = configuration.application.get_shutdown();
+
+        let mut workers = Vec::new();
+        // Reload the settings which can change at runtime, such as the rate limits, on `SIGHUP`
+        #[cfg(unix)]
+        {
+            let reloader = crate::reload::ConfigReloader::new(
+                &configuration,
+                live_settings.clone(),
+                signup_protection.ip_rate_limiter.clone(),
+                signup_protection.domain_rate_limiter.clone(),
+                log_filter_handle.clone(),
+            );
+            workers.push(tokio::spawn(async move {
+                if let Err(e) = crate::reload::reload_on_hangup(reloader).await {
+                    tracing::error!("Failed to listen for SIGHUP: {}", e);
+                }
+            }));
+        }
+        if let Some(tls) = &tls {
+            workers.push(tokio::spawn(tls.clone().reload_on_change(
+                configuration.application.tls.get_reload_interval(),
+            )));
+        }
+
+        let host = &configuration.application.host;
+        let admin_port = configuration.metrics.admin_port;
+        let trust_incoming_request_id = configuration.application.trust_incoming_request_id;
+        let log_filter_admin = || LogFilterAdmin {
+            handle: log_filter_handle.clone(),
+            token: AdminToken(configuration.logging.admin_token.clone()),
+        };
+        let subscribers_admin = SubscribersAdmin {
+            token: AdminToken(configuration.subscribers.admin_token.clone()),
+        };
+        let redirect_port = configuration
+            .application
+            .tls
+            .redirect_port
+            .filter(|_| tls.is_some());
+
+        let (listener, port) = bind(host, configuration.application.port)?;
+        let mut servers = vec![run(
+            listener,
+            repository.clone(),
+            email_client,
+            email_policy,
+            domain_verifier,
+            signup_protection,
+            readiness,
+            metrics.clone(),
+            log_filter_admin(),
+            subscribers_admin.clone(),
+            admin_port.is_none(),
+            trust_incoming_request_id,
+            live_settings,
+            shutdown.clone(),
+            tls,
+            configuration.application.tls.hsts,
+        )?];
+
+        let admin_port = match admin_port {
+            Some(admin_port) => {
+                let (admin_listener, admin_port) = bind(host, admin_port)?;
+                servers.push(run_admin(
+                    admin_listener,
+                    repository.clone(),
+                    metrics,
+                    log_filter_admin(),
+                    subscribers_admin,
+                    trust_incoming_request_id,
+                    &shutdown,
+                )?);
+                Some(admin_port)
+            }
+            None => None,
+        };
+
+        let redirect_port = match redirect_port {
+            Some(redirect_port) => {
+                let (redirect_listener, redirect_port) = bind(host, redirect_port)?;
+                servers.push(run_redirect(redirect_listener, port, &shutdown)?);
+                Some(redirect_port)
+            }
+            None => None,
+        };
+
+        Ok(Application {
+            port,
+            admin_port,
+            redirect_port,
+            repository,
+            shutdown,
+            servers,
+            workers,
+        })
+    }
+}
+
+/// Binds `host:port`, and returns the port that it is bound to, which differs if `port` is 0
+fn bind(host: &str, port: u16) -> Result<(TcpListener, u16), std::io::Error> {
+    let listener = TcpListener::bind(format!("{}:{}", host, port))?;
+    let port = listener.local_addr()?.port();
+    Ok((listener, port))
+}
+
+/// Applies the pending migrations to the configured database, and logs them
+async fn migrate(configuration: &Settings) -> Result<(), StartupError> {
+    let pool = configuration.database.get_pool().await?;
+    let outcome = pool.run_migrations().await;
+    pool.close().await;
+    let applied = outcome?;
+    for version in &applied {
+        tracing::info!(version, "Applied the migration {}.", version);
+    }
+    tracing::info!("The database schema is up to date.");
+
+    Ok(())
+}
+
+/// Why the app couldn't be built
+#[derive(Debug)]
+pub enum StartupError {
+    /// A listener couldn't be bound, or a server couldn't be started
+    Io(std::io::Error),
+    Database(sqlx::Error),
+    /// The database couldn't be migrated, or its schema is newer than the app
+    Migration(MigrationError),
+    /// A component couldn't be built from the settings at this path
+    Component(&'static str, String),
+}
+
+impl std::fmt::Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartupError::Io(e) => write!(f, "Failed to start the servers: {}", e),
+            StartupError::Database(e) => write!(f, "Failed to connect to the database: {}", e),
+            StartupError::Migration(e) => write!(f, "{}", e),
+            StartupError::Component(setting, e) => write!(f, "Invalid {}: {}", setting, e),
+        }
+    }
+}
+
+impl std::error::Error for StartupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StartupError::Io(e) => Some(e),
+            StartupError::Database(e) => Some(e),
+            StartupError::Migration(e) => Some(e),
+            StartupError::Component(_, _) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StartupError {
+    fn from(e: std::io::Error) -> Self {
+        StartupError::Io(e)
+    }
+}
+
+impl From<sqlx::Error> for StartupError {
+    fn from(e: sqlx::Error) -> Self {
+        StartupError::Database(e)
+    }
+}
+
+impl From<MigrationError> for StartupError {
+    fn from(e: MigrationError) -> Self {
+        StartupError::Migration(e)
+    }
+}
+
 /// Run the application - the web server - concurrently
 ///
 /// Spin up a worker process for each available CPU core.
 /// Each worker runs its own copy of the application.
-#[tracing::instrument(name = "Starting the app")]
-pub fn run(
+///
+/// The admin routes, `/metrics` and those under `/admin`, are only served if `serve_admin_routes`
+/// is set; otherwise, `Application` serves them on a separate admin port with `run_admin`.
+///
+/// Every request gets a `RequestId`, which is taken from its `X-Request-Id` header
+/// only if `trust_incoming_request_id` is set.
+///
+/// Handlers get the settings which can be reloaded at runtime as `Data<LiveSettings>`,
+/// and `Data<Shutdown>`, to spawn background jobs which shutdown waits for.
+///
+/// The server doesn't handle signals itself; stop it with `Shutdown::stop_servers`,
+/// which gives the requests in flight the `shutdown` grace period to finish.
+///
+/// With `tls`, it serves HTTPS, and HTTP/2 to the clients which negotiate it.
+/// With `hsts`, it sends `Strict-Transport-Security` over HTTPS, whether it terminated TLS
+/// itself or a proxy did.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Starting the app",
+    skip(
+        repository,
+        email_policy,
+        domain_verifier,
+        signup_protection,
+        readiness,
+        metrics,
+        log_filter_admin,
+        subscribers_admin,
+        live_settings,
+        shutdown,
+        tls
+    )
+)]
+fn run(
     listener: TcpListener,
-    db_pool: PgPool,
+    repository: Arc<dyn SubscriberRepository>,
     email_client: EmailClient,
+    email_policy: EmailPolicy,
+    domain_verifier: Arc<dyn DomainVerifier>,
+    signup_protection: SignupProtection,
+    readiness: ReadinessCheck,
+    metrics: Metrics,
+    log_filter_admin: LogFilterAdmin,
+    subscribers_admin: SubscribersAdmin,
+    serve_admin_routes: bool,
+    trust_incoming_request_id: bool,
+    live_settings: LiveSettings,
+    shutdown: Shutdown,
+    tls: Option<Tls>,
+    hsts: bool,
 ) -> Result<Server, std::io::Error> {
-    let db_pool = Data::new(db_pool);
+    let repository: Arc<dyn SubscriberRepository> = Arc::new(MeteredSubscriberRepository::new(
+        repository,
+        metrics.clone(),
+    ));
+    let repository = Data::from(repository);
     let email_client = Data::new(email_client);
+    let email_policy = Data::new(email_policy);
+    let domain_verifier = Data::from(domain_verifier);
+    let ip_rate_limiter = signup_protection.ip_rate_limiter.clone();
+    let use_forwarded_ip = signup_protection.use_forwarded_ip;
+    let signup_protection = Data::new(signup_protection);
+    let readiness = Data::new(readiness);
+    let request_metrics = metrics.clone();
+    let metrics = Data::new(metrics);
+    let log_filter_admin = Data::new(log_filter_admin);
+    let subscribers_admin = Data::new(subscribers_admin);
+    let live_settings = Data::new(live_settings);
+    let export_limits = Data::new(ExportLimits::new(shutdown.clone()));
+    let shutdown_timeout = shutdown.grace_period().as_secs();
+    let shutdown_data = Data::new(shutdown.clone());
     let server = HttpServer::new(move || {
-        App::new()
-            .wrap(TracingLogger::default())
+        let app = App::new()
+            .wrap(Condition::new(hsts, Hsts))
+            .wrap(RequestMetrics::new(request_metrics.clone()))
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
+            .wrap(PropagateRequestId::new(trust_incoming_request_id))
+            .wrap(TrackRequests::new(shutdown.clone()))
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
-            .app_data(db_pool.clone()) // Get a pointer copy and attach it to the application state.
+            .route("/ready", web::get().to(ready))
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(IpRateLimit::new(ip_rate_limiter.clone(), use_forwarded_ip))
+                    .route(web::post().to(subscribe)),
+            )
+            .route("/subscriptions/form_token", web::get().to(form_token))
+            .app_data(repository.clone()) // Get a pointer copy and attach it to the application state.
             .app_data(email_client.clone())
+            .app_data(email_policy.clone())
+            .app_data(domain_verifier.clone())
+            .app_data(signup_protection.clone())
+            .app_data(readiness.clone())
+            .app_data(metrics.clone())
+            .app_data(log_filter_admin.clone())
+            .app_data(subscribers_admin.clone())
+            .app_data(live_settings.clone())
+            .app_data(export_limits.clone())
+            .app_data(shutdown_data.clone());
+        if serve_admin_routes {
+            app.configure(admin_routes)
+        } else {
+            app
+        }
     })
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout);
+    let server = match tls {
+        Some(tls) => server.listen_rustls_0_21(listener, tls.server_config())?,
+        None => server.listen(listener)?,
+    }
+    .run();
+
+    Ok(server)
+}
+
+/// Run the admin web server, which only serves the admin routes
+///
+/// It is meant to listen on a port which isn't exposed to the public,
+/// unlike the one that `run` listens on.
+#[tracing::instrument(
+    name = "Starting the admin server",
+    skip(repository, metrics, log_filter_admin, subscribers_admin, shutdown)
+)]
+fn run_admin(
+    listener: TcpListener,
+    repository: Arc<dyn SubscriberRepository>,
+    metrics: Metrics,
+    log_filter_admin: LogFilterAdmin,
+    subscribers_admin: SubscribersAdmin,
+    trust_incoming_request_id: bool,
+    shutdown: &Shutdown,
+) -> Result<Server, std::io::Error> {
+    let repository = Data::from(repository);
+    let metrics = Data::new(metrics);
+    let log_filter_admin = Data::new(log_filter_admin);
+    let subscribers_admin = Data::new(subscribers_admin);
+    let export_limits = Data::new(ExportLimits::new(shutdown.clone()));
+    let shutdown_timeout = shutdown.grace_period().as_secs();
+    let shutdown = shutdown.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TracingLogger::<RequestIdRootSpanBuilder>::new())
+            .wrap(PropagateRequestId::new(trust_incoming_request_id))
+            .wrap(TrackRequests::new(shutdown.clone()))
+            .configure(admin_routes)
+            .app_data(repository.clone())
+            .app_data(metrics.clone())
+            .app_data(log_filter_admin.clone())
+            .app_data(subscribers_admin.clone())
+            .app_data(export_limits.clone())
+    })
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
     .listen(listener)?
     .run();
 
     Ok(server)
 }
+
+/// Run the web server which redirects plain HTTP requests to HTTPS, on `https_port`
+///
+/// It is only useful alongside `run` with TLS, and answers every request with a redirect.
+#[tracing::instrument(name = "Starting the HTTPS redirect server", skip(shutdown))]
+fn run_redirect(
+    listener: TcpListener,
+    https_port: u16,
+    shutdown: &Shutdown,
+) -> Result<Server, std::io::Error> {
+    let https_port = Data::new(HttpsPort(https_port));
+    let shutdown_timeout = shutdown.grace_period().as_secs();
+    let shutdown = shutdown.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TrackRequests::new(shutdown.clone()))
+            .default_service(web::to(redirect_to_https))
+            .app_data(https_port.clone())
+    })
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
+    .listen(listener)?
+    .run();
+
+    Ok(server)
+}
+
+/// The routes for operators, rather than for the public
+fn admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(export_metrics))
+        .service(
+            web::resource("/admin/log_filter")
+                .route(web::get().to(get_log_filter))
+                .route(web::put().to(set_log_filter)),
+        )
+        .route(
+            "/admin/subscribers/import",
+            web::post().to(import_subscribers),
+        )
+        .route(
+            "/admin/subscribers/export",
+            web::get().to(export_subscribers),
+        );
+}
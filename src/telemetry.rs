@@ -1,29 +1,108 @@
 //! src/telemetry.rs
 
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::{TraceContextExt, TraceError, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Config, Tracer, TracerProvider};
+use opentelemetry_sdk::Resource;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::time::Duration;
 use tracing::subscriber::set_global_default;
 use tracing::Subscriber;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
-use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Formats that we can write logs in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// JSON, for log aggregators
+    #[default]
+    Bunyan,
+    /// Human-readable and multi-line, for local development
+    Pretty,
+    /// Compact `key=value` pairs, one line per event
+    Logfmt,
+}
+
+/// Changes the log filter of the running application, without a restart
+///
+/// It is handed out by `get_subscriber`, and it is cheap to clone.
+#[derive(Clone)]
+pub struct LogFilterHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilterHandle {
+    /// Gets the current filter directives, such as `info,zero2prod=debug`
+    pub fn directives(&self) -> Result<String, String> {
+        self.0
+            .with_current(|filter| filter.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Replaces the filter directives, which use the same syntax as `RUST_LOG`
+    ///
+    /// Returns an error, and keeps the current filter, if the directives are invalid.
+    pub fn set_directives(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        self.0.reload(filter).map_err(|e| e.to_string())
+    }
+}
 
 /// Compose multiple layers into a `tracing`'s subscriber
+///
+/// Logs are written to `sink` in the given `format`. Their filter is `log_level`,
+/// unless `RUST_LOG` is set, and it can be changed later through the returned handle.
+///
+/// Besides the logs, spans are handed over to OpenTelemetry through `tracer`,
+/// which gives them trace and span ids that we propagate to other services.
 pub fn get_subscriber<Sink>(
     name: &str,
     log_level: &str,
+    format: LogFormat,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+    tracer: Tracer,
+) -> (impl Subscriber + Send + Sync, LogFilterHandle)
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
-    let formatting_layer = BunyanFormattingLayer::new(String::from(name), sink);
     let env_filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new(String::from(log_level)));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
 
-    Registry::default()
+    // Only one of the formatting layers is set; the others are `None`, which is a no-op layer.
+    let (bunyan_layer, pretty_layer, logfmt_layer) = match format {
+        LogFormat::Bunyan => (
+            Some(JsonStorageLayer.and_then(BunyanFormattingLayer::new(String::from(name), sink))),
+            None,
+            None,
+        ),
+        LogFormat::Pretty => (
+            None,
+            Some(tracing_subscriber::fmt::layer().pretty().with_writer(sink)),
+            None,
+        ),
+        LogFormat::Logfmt => (
+            None,
+            None,
+            Some(tracing_logfmt::builder().layer().with_writer(sink)),
+        ),
+    };
+    let opentelemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+
+    let subscriber = Registry::default()
         .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(bunyan_layer)
+        .with(pretty_layer)
+        .with(logfmt_layer)
+        .with(opentelemetry_layer);
+
+    (subscriber, LogFilterHandle(handle))
 }
 
 /// Register a subscriber as global default to process span data
@@ -33,3 +112,315 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to initialize logger.");
     set_global_default(subscriber).expect("Failed to set subscriber.");
 }
+
+/// Build an OpenTelemetry tracer provider for the service called `service_name`
+///
+/// Spans are exported in batches to the OTLP/HTTP collector at `otlp_endpoint`,
+/// such as `http://localhost:4318/v1/traces`, if it is set.
+/// Otherwise, they aren't exported anywhere, but they still get trace ids,
+/// so the trace context is propagated to the services that we call.
+///
+/// Batches are exported on the Tokio runtime, so this has to be called from within one.
+pub fn get_tracer_provider(
+    service_name: &str,
+    otlp_endpoint: Option<&str>,
+    timeout: Duration,
+) -> Result<TracerProvider, TraceError> {
+    let config = Config::default().with_resource(Resource::new([KeyValue::new(
+        "service.name",
+        service_name.to_string(),
+    )]));
+    let mut builder = TracerProvider::builder().with_config(config);
+
+    if let Some(otlp_endpoint) = otlp_endpoint {
+        let exporter = opentelemetry_otlp::SpanExporterBuilder::from(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(otlp_endpoint)
+                .with_timeout(timeout),
+        )
+        .build_span_exporter()?;
+        builder = builder.with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio);
+    }
+
+    Ok(builder.build())
+}
+
+/// Install the tracer provider globally, and get a tracer for `get_subscriber` out of it
+///
+/// The global provider keeps it alive, and `opentelemetry::global::shutdown_tracer_provider`
+/// exports the spans that are still buffered.
+/// It also sets the W3C Trace Context (`traceparent`) propagator, which `TracingLogger`
+/// uses to continue the traces of incoming requests, and `trace_context_headers`
+/// uses for outgoing requests.
+///
+/// It should be called only once for the entire lifetime of the application!
+pub fn init_tracer_provider(name: &str, tracer_provider: TracerProvider) -> Tracer {
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = tracer_provider.tracer(name.to_string());
+    opentelemetry::global::set_tracer_provider(tracer_provider);
+
+    tracer
+}
+
+/// Get the HTTP headers which carry the trace context of the current span,
+/// so that the service we call continues our trace
+pub fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+
+    headers
+}
+
+/// Make `span` continue the trace of the incoming request with `headers`, if they carry one,
+/// and record its trace id in the span's `trace_id` field
+pub fn set_remote_parent(span: &tracing::Span, headers: &actix_web::http::header::HeaderMap) {
+    let parent_context = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    });
+    span.set_parent(parent_context);
+    // It is the remote parent's trace id, or a new one if this span is the root of the trace
+    let trace_id = span.context().span().span_context().trace_id();
+    span.record("trace_id", tracing::field::display(trace_id));
+}
+
+/// Lets the propagator read the trace context from `actix-web`'s headers
+struct HeaderExtractor<'a>(&'a actix_web::http::header::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Lets the propagator write the trace context into `reqwest`'s headers
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// A log sink which keeps what is written to it, so tests can inspect the logs
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl SharedBuffer {
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl<'a> MakeWriter<'a> for SharedBuffer {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test::{call_and_read_body, init_service, TestRequest};
+    use actix_web::{web, App};
+    use tracing_actix_web::TracingLogger;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    /// Logs an event in the given format and returns what was written
+    fn log_in_format(format: LogFormat) -> String {
+        let tracer_provider = get_tracer_provider("test", None, Duration::from_secs(1)).unwrap();
+        let buffer = SharedBuffer::default();
+        let (subscriber, _) = get_subscriber(
+            "test",
+            "info",
+            format,
+            buffer.clone(),
+            tracer_provider.tracer("test"),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(answer = 42, "Hello!");
+        });
+
+        buffer.contents()
+    }
+
+    #[test]
+    fn logs_are_written_in_the_selected_format() {
+        let bunyan = log_in_format(LogFormat::Bunyan);
+        let pretty = log_in_format(LogFormat::Pretty);
+        let logfmt = log_in_format(LogFormat::Logfmt);
+
+        assert!(bunyan.contains(r#""msg":"Hello!""#), "{}", bunyan);
+        assert!(bunyan.contains(r#""answer":42"#), "{}", bunyan);
+        assert!(pretty.contains("Hello!"), "{}", pretty);
+        assert!(pretty.contains("answer"), "{}", pretty);
+        assert!(pretty.lines().count() > 1, "{}", pretty);
+        assert!(logfmt.contains("level=info"), "{}", logfmt);
+        assert!(logfmt.contains("answer=42"), "{}", logfmt);
+        assert_eq!(1, logfmt.lines().count(), "{}", logfmt);
+    }
+
+    #[test]
+    fn the_log_filter_can_be_changed_at_runtime() {
+        let tracer_provider = get_tracer_provider("test", None, Duration::from_secs(1)).unwrap();
+        let buffer = SharedBuffer::default();
+        let (subscriber, handle) = get_subscriber(
+            "test",
+            "info",
+            LogFormat::Logfmt,
+            buffer.clone(),
+            tracer_provider.tracer("test"),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("Before");
+            assert!(handle.set_directives("not a [valid filter").is_err());
+            handle.set_directives("debug").unwrap();
+            tracing::debug!("After");
+            assert_eq!("debug", handle.directives().unwrap());
+        });
+
+        let logs = buffer.contents();
+        assert!(!logs.contains("Before"), "{}", logs);
+        assert!(logs.contains("After"), "{}", logs);
+    }
+
+    /// Gets the trace id of the current span
+    fn current_trace_id() -> String {
+        tracing::Span::current()
+            .context()
+            .span()
+            .span_context()
+            .trace_id()
+            .to_string()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_collector() {
+        // Arrange
+        let collector = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .named("OTLP collector")
+            .mount(&collector)
+            .await;
+        let tracer_provider = get_tracer_provider(
+            "test",
+            Some(&format!("{}/v1/traces", collector.uri())),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        let (subscriber, _) = get_subscriber(
+            "test",
+            "info",
+            LogFormat::Bunyan,
+            std::io::sink,
+            tracer_provider.tracer("test"),
+        );
+
+        // Act
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("Exported span").in_scope(|| tracing::info!("Hello!"));
+        });
+        tracer_provider.force_flush();
+
+        // Assert
+        collector.verify().await;
+    }
+
+    #[actix_web::test]
+    async fn incoming_requests_continue_the_trace_of_the_caller() {
+        // Arrange
+        let tracer_provider = get_tracer_provider("test", None, Duration::from_secs(1)).unwrap();
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let (subscriber, _) = get_subscriber(
+            "test",
+            "info",
+            LogFormat::Bunyan,
+            std::io::sink,
+            tracer_provider.tracer("test"),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let app = init_service(
+            App::new()
+                .wrap(TracingLogger::default())
+                .route("/", web::get().to(|| async { current_trace_id() })),
+        )
+        .await;
+
+        // Act
+        let request = TestRequest::get()
+            .insert_header((
+                "traceparent",
+                format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+            ))
+            .to_request();
+        let body = call_and_read_body(&app, request).await;
+
+        // Assert
+        assert_eq!(TRACE_ID.as_bytes(), &body[..]);
+    }
+
+    #[test]
+    fn trace_context_headers_carry_the_current_trace() {
+        // Arrange
+        let tracer_provider = get_tracer_provider("test", None, Duration::from_secs(1)).unwrap();
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let (subscriber, _) = get_subscriber(
+            "test",
+            "info",
+            LogFormat::Bunyan,
+            std::io::sink,
+            tracer_provider.tracer("test"),
+        );
+
+        // Act
+        let (trace_id, headers) = tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("Outgoing request")
+                .in_scope(|| (current_trace_id(), trace_context_headers()))
+        });
+
+        // Assert
+        let traceparent = headers
+            .get("traceparent")
+            .expect("The traceparent header is missing.")
+            .to_str()
+            .unwrap();
+        assert!(traceparent.starts_with(&format!("00-{}-", trace_id)));
+    }
+}
@@ -0,0 +1,369 @@
+//! src/tls.rs
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderValue, STRICT_TRANSPORT_SECURITY};
+use futures_util::future::LocalBoxFuture;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{CertifiedKey, SigningKey};
+use rustls::{Certificate, PrivateKey, ServerConfig, SignatureScheme};
+use std::future::{ready, Ready};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// The value of the `Strict-Transport-Security` header: a year, for the subdomains too
+pub const HSTS_HEADER_VALUE: &str = "max-age=31536000; includeSubDomains";
+
+/// Middleware which sends the `Strict-Transport-Security` header over HTTPS
+///
+/// A request came over HTTPS if the app terminated TLS itself, or if the proxy in front of it
+/// says so, with the `Forwarded` or `X-Forwarded-Proto` header. Browsers ignore the header
+/// over plain HTTP, so a client which forges those headers only fools itself.
+pub struct Hsts;
+
+impl<S, B> Transform<S, ServiceRequest> for Hsts
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = HstsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(HstsMiddleware { service }))
+    }
+}
+
+pub struct HstsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for HstsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let is_https = req.connection_info().scheme() == "https";
+        let response = self.service.call(req);
+
+        Box::pin(async move {
+            let mut response = response.await?;
+            if is_https {
+                response.headers_mut().insert(
+                    STRICT_TRANSPORT_SECURITY,
+                    HeaderValue::from_static(HSTS_HEADER_VALUE),
+                );
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// HTTPS for the app, with a certificate which is reloaded when its files change
+///
+/// HTTP/2 is negotiated with ALPN, by `actix-web`, for the clients which support it.
+/// It is cheap to clone, and all the clones share the certificate.
+#[derive(Clone)]
+pub struct Tls {
+    resolver: Arc<CertificateResolver>,
+}
+
+impl Tls {
+    /// Loads the certificate chain and the private key from PEM files
+    pub fn new(
+        certificate_path: impl Into<PathBuf>,
+        private_key_path: impl Into<PathBuf>,
+    ) -> Result<Self, TlsError> {
+        let resolver = CertificateResolver::new(certificate_path.into(), private_key_path.into())?;
+
+        Ok(Self {
+            resolver: Arc::new(resolver),
+        })
+    }
+
+    /// Builds the configuration for `HttpServer::listen_rustls_0_21`
+    pub fn server_config(&self) -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self.resolver.clone())
+    }
+
+    /// Reloads the certificate if its files have changed; the connections after it get the new one
+    ///
+    /// A certificate which can't be loaded, such as one which is halfway written,
+    /// or whose private key doesn't match it, is an error, and the current one stays in use.
+    pub fn reload_if_changed(&self) -> Result<bool, TlsError> {
+        self.resolver.reload_if_changed()
+    }
+
+    /// Checks the certificate files for changes every `interval`, and reloads them
+    pub async fn reload_on_change(self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match self.reload_if_changed() {
+                Ok(true) => tracing::info!("The TLS certificate is reloaded."),
+                Ok(false) => {}
+                Err(e) => tracing::error!("Failed to reload the TLS certificate: {}", e),
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Tls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tls")
+            .field("certificate_path", &self.resolver.certificate_path)
+            .field("private_key_path", &self.resolver.private_key_path)
+            .finish()
+    }
+}
+
+/// Serves the current certificate to every TLS handshake
+struct CertificateResolver {
+    certificate_path: PathBuf,
+    private_key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+    /// When the files that `current` was loaded from were last modified
+    modified: Mutex<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+impl CertificateResolver {
+    fn new(certificate_path: PathBuf, private_key_path: PathBuf) -> Result<Self, TlsError> {
+        let modified = (modified(&certificate_path), modified(&private_key_path));
+        let current = load_certified_key(&certificate_path, &private_key_path)?;
+
+        Ok(Self {
+            certificate_path,
+            private_key_path,
+            current: RwLock::new(Arc::new(current)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    fn reload_if_changed(&self) -> Result<bool, TlsError> {
+        let mut last_modified = self.modified.lock().expect("TLS lock is poisoned.");
+        let modified = (
+            modified(&self.certificate_path),
+            modified(&self.private_key_path),
+        );
+        if modified == *last_modified {
+            return Ok(false);
+        }
+
+        let reloaded = load_certified_key(&self.certificate_path, &self.private_key_path)?;
+        *self.current.write().expect("TLS lock is poisoned.") = Arc::new(reloaded);
+        *last_modified = modified;
+
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().expect("TLS lock is poisoned.").clone())
+    }
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Loads a certificate chain, and the PKCS#8, RSA or EC private key which goes with it
+///
+/// The key is checked against the certificate, so that a certificate which was renewed
+/// before its new key was written isn't served.
+fn load_certified_key(
+    certificate_path: &PathBuf,
+    private_key_path: &PathBuf,
+) -> Result<CertifiedKey, TlsError> {
+    let read = |path: &PathBuf| {
+        std::fs::read(path)
+            .map_err(|e| TlsError(format!("Failed to read '{}': {}", path.display(), e)))
+    };
+
+    let certificates = rustls_pemfile::certs(&mut read(certificate_path)?.as_slice())
+        .map_err(|e| TlsError(format!("Invalid certificate: {}", e)))?;
+    if certificates.is_empty() {
+        return Err(TlsError(format!(
+            "There is no certificate in '{}'.",
+            certificate_path.display()
+        )));
+    }
+
+    let private_key = rustls_pemfile::read_all(&mut read(private_key_path)?.as_slice())
+        .map_err(|e| TlsError(format!("Invalid private key: {}", e)))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            TlsError(format!(
+                "There is no private key in '{}'.",
+                private_key_path.display()
+            ))
+        })?;
+    let signing_key = rustls::sign::any_supported_type(&private_key)
+        .map_err(|e| TlsError(format!("Unsupported private key: {}", e)))?;
+    if !key_matches_certificate(signing_key.as_ref(), &certificates[0]) {
+        return Err(TlsError(format!(
+            "The private key in '{}' doesn't match the certificate in '{}'.",
+            private_key_path.display(),
+            certificate_path.display()
+        )));
+    }
+
+    Ok(CertifiedKey::new(
+        certificates.into_iter().map(Certificate).collect(),
+        signing_key,
+    ))
+}
+
+/// Checks that `key` is the private key of `certificate`, by signing a message with it,
+/// and verifying the signature with the public key of the certificate
+fn key_matches_certificate(key: &dyn SigningKey, certificate: &[u8]) -> bool {
+    const MESSAGE: &[u8] = b"Does the private key match the certificate?";
+    let schemes = [
+        (
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            &webpki::ECDSA_P256_SHA256,
+        ),
+        (
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            &webpki::ECDSA_P384_SHA384,
+        ),
+        (SignatureScheme::ED25519, &webpki::ED25519),
+        (
+            SignatureScheme::RSA_PKCS1_SHA256,
+            &webpki::RSA_PKCS1_2048_8192_SHA256,
+        ),
+    ];
+
+    let Ok(certificate) = webpki::EndEntityCert::try_from(certificate) else {
+        return false;
+    };
+    let offered = schemes.map(|(scheme, _)| scheme);
+    let Some(signer) = key.choose_scheme(&offered) else {
+        return false;
+    };
+    let Some((_, algorithm)) = schemes
+        .iter()
+        .find(|(scheme, _)| *scheme == signer.scheme())
+    else {
+        return false;
+    };
+
+    signer.sign(MESSAGE).is_ok_and(|signature| {
+        certificate
+            .verify_signature(algorithm, MESSAGE, &signature)
+            .is_ok()
+    })
+}
+
+/// Why the certificate or the private key couldn't be loaded
+#[derive(Debug)]
+pub struct TlsError(String);
+
+impl std::fmt::Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use claims::{assert_err, assert_ok_eq};
+
+    const CERTIFICATE: &str = "tests/fixtures/tls/localhost.crt";
+    const PRIVATE_KEY: &str = "tests/fixtures/tls/localhost.key";
+
+    /// Copies the fixture certificate and key into a temporary directory, which the test can change
+    fn temporary_certificate() -> (PathBuf, PathBuf, PathBuf) {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir(&directory).unwrap();
+        let certificate = directory.join("tls.crt");
+        let private_key = directory.join("tls.key");
+        std::fs::copy(CERTIFICATE, &certificate).unwrap();
+        std::fs::copy(PRIVATE_KEY, &private_key).unwrap();
+        (directory, certificate, private_key)
+    }
+
+    fn current_certificate(tls: &Tls) -> Certificate {
+        tls.resolver.current.read().unwrap().cert[0].clone()
+    }
+
+    #[test]
+    fn the_certificate_is_reloaded_when_its_files_change() {
+        let (directory, certificate, private_key) = temporary_certificate();
+        let tls = Tls::new(&certificate, &private_key).unwrap();
+        let before = current_certificate(&tls);
+
+        assert_ok_eq!(tls.reload_if_changed(), false);
+        // Make sure that the modification time changes, even on coarse file systems
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::copy("tests/fixtures/tls/other.crt", &certificate).unwrap();
+        std::fs::copy("tests/fixtures/tls/other.key", &private_key).unwrap();
+
+        assert_ok_eq!(tls.reload_if_changed(), true);
+        assert_ne!(before, current_certificate(&tls));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_certificate_without_its_key_is_rejected_and_the_current_one_stays() {
+        let (directory, certificate, private_key) = temporary_certificate();
+        let tls = Tls::new(&certificate, &private_key).unwrap();
+        let before = current_certificate(&tls);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        // The renewed certificate is written, but its key isn't yet
+        std::fs::copy("tests/fixtures/tls/other.crt", &certificate).unwrap();
+
+        assert_err!(tls.reload_if_changed());
+        assert_eq!(before, current_certificate(&tls));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_broken_certificate_is_rejected_and_the_current_one_stays() {
+        let (directory, certificate, private_key) = temporary_certificate();
+        let tls = Tls::new(&certificate, &private_key).unwrap();
+        let before = current_certificate(&tls);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::write(&certificate, "-----BEGIN CERTIFICATE-----\n").unwrap();
+
+        assert_err!(tls.reload_if_changed());
+        assert_eq!(before, current_certificate(&tls));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_missing_private_key_is_an_error() {
+        assert_err!(Tls::new(CERTIFICATE, CERTIFICATE));
+        assert_err!(Tls::new(CERTIFICATE, "tests/fixtures/tls/missing.key"));
+    }
+
+    #[test]
+    fn a_private_key_which_does_not_match_the_certificate_is_an_error() {
+        assert_err!(Tls::new(CERTIFICATE, "tests/fixtures/tls/other.key"));
+    }
+}
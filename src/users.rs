@@ -0,0 +1,177 @@
+//! src/users.rs
+
+use crate::database::DatabasePool;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+/// Shorter passwords are rejected
+pub const MIN_PASSWORD_LENGTH: usize = 12;
+
+/// Hashes `password` with Argon2id, into a PHC string, which carries the salt and the parameters,
+/// so that they can change later without breaking the existing hashes
+pub fn hash_password(password: &Secret<String>) -> Result<Secret<String>, UserError> {
+    let salt = SaltString::generate(&mut OsRng);
+    // The parameters that OWASP recommends for Argon2id
+    let params = Params::new(15000, 2, 1, None).map_err(|e| UserError::Hashing(e.into()))?;
+    let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .map_err(UserError::Hashing)?;
+
+    Ok(Secret::new(hash.to_string()))
+}
+
+/// Stores a new user, with admin rights, which all the users have for now, and returns its id
+pub async fn create_admin(
+    pool: &DatabasePool,
+    username: &str,
+    password: &Secret<String>,
+) -> Result<Uuid, UserError> {
+    let username = username.trim();
+    if username.is_empty() || username.chars().any(char::is_whitespace) {
+        return Err(UserError::Invalid(String::from(
+            "The username must not be empty, nor contain whitespace.",
+        )));
+    }
+    if password.expose_secret().chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(UserError::Invalid(format!(
+            "The password must be at least {} characters long.",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    let user_id = Uuid::new_v4();
+    let password_hash = hash_password(password)?;
+    let outcome = match pool {
+        DatabasePool::Postgres(pool) => sqlx::query(
+            r#"
+                INSERT INTO users (user_id, username, password_hash, created_at)
+                VALUES ($1, $2, $3, $4)
+                "#,
+        )
+        .bind(user_id)
+        .bind(username)
+        .bind(password_hash.expose_secret())
+        .bind(Utc::now())
+        .execute(pool)
+        .await
+        .map(|_| ()),
+        DatabasePool::Sqlite(pool) => sqlx::query(
+            r#"
+                INSERT INTO users (user_id, username, password_hash, created_at)
+                VALUES (?1, ?2, ?3, ?4)
+                "#,
+        )
+        .bind(user_id.to_string())
+        .bind(username)
+        .bind(password_hash.expose_secret())
+        .bind(Utc::now())
+        .execute(pool)
+        .await
+        .map(|_| ()),
+    };
+
+    match outcome {
+        Ok(()) => Ok(user_id),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            Err(UserError::DuplicateUsername)
+        }
+        Err(e) => Err(UserError::Database(e)),
+    }
+}
+
+/// Errors of the user management
+#[derive(Debug)]
+pub enum UserError {
+    /// The username or the password is invalid
+    Invalid(String),
+    DuplicateUsername,
+    Hashing(argon2::password_hash::Error),
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for UserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserError::Invalid(e) => write!(f, "{}", e),
+            UserError::DuplicateUsername => write!(f, "The username is already taken."),
+            UserError::Hashing(e) => write!(f, "Failed to hash the password: {}", e),
+            UserError::Database(e) => write!(f, "Failed to store the user: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UserError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UserError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use argon2::{PasswordHash, PasswordVerifier};
+    use claims::{assert_err, assert_matches, assert_ok};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn migrated_sqlite() -> DatabasePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let pool = DatabasePool::Sqlite(pool);
+        pool.run_migrations().await.unwrap();
+        pool
+    }
+
+    fn password(password: &str) -> Secret<String> {
+        Secret::new(String::from(password))
+    }
+
+    #[test]
+    fn the_hash_verifies_the_password_and_only_it() {
+        let hash = hash_password(&password("correct horse battery staple")).unwrap();
+        let hash = PasswordHash::new(hash.expose_secret()).unwrap();
+
+        assert_ok!(Argon2::default().verify_password(b"correct horse battery staple", &hash));
+        assert_err!(Argon2::default().verify_password(b"Tr0ub4dor&3", &hash));
+    }
+
+    #[test]
+    fn the_same_password_is_hashed_with_different_salts() {
+        let first = hash_password(&password("correct horse battery staple")).unwrap();
+        let second = hash_password(&password("correct horse battery staple")).unwrap();
+
+        assert_ne!(first.expose_secret(), second.expose_secret());
+    }
+
+    #[tokio::test]
+    async fn an_admin_is_created_only_once_per_username() {
+        let pool = migrated_sqlite().await;
+
+        assert_ok!(create_admin(&pool, "ursula", &password("correct horse battery staple")).await);
+        let duplicate = create_admin(&pool, "ursula", &password("another long password")).await;
+
+        assert_matches!(duplicate, Err(UserError::DuplicateUsername));
+    }
+
+    #[tokio::test]
+    async fn short_passwords_and_blank_usernames_are_rejected() {
+        let pool = migrated_sqlite().await;
+
+        let short_password = create_admin(&pool, "ursula", &password("s3cr3t")).await;
+        let blank_username =
+            create_admin(&pool, " ", &password("correct horse battery staple")).await;
+
+        assert_matches!(short_password, Err(UserError::Invalid(_)));
+        assert_matches!(blank_username, Err(UserError::Invalid(_)));
+    }
+}
@@ -0,0 +1,153 @@
+//! tests/api/admin.rs
+
+use crate::helpers::{spawn_app, spawn_app_with, Overrides};
+use rstest::rstest;
+use secrecy::Secret;
+use zero2prod::configuration::DatabaseBackend;
+
+#[rstest]
+#[tokio::test]
+async fn metrics_are_exported_in_the_prometheus_format(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("Failed to send request to '/metrics'.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let metrics = response.text().await.expect("Failed to read the metrics.");
+    for expected in [
+        r#"zero2prod_http_requests_total{method="POST",route="/subscriptions",status="200"} 1"#,
+        r#"zero2prod_http_request_duration_seconds_count{method="POST",route="/subscriptions",status="200"} 1"#,
+        r#"zero2prod_subscriptions_total{event="signed_up"} 1"#,
+        r#"zero2prod_db_pool_connections{state="max"}"#,
+    ] {
+        assert!(
+            metrics.contains(expected),
+            "'{}' is missing from the metrics:\n{}",
+            expected,
+            metrics
+        );
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn metrics_are_only_exported_on_the_admin_port_when_it_is_set(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |configuration| configuration.metrics.admin_port = Some(0),
+        Overrides::default(),
+    )
+    .await;
+    let admin_address = app.admin_address.expect("The admin server isn't running.");
+    let client = reqwest::Client::new();
+
+    // Act
+    let public_response = client
+        .get(format!("{}/metrics", &app.address))
+        .send()
+        .await
+        .expect("Failed to send request to '/metrics'.");
+    let admin_response = client
+        .get(format!("{}/metrics", &admin_address))
+        .send()
+        .await
+        .expect("Failed to send request to the admin '/metrics'.");
+
+    // Assert
+    assert_eq!(404, public_response.status().as_u16());
+    assert_eq!(200, admin_response.status().as_u16());
+    let metrics = admin_response
+        .text()
+        .await
+        .expect("Failed to read the metrics.");
+    assert!(metrics.contains(r#"route="unmatched",status="404""#));
+}
+
+#[rstest(
+    token,
+    expected_status,
+    case::missing_token(None, 401),
+    case::wrong_token(Some("guessed"), 401),
+    case::valid_token(Some("s3cr3t"), 200),
+    backend => [DatabaseBackend::Postgres, DatabaseBackend::Sqlite]
+)]
+#[tokio::test]
+async fn log_filter_can_only_be_changed_with_the_admin_token(
+    token: Option<&str>,
+    expected_status: u16,
+    backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |configuration| {
+            configuration.logging.admin_token = Some(Secret::new(String::from("s3cr3t")))
+        },
+        Overrides::default(),
+    )
+    .await;
+
+    // Act
+    // All the test apps share the log filter, so we don't change it, but only set it again
+    let response = app.put_log_filter("debug", token).await;
+
+    // Assert
+    assert_eq!(expected_status, response.status().as_u16());
+}
+
+#[rstest]
+#[tokio::test]
+async fn log_filter_rejects_invalid_directives(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |configuration| {
+            configuration.logging.admin_token = Some(Secret::new(String::from("s3cr3t")))
+        },
+        Overrides::default(),
+    )
+    .await;
+
+    // Act
+    let response = app
+        .put_log_filter("not a [valid filter", Some("s3cr3t"))
+        .await;
+    let current = app.get_log_filter(Some("s3cr3t")).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(200, current.status().as_u16());
+    assert_eq!("debug", current.text().await.unwrap());
+}
+
+#[rstest]
+#[tokio::test]
+async fn log_filter_endpoint_is_disabled_without_an_admin_token(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+
+    // Act
+    let response = app.get_log_filter(None).await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+}
@@ -0,0 +1,230 @@
+//! tests/api/export.rs
+
+use crate::helpers::TestApp;
+use crate::import::spawn_app_with_admin_token;
+use rstest::rstest;
+use zero2prod::configuration::DatabaseBackend;
+
+/// Imports a confirmed subscriber per year from 2019 to 2021, tagged with the year,
+/// and signs up a pending one
+async fn add_subscribers(app: &TestApp) {
+    let csv = "email,name,tags,subscribed_at\n\
+               twenty_nineteen@gmail.com,le guin,2019,2019-05-01\n\
+               twenty_twenty@gmail.com,\"tolkien, jrr\",2020;legacy,2020-05-01T12:30:00Z\n\
+               twenty_twenty_one@gmail.com,herbert,2021,2021-05-01\n";
+    let response = app.post_import(csv, "confirmed=true", Some("s3cr3t")).await;
+    assert_eq!(200, response.status().as_u16());
+    app.post_subscriptions("name=pending&email=pending%40gmail.com")
+        .await;
+}
+
+#[rstest]
+#[tokio::test]
+async fn csv_export_is_filtered_by_status_and_subscription_date(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with_admin_token(backend).await;
+    add_subscribers(&app).await;
+
+    // Act
+    let response = app
+        .get_export(
+            "status=confirmed&subscribed_since=2020-01-01&subscribed_before=2021-05-01",
+            Some("s3cr3t"),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let header = |name: &str| response.headers()[name].to_str().unwrap().to_owned();
+    assert_eq!("text/csv; charset=utf-8", header("Content-Type"));
+    assert_eq!(
+        r#"attachment; filename="subscribers.csv""#,
+        header("Content-Disposition")
+    );
+    let csv = response.text().await.unwrap();
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    assert_eq!(
+        vec![
+            "id",
+            "email",
+            "name",
+            "status",
+            "subscribed_at",
+            "tags",
+            "review_reason"
+        ],
+        reader.headers().unwrap().iter().collect::<Vec<_>>()
+    );
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(1, rows.len());
+    assert_eq!(
+        vec![
+            "twenty_twenty@gmail.com",
+            "tolkien, jrr",
+            "confirmed",
+            "2020-05-01T12:30:00+00:00",
+            "2020;legacy",
+            ""
+        ],
+        rows[0].iter().skip(1).collect::<Vec<_>>()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn ndjson_export_has_every_subscriber_oldest_first(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with_admin_token(backend).await;
+    add_subscribers(&app).await;
+
+    // Act
+    let response = app.get_export("format=ndjson", Some("s3cr3t")).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "application/x-ndjson",
+        response.headers()["Content-Type"].to_str().unwrap()
+    );
+    let subscribers: Vec<serde_json::Value> = response
+        .text()
+        .await
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let emails: Vec<_> = subscribers
+        .iter()
+        .map(|subscriber| subscriber["email"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        vec![
+            "twenty_nineteen@gmail.com",
+            "twenty_twenty@gmail.com",
+            "twenty_twenty_one@gmail.com",
+            "pending@gmail.com",
+        ],
+        emails
+    );
+    assert_eq!(
+        serde_json::json!(["2020", "legacy"]),
+        subscribers[1]["tags"]
+    );
+    assert_eq!("pending_confirmation", subscribers[3]["status"]);
+}
+
+#[tokio::test]
+async fn a_csv_export_can_be_imported_back() {
+    // Arrange
+    let app = spawn_app_with_admin_token(DatabaseBackend::Sqlite).await;
+    add_subscribers(&app).await;
+    let export = app
+        .get_export("status=confirmed", Some("s3cr3t"))
+        .await
+        .text()
+        .await
+        .unwrap();
+    let other_app = spawn_app_with_admin_token(DatabaseBackend::Sqlite).await;
+
+    // Act
+    let response = other_app
+        .post_import(export, "confirmed=true", Some("s3cr3t"))
+        .await;
+
+    // Assert
+    assert_eq!(
+        "3",
+        response.headers()["X-Import-Accepted"].to_str().unwrap()
+    );
+    let imported = other_app
+        .repository
+        .find_by_email("twenty_twenty@gmail.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!("tolkien, jrr", imported.name);
+    assert_eq!(vec!["2020", "legacy"], imported.tags);
+    assert_eq!(
+        "2020-05-01T12:30:00+00:00",
+        imported.subscribed_at.to_rfc3339()
+    );
+}
+
+#[tokio::test]
+async fn escaped_formulas_are_imported_back_unescaped() {
+    // Arrange
+    let app = spawn_app_with_admin_token(DatabaseBackend::Sqlite).await;
+    let csv = "email,name,tags\nminus@gmail.com,-foo,-tag;=1+1\n";
+    app.post_import(csv, "confirmed=true", Some("s3cr3t")).await;
+    let export = app
+        .get_export("", Some("s3cr3t"))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(export.contains(",'-foo,"), "{}", export);
+    assert!(export.contains(",'-tag;=1+1,"), "{}", export);
+    let other_app = spawn_app_with_admin_token(DatabaseBackend::Sqlite).await;
+
+    // Act
+    let response = other_app
+        .post_import(export, "confirmed=true", Some("s3cr3t"))
+        .await;
+
+    // Assert
+    assert_eq!(
+        "1",
+        response.headers()["X-Import-Accepted"].to_str().unwrap()
+    );
+    let imported = other_app
+        .repository
+        .find_by_email("minus@gmail.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!("-foo", imported.name);
+    assert_eq!(vec!["-tag", "=1+1"], imported.tags);
+}
+
+#[rstest(
+    query,
+    case::unknown_format("format=xml"),
+    case::unknown_status("status=deleted"),
+    case::invalid_date("subscribed_since=01/31/2024")
+)]
+#[tokio::test]
+async fn export_with_invalid_parameters_is_rejected(query: &str) {
+    // Arrange
+    let app = spawn_app_with_admin_token(DatabaseBackend::Sqlite).await;
+
+    // Act
+    let response = app.get_export(query, Some("s3cr3t")).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[rstest(
+    token,
+    query,
+    case::missing_token(None, ""),
+    case::wrong_token(Some("guessed"), ""),
+    case::missing_token_and_invalid_parameters(None, "format=xml")
+)]
+#[tokio::test]
+async fn export_requires_the_admin_token(token: Option<&str>, query: &str) {
+    // Arrange
+    let app = spawn_app_with_admin_token(DatabaseBackend::Sqlite).await;
+    add_subscribers(&app).await;
+
+    // Act
+    let response = app.get_export(query, token).await;
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert!(!response.text().await.unwrap().contains("@gmail.com"));
+}
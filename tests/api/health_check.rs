@@ -0,0 +1,239 @@
+//! tests/api/health_check.rs
+
+use crate::helpers::{spawn_app, spawn_app_with, Overrides};
+use rstest::rstest;
+use std::time::Duration;
+use uuid::Uuid;
+use wiremock::matchers::method;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::DatabaseBackend;
+
+/// Test health check
+///
+/// `spawn_app()` is the only piece that will, reasonably, depend on our application code.
+/// Everything else is completely decoupled from the underlying implementation details.
+///
+/// Additionally, the test covers a full range of properties we are interested in checking:
+/// - the verb used is GET,
+/// - the endpoint is `/health_check`,
+/// - the endpoint always returns `200 OK`,
+/// - the response has no body.
+#[rstest]
+#[tokio::test]
+async fn health_check_works(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+
+    // Act
+    let response = app.get_health_check().await;
+
+    // Assert
+    assert!(response.status().is_success());
+    assert_eq!(Some(0), response.content_length());
+}
+
+#[rstest]
+#[tokio::test]
+async fn responses_carry_a_generated_request_id(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .get(format!("{}/health_check", &app.address))
+        // It isn't trusted by default, so it is ignored
+        .header("X-Request-Id", "support-ticket-1")
+        .send()
+        .await
+        .expect("Failed to send request to '/health_check'.");
+
+    // Assert
+    let request_id = response
+        .headers()
+        .get("X-Request-Id")
+        .expect("The request id is missing.")
+        .to_str()
+        .unwrap();
+    assert!(Uuid::parse_str(request_id).is_ok(), "{}", request_id);
+}
+
+#[rstest(
+    incoming,
+    is_accepted,
+    case::well_formed("support-ticket-1", true),
+    case::malformed("support ticket #1", false),
+    backend => [DatabaseBackend::Postgres, DatabaseBackend::Sqlite]
+)]
+#[tokio::test]
+async fn a_trusted_incoming_request_id_is_used_when_it_is_well_formed(
+    incoming: &str,
+    is_accepted: bool,
+    backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |configuration| configuration.application.trust_incoming_request_id = true,
+        Overrides::default(),
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Request-Id", incoming)
+        .send()
+        .await
+        .expect("Failed to send request to '/health_check'.");
+
+    // Assert
+    let request_id = response.headers().get("X-Request-Id").unwrap();
+    assert_eq!(is_accepted, request_id == incoming);
+}
+
+#[rstest]
+#[tokio::test]
+async fn error_responses_contain_the_request_id(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+    #[values("/nowhere", "/subscriptions")] path: &str,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .post(format!("{}{}", &app.address, path))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(response.status().is_client_error());
+    let request_id = response.headers().get("X-Request-Id").unwrap().clone();
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(request_id.to_str().unwrap(), body["request_id"]);
+    assert!(body["error"].is_string());
+}
+
+/// Test readiness
+///
+/// Unlike the health check, the readiness probe checks our dependencies,
+/// and reports on each of them in the JSON body.
+#[rstest]
+#[tokio::test]
+async fn ready_returns_200_when_the_database_is_ready(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+
+    // Act
+    let response = app.get_ready().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.expect("The body is not JSON.");
+    assert_eq!("ready", report["status"]);
+    let database = &report["components"]["database"];
+    assert_eq!("up", database["status"]);
+    assert_eq!(true, database["migrations_current"]);
+    assert!(database["latency_ms"].is_u64());
+    assert!(database["pool"]["max_size"].is_u64());
+    assert!(report["components"]["email"].is_null());
+}
+
+#[rstest(
+    email_provider_is_up,
+    expected_status,
+    case::email_provider_is_up(true, 200),
+    case::email_provider_is_down(false, 503),
+    backend => [DatabaseBackend::Postgres, DatabaseBackend::Sqlite]
+)]
+#[tokio::test]
+async fn ready_probes_the_email_provider_when_enabled(
+    email_provider_is_up: bool,
+    expected_status: u16,
+    backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |configuration| {
+            configuration.readiness.probe_email_transport = true;
+            if !email_provider_is_up {
+                // Nothing listens on this port, so the connection is refused
+                configuration.email_client.base_url = String::from("http://127.0.0.1:1");
+            }
+        },
+        Overrides::default(),
+    )
+    .await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.get_ready().await;
+
+    // Assert
+    assert_eq!(expected_status, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.expect("The body is not JSON.");
+    assert_eq!("up", report["components"]["database"]["status"]);
+    let expected_email_status = if email_provider_is_up { "up" } else { "down" };
+    assert_eq!(
+        expected_email_status,
+        report["components"]["email"]["status"]
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn requests_in_flight_finish_when_the_server_is_stopped(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |configuration| configuration.readiness.probe_email_transport = true,
+        Overrides::default(),
+    )
+    .await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .mount(&app.email_server)
+        .await;
+    // The client is dropped with the request, so that it doesn't keep the connection alive
+    let in_flight = reqwest::Client::new()
+        .get(format!("{}/ready", &app.address))
+        .send();
+    let in_flight = tokio::spawn(in_flight);
+    // Give the request the time to reach the handler, which waits for the email provider
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Act
+    app.shutdown.trigger();
+    let stopped = app.stopped.await.unwrap();
+
+    // Assert
+    assert!(stopped.is_ok());
+    let response = in_flight
+        .await
+        .unwrap()
+        .expect("The request in flight was cut off.");
+    assert_eq!(200, response.status().as_u16());
+    let after_stop = reqwest::Client::new()
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await;
+    assert!(after_stop.is_err());
+}
@@ -0,0 +1,386 @@
+//! tests/api/helpers.rs
+//!
+//! What every integration test needs: a fully wired app on a fresh database,
+//! a fake email provider, and typed helpers for our API.
+
+use once_cell::sync::Lazy;
+use sqlx::postgres::PgConnectOptions;
+use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+use wiremock::MockServer;
+use zero2prod::abuse_protection::ChallengeVerifier;
+use zero2prod::configuration::{
+    get_configuration, DatabaseBackend, DatabaseSettings, Environment, Settings,
+};
+use zero2prod::domain_verifier::DomainVerifier;
+use zero2prod::maintenance::mark_test_database;
+use zero2prod::repository::SubscriberRepository;
+use zero2prod::shutdown::Shutdown;
+use zero2prod::startup::Application;
+use zero2prod::telemetry::{
+    get_subscriber, get_tracer_provider, init_subscriber, init_tracer_provider, LogFilterHandle,
+    LogFormat,
+};
+
+// Ensure that the `tracing` stack is initialized only once by using `once_cell`
+// All the apps share its log filter handle.
+static TRACING: Lazy<LogFilterHandle> = Lazy::new(|| {
+    let subscriber_name = "test";
+    let default_log_level = "debug";
+    // Spans aren't exported anywhere, but they still carry the trace context
+    let tracer_provider =
+        get_tracer_provider(subscriber_name, None, std::time::Duration::from_secs(1))
+            .expect("Failed to build the tracer provider.");
+    let tracer = init_tracer_provider(subscriber_name, tracer_provider);
+    let log_format = LogFormat::Bunyan;
+    if std::env::var("TEST_LOG").is_ok() {
+        let (subscriber, handle) = get_subscriber(
+            subscriber_name,
+            default_log_level,
+            log_format,
+            std::io::stdout,
+            tracer,
+        );
+        init_subscriber(subscriber);
+        handle
+    } else {
+        let (subscriber, handle) = get_subscriber(
+            subscriber_name,
+            default_log_level,
+            log_format,
+            std::io::sink,
+            tracer,
+        );
+        init_subscriber(subscriber);
+        handle
+    }
+});
+
+pub struct TestApp {
+    pub address: String,
+    /// The address of the admin server, if the configuration asks for one
+    pub admin_address: Option<String>,
+    /// The address of the server which redirects to HTTPS, if the configuration asks for one
+    pub redirect_address: Option<String>,
+    pub repository: Arc<dyn SubscriberRepository>,
+    /// Stands in for the email provider; the app's email client is pointed at it
+    pub email_server: MockServer,
+    pub api_client: reqwest::Client,
+    /// Stops the app gracefully, like `main` does on `SIGTERM`, when it is triggered
+    pub shutdown: Shutdown,
+    /// Completes once the app has stopped
+    pub stopped: JoinHandle<Result<(), std::io::Error>>,
+    /// Drops the test's database along with the app
+    _database: TestDatabase,
+}
+
+impl TestApp {
+    #[allow(clippy::needless_borrows_for_generic_args)]
+    pub async fn get_health_check(&self) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/health_check", &self.address))
+            .send()
+            .await
+            .expect("Failed to send request to '/health_check'.")
+    }
+
+    pub async fn get_ready(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/ready", &self.address))
+            .send()
+            .await
+            .expect("Failed to send request to '/ready'.")
+    }
+
+    /// Submits the signup form, with an already URL-encoded `body`
+    #[allow(clippy::needless_borrows_for_generic_args)]
+    pub async fn post_subscriptions(&self, body: impl Into<String>) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.into())
+            .send()
+            .await
+            .expect("Failed to send request to '/subscriptions'.")
+    }
+
+    pub async fn get_form_token(&self) -> String {
+        self.api_client
+            .get(format!("{}/subscriptions/form_token", &self.address))
+            .send()
+            .await
+            .expect("Failed to send request to '/subscriptions/form_token'.")
+            .text()
+            .await
+            .expect("Failed to read the form token.")
+    }
+
+    /// Reads the log filter, with the admin `token`, if any
+    pub async fn get_log_filter(&self, token: Option<&str>) -> reqwest::Response {
+        let request = self
+            .api_client
+            .get(format!("{}/admin/log_filter", &self.address));
+        with_bearer_token(request, token)
+            .send()
+            .await
+            .expect("Failed to send request to '/admin/log_filter'.")
+    }
+
+    /// Sets the log filter to `directives`, with the admin `token`, if any
+    pub async fn put_log_filter(&self, directives: &str, token: Option<&str>) -> reqwest::Response {
+        let request = self
+            .api_client
+            .put(format!("{}/admin/log_filter", &self.address))
+            .body(directives.to_owned());
+        with_bearer_token(request, token)
+            .send()
+            .await
+            .expect("Failed to send request to '/admin/log_filter'.")
+    }
+
+    /// Uploads `csv` to the subscriber import, with the admin `token`, if any
+    pub async fn post_import(
+        &self,
+        csv: impl Into<String>,
+        query: &str,
+        token: Option<&str>,
+    ) -> reqwest::Response {
+        let request = self
+            .api_client
+            .post(format!(
+                "{}/admin/subscribers/import?{}",
+                &self.address, query
+            ))
+            .header("Content-Type", "text/csv")
+            .body(csv.into());
+        with_bearer_token(request, token)
+            .send()
+            .await
+            .expect("Failed to send request to '/admin/subscribers/import'.")
+    }
+
+    /// Downloads the subscriber export, with the admin `token`, if any
+    pub async fn get_export(&self, query: &str, token: Option<&str>) -> reqwest::Response {
+        let request = self.api_client.get(format!(
+            "{}/admin/subscribers/export?{}",
+            &self.address, query
+        ));
+        with_bearer_token(request, token)
+            .send()
+            .await
+            .expect("Failed to send request to '/admin/subscribers/export'.")
+    }
+}
+
+fn with_bearer_token(
+    request: reqwest::RequestBuilder,
+    token: Option<&str>,
+) -> reqwest::RequestBuilder {
+    match token {
+        Some(token) => request.header("Authorization", format!("Bearer {}", token)),
+        None => request,
+    }
+}
+
+/// Initializes the `tracing` stack, and returns the log filter handle that all the apps share
+pub fn init_tracing() -> LogFilterHandle {
+    // The code in `TRACING` is executed only the first time `init_tracing` is invoked.
+    // All other invocations will skip its execution.
+    // This means that subscriber initialization happens only once.
+    Lazy::force(&TRACING).clone()
+}
+
+/// Components that a test can swap for local fakes, instead of building them from configuration
+#[derive(Default)]
+pub struct Overrides {
+    pub domain_verifier: Option<Arc<dyn DomainVerifier>>,
+    pub challenge_verifier: Option<Arc<dyn ChallengeVerifier>>,
+}
+
+/// Spin up an instance of our application in the background and return a `TestApp` struct
+/// with the app's address (i.e., `http:://127.0.0.1:XXXX`) and a handle to its storage.
+///
+/// Every test gets a fresh database on the given `backend`, which is dropped with the `TestApp`.
+pub async fn spawn_app(backend: DatabaseBackend) -> TestApp {
+    spawn_app_with(backend, |_| {}, Overrides::default()).await
+}
+
+/// Spin up an instance of our application like `spawn_app` does,
+/// but let the test adjust the configuration first, and override some of the components.
+pub async fn spawn_app_with(
+    backend: DatabaseBackend,
+    configure: impl FnOnce(&mut Settings),
+    overrides: Overrides,
+) -> TestApp {
+    let log_filter_handle = init_tracing();
+
+    let email_server = MockServer::start().await;
+
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+
+    // Port 0 asks the OS for a random port
+    configuration.application.port = 0;
+    configuration.database.backend = backend;
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.database.sqlite_path = std::env::temp_dir()
+        .join(format!("{}.sqlite", configuration.database.database_name))
+        .to_string_lossy()
+        .into_owned();
+    configuration.email_client.base_url = email_server.uri();
+    // Tests post the form without fetching a form token first, unless they opt into the check
+    configuration.abuse_protection.min_fill_time_millis = 0;
+    configure(&mut configuration);
+    configuration
+        .validate(&Environment::Local)
+        .expect("The test configuration is invalid.");
+    // The SQLite database file is created and migrated on connect,
+    // while a Postgres database has to be created, and migrated unless the app does it on boot.
+    if backend == DatabaseBackend::Postgres {
+        configure_database(&configuration.database).await;
+    }
+    let database = TestDatabase::new(&configuration.database);
+    // The test certificate is issued for `localhost`, rather than for the IP address
+    let (scheme, host) = match configuration.application.tls.enabled {
+        true => ("https", "localhost"),
+        false => ("http", "127.0.0.1"),
+    };
+
+    let mut builder = Application::builder(configuration, log_filter_handle);
+    if let Some(domain_verifier) = overrides.domain_verifier {
+        builder = builder.domain_verifier(domain_verifier);
+    }
+    if let Some(challenge_verifier) = overrides.challenge_verifier {
+        builder = builder.challenge_verifier(challenge_verifier);
+    }
+    // We are not propagating errors like in `main()`, because this is a test function. We can simply panic instead.
+    let application = builder.build().await.expect("Failed to build the app.");
+
+    let address = format!("{}://{}:{}", scheme, host, application.port());
+    let admin_address = application
+        .admin_port()
+        .map(|admin_port| format!("http://127.0.0.1:{}", admin_port));
+    let redirect_address = application
+        .redirect_port()
+        .map(|redirect_port| format!("http://localhost:{}", redirect_port));
+    let repository = application.repository();
+    let shutdown = application.shutdown();
+
+    // Launch the app as a background task
+    let stopped = tokio::spawn(application.run_until_stopped());
+
+    TestApp {
+        address,
+        admin_address,
+        redirect_address,
+        repository,
+        email_server,
+        api_client: reqwest::Client::new(),
+        shutdown,
+        stopped,
+        _database: database,
+    }
+}
+
+/// Creates the Postgres database, and applies the migrations, unless the app is to apply them
+pub async fn configure_database(db_settings: &DatabaseSettings) {
+    let connection_options = db_settings.without_db();
+
+    // Create database
+    let mut connection = PgConnection::connect_with(&connection_options)
+        .await
+        .expect("Failed to connect to Postgres.");
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, db_settings.database_name).as_str())
+        .await
+        .expect("Failed to create database.");
+    // So that `gc-test-databases` can tell how old it is, if the test is killed before it drops it
+    connection
+        .execute(mark_test_database(&db_settings.database_name, chrono::Utc::now()).as_str())
+        .await
+        .expect("Failed to mark the test database.");
+
+    if db_settings.migrate_on_boot {
+        return;
+    }
+
+    // Migrate database
+    let db_pool = PgPool::connect_with(db_settings.with_db())
+        .await
+        .expect("Failed to create a new connection pool and to connect to Postgres.");
+    sqlx::migrate!("./migrations")
+        .run(&db_pool)
+        .await
+        .expect("Failed to migrate the database.");
+}
+
+/// The database of a single test, which is dropped when the test is over, even if it fails
+pub struct TestDatabase {
+    backend: DatabaseBackend,
+    /// To connect to the Postgres server, rather than to the database itself
+    connection_options: PgConnectOptions,
+    database_name: String,
+    sqlite_path: PathBuf,
+}
+
+impl TestDatabase {
+    pub fn new(db_settings: &DatabaseSettings) -> Self {
+        Self {
+            backend: db_settings.backend,
+            connection_options: db_settings.without_db(),
+            database_name: db_settings.database_name.clone(),
+            sqlite_path: PathBuf::from(&db_settings.sqlite_path),
+        }
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        match self.backend {
+            DatabaseBackend::Postgres => {
+                let connection_options = self.connection_options.clone();
+                let database_name = self.database_name.clone();
+                // `Drop` can't be async, and the test's runtime can't be blocked on,
+                // so the database is dropped on a runtime of its own
+                let dropped = std::thread::spawn(move || {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .expect("Failed to build a runtime.")
+                        .block_on(drop_database(connection_options, database_name))
+                })
+                .join();
+                if let Ok(Err(e)) = dropped {
+                    eprintln!("Failed to drop a test database: {}", e);
+                }
+            }
+            DatabaseBackend::Sqlite => {
+                for suffix in ["", "-wal", "-shm"] {
+                    let mut file = self.sqlite_path.clone().into_os_string();
+                    file.push(suffix);
+                    let _ = std::fs::remove_file(file);
+                }
+            }
+        }
+    }
+}
+
+async fn drop_database(
+    connection_options: PgConnectOptions,
+    database_name: String,
+) -> Result<(), sqlx::Error> {
+    let mut connection = PgConnection::connect_with(&connection_options).await?;
+    // The app may still hold connections to it
+    connection
+        .execute(
+            format!(
+                r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE);"#,
+                database_name
+            )
+            .as_str(),
+        )
+        .await?;
+    Ok(())
+}
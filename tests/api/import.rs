@@ -0,0 +1,251 @@
+//! tests/api/import.rs
+
+use crate::helpers::{spawn_app_with, Overrides, TestApp};
+use rstest::rstest;
+use secrecy::Secret;
+use zero2prod::configuration::DatabaseBackend;
+use zero2prod::domain::SubscriptionStatus;
+
+pub async fn spawn_app_with_admin_token(backend: DatabaseBackend) -> TestApp {
+    spawn_app_with(
+        backend,
+        |configuration| {
+            configuration.subscribers.admin_token = Some(Secret::new(String::from("s3cr3t")))
+        },
+        Overrides::default(),
+    )
+    .await
+}
+
+#[rstest]
+#[tokio::test]
+async fn import_reports_accepted_duplicate_and_rejected_rows(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with_admin_token(backend).await;
+    app.post_subscriptions("name=already&email=already_signed_up%40gmail.com")
+        .await;
+    let csv = "email,name,tags,subscribed_at\n\
+               ursula_le_guin@gmail.com,le guin,weekly;legacy,2019-05-01\n\
+               already_signed_up@gmail.com,already,,\n\
+               ursula_le_guin@gmail.com,ursula,,\n\
+               not-an-email,tolkien,,\n";
+
+    // Act
+    let response = app.post_import(csv, "confirmed=true", Some("s3cr3t")).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let header = |name: &str| response.headers()[name].to_str().unwrap().to_owned();
+    assert_eq!("1", header("X-Import-Accepted"));
+    assert_eq!("2", header("X-Import-Duplicates"));
+    assert_eq!("1", header("X-Import-Rejected"));
+    assert!(header("Content-Disposition").starts_with("attachment"));
+    let report = response.text().await.unwrap();
+    let outcomes: Vec<_> = report
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').take(3).collect::<Vec<_>>().join(","))
+        .collect();
+    assert_eq!(
+        vec![
+            "2,ursula_le_guin@gmail.com,accepted",
+            "3,already_signed_up@gmail.com,duplicate",
+            "4,ursula_le_guin@gmail.com,duplicate",
+            "5,not-an-email,rejected",
+        ],
+        outcomes
+    );
+    let imported = app
+        .repository
+        .find_by_email("ursula_le_guin@gmail.com")
+        .await
+        .unwrap()
+        .expect("The subscriber wasn't imported.");
+    assert_eq!("le guin", imported.name);
+    assert_eq!(SubscriptionStatus::Confirmed, imported.status);
+    assert_eq!(
+        "2019-05-01T00:00:00+00:00",
+        imported.subscribed_at.to_rfc3339()
+    );
+    assert_eq!(vec!["legacy", "weekly"], imported.tags);
+    let already = app
+        .repository
+        .find_by_email("already_signed_up@gmail.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(SubscriptionStatus::PendingConfirmation, already.status);
+}
+
+#[rstest]
+#[tokio::test]
+async fn reconfirmed_subscribers_are_queued_for_a_confirmation_email(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with_admin_token(backend).await;
+    app.post_subscriptions("name=already&email=already_signed_up%40gmail.com")
+        .await;
+    let csv = "email,name\n\
+               ursula_le_guin@gmail.com,le guin\n\
+               already_signed_up@gmail.com,already\n\
+               jrr_tolkien@gmail.com,tolkien\n";
+
+    // Act
+    let response = app.post_import(csv, "reconfirm=true", Some("s3cr3t")).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let mut queued = Vec::new();
+    for email in ["ursula_le_guin@gmail.com", "jrr_tolkien@gmail.com"] {
+        let imported = app.repository.find_by_email(email).await.unwrap().unwrap();
+        assert_eq!(SubscriptionStatus::PendingConfirmation, imported.status);
+        queued.push(imported.id);
+    }
+    let mut requests = app.repository.confirmation_requests().await.unwrap();
+    requests.sort();
+    queued.sort();
+    assert_eq!(queued, requests);
+}
+
+#[tokio::test]
+async fn subscribers_cannot_be_both_confirmed_and_reconfirmed() {
+    // Arrange
+    let app = spawn_app_with_admin_token(DatabaseBackend::Sqlite).await;
+
+    // Act
+    let response = app
+        .post_import(
+            "email,name\nursula_le_guin@gmail.com,le guin\n",
+            "confirmed=true&reconfirm=true",
+            Some("s3cr3t"),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert!(app
+        .repository
+        .find_by_email("ursula_le_guin@gmail.com")
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn the_log_filter_token_does_not_open_the_subscriber_endpoints() {
+    // Arrange
+    let app = spawn_app_with(
+        DatabaseBackend::Sqlite,
+        |configuration| {
+            configuration.logging.admin_token = Some(Secret::new(String::from("log-token")));
+            configuration.subscribers.admin_token = Some(Secret::new(String::from("s3cr3t")));
+        },
+        Overrides::default(),
+    )
+    .await;
+
+    // Act
+    let import = app
+        .post_import(
+            "email,name\nursula_le_guin@gmail.com,le guin\n",
+            "",
+            Some("log-token"),
+        )
+        .await;
+    let export = app.get_export("", Some("log-token")).await;
+
+    // Assert
+    assert_eq!(401, import.status().as_u16());
+    assert_eq!(401, export.status().as_u16());
+}
+
+#[rstest]
+#[tokio::test]
+async fn imports_larger_than_a_batch_are_stored(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with_admin_token(backend).await;
+    let count = zero2prod::import::BATCH_SIZE + 1;
+    let csv: String = std::iter::once(String::from("email,name\n"))
+        .chain((0..count).map(|i| format!("subscriber{}@gmail.com,subscriber {}\n", i, i)))
+        .collect();
+
+    // Act
+    let response = app.post_import(csv, "", Some("s3cr3t")).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        count.to_string(),
+        response.headers()["X-Import-Accepted"].to_str().unwrap()
+    );
+    let stored = app
+        .repository
+        .list(Some(SubscriptionStatus::PendingConfirmation))
+        .await
+        .unwrap();
+    assert_eq!(count, stored.len());
+}
+
+#[rstest]
+#[tokio::test]
+async fn import_without_the_required_columns_is_rejected(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with_admin_token(backend).await;
+
+    // Act
+    let response = app
+        .post_import(
+            "e-mail,name\nursula_le_guin@gmail.com,le guin\n",
+            "",
+            Some("s3cr3t"),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("email"));
+}
+
+#[rstest(
+    token,
+    query,
+    expected_status,
+    case::missing_token(None, "", 401),
+    case::wrong_token(Some("guessed"), "", 401),
+    case::missing_token_and_invalid_query(None, "confirmed=maybe", 401),
+    case::invalid_query(Some("s3cr3t"), "confirmed=maybe", 400)
+)]
+#[tokio::test]
+async fn import_requires_the_admin_token_then_valid_options(
+    token: Option<&str>,
+    query: &str,
+    expected_status: u16,
+) {
+    // Arrange
+    let app = spawn_app_with_admin_token(DatabaseBackend::Sqlite).await;
+
+    // Act
+    let response = app
+        .post_import(
+            "email,name\nursula_le_guin@gmail.com,le guin\n",
+            query,
+            token,
+        )
+        .await;
+
+    // Assert
+    assert_eq!(expected_status, response.status().as_u16());
+    assert!(app
+        .repository
+        .find_by_email("ursula_le_guin@gmail.com")
+        .await
+        .unwrap()
+        .is_none());
+}
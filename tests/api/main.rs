@@ -0,0 +1,15 @@
+//! tests/api/main.rs
+//!
+//! All the API tests are built into a single binary, which shares the `helpers`.
+//!
+//! Run with:
+//! `cargo test --test api`
+
+mod admin;
+mod export;
+mod health_check;
+mod helpers;
+mod import;
+mod migrations;
+mod subscriptions;
+mod tls;
@@ -0,0 +1,138 @@
+//! tests/api/migrations.rs
+
+use crate::helpers::{configure_database, init_tracing, spawn_app_with, Overrides, TestDatabase};
+use sqlx::PgPool;
+use uuid::Uuid;
+use zero2prod::configuration::{get_configuration, DatabaseBackend, Environment, Settings};
+use zero2prod::database::{DatabasePool, MigrationError};
+use zero2prod::startup::{Application, StartupError};
+
+/// The configuration of an app which migrates a fresh Postgres database on boot
+fn migrate_on_boot_configuration() -> Settings {
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.application.port = 0;
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.database.migrate_on_boot = true;
+    configuration
+        .validate(&Environment::Local)
+        .expect("The test configuration is invalid.");
+    configuration
+}
+
+#[tokio::test]
+async fn the_database_is_migrated_on_boot_when_asked() {
+    // Arrange
+    let app = spawn_app_with(
+        DatabaseBackend::Postgres,
+        |configuration| configuration.database.migrate_on_boot = true,
+        Overrides::default(),
+    )
+    .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(200, app.get_ready().await.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_app_refuses_to_start_when_the_schema_is_newer() {
+    // Arrange
+    let configuration = migrate_on_boot_configuration();
+    configure_database(&configuration.database).await;
+    let _database = TestDatabase::new(&configuration.database);
+    let db_pool = PgPool::connect_with(configuration.database.with_db())
+        .await
+        .expect("Failed to connect to Postgres.");
+    let pool = DatabasePool::Postgres(db_pool.clone());
+    pool.run_migrations().await.unwrap();
+    // As if a newer version of the app had migrated the database
+    sqlx::query(
+        r#"
+        INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+        VALUES (99991231000000, 'from the future', true, '\x00', 0)
+        "#,
+    )
+    .execute(&db_pool)
+    .await
+    .unwrap();
+    db_pool.close().await;
+
+    // Act
+    let outcome = Application::build(configuration, init_tracing()).await;
+
+    // Assert
+    match outcome {
+        Err(StartupError::Migration(MigrationError::SchemaNewer(versions))) => {
+            assert_eq!(vec![99991231000000], versions)
+        }
+        Err(e) => panic!("The app failed to start for another reason: {}", e),
+        Ok(_) => panic!("The app started on a newer schema."),
+    }
+}
+
+#[tokio::test]
+async fn instances_booting_together_apply_each_migration_once() {
+    // Arrange
+    let configuration = migrate_on_boot_configuration();
+    configure_database(&configuration.database).await;
+    let _database = TestDatabase::new(&configuration.database);
+    let connect = || async {
+        DatabasePool::Postgres(
+            PgPool::connect_with(configuration.database.with_db())
+                .await
+                .expect("Failed to connect to Postgres."),
+        )
+    };
+    let (first, second) = (connect().await, connect().await);
+
+    // Act
+    let (first_applied, second_applied) =
+        tokio::join!(first.run_migrations(), second.run_migrations());
+
+    // Assert
+    let mut applied = [first_applied.unwrap(), second_applied.unwrap()].concat();
+    applied.sort_unstable();
+    let expected: Vec<i64> = first
+        .migration_status()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|status| status.version)
+        .collect();
+    assert_eq!(expected, applied);
+    first.close().await;
+    second.close().await;
+}
+
+#[tokio::test]
+async fn the_sqlite_database_file_is_removed_with_the_app() {
+    // Arrange
+    let mut sqlite_path = String::new();
+    let app = spawn_app_with(
+        DatabaseBackend::Sqlite,
+        |configuration| sqlite_path = configuration.database.sqlite_path.clone(),
+        Overrides::default(),
+    )
+    .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+    assert!(std::path::Path::new(&sqlite_path).exists());
+
+    // Act
+    drop(app);
+
+    // Assert
+    for suffix in ["", "-wal", "-shm"] {
+        let file = format!("{}{}", sqlite_path, suffix);
+        assert!(
+            !std::path::Path::new(&file).exists(),
+            "{} was left behind.",
+            file
+        );
+    }
+}
@@ -0,0 +1,461 @@
+//! tests/api/subscriptions.rs
+
+use crate::helpers::{spawn_app, spawn_app_with, Overrides};
+use rstest::rstest;
+use std::sync::Arc;
+use uuid::Uuid;
+use zero2prod::abuse_protection::FakeChallengeVerifier;
+use zero2prod::configuration::DatabaseBackend;
+use zero2prod::domain::SubscriptionStatus;
+use zero2prod::domain_verifier::StubDomainVerifier;
+
+#[rstest]
+#[tokio::test]
+async fn subscribe_returns_200_for_valid_form_data(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    // Act
+    let saved = app
+        .repository
+        .find_by_email("ursula_le_guin@gmail.com")
+        .await
+        .unwrap()
+        .expect("Failed to fetch saved subscription.");
+
+    // Assert
+    assert_eq!("ursula_le_guin@gmail.com", saved.email);
+    assert_eq!("le guin", saved.name);
+}
+
+#[rstest]
+#[tokio::test]
+async fn subscribe_returns_400_when_fields_are_missing(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+    let test_cases = [
+        ("name=le%20guin", "missing the email"),
+        ("email=ursula_le_guin%40gmail.com", "missing the name"),
+        ("", "missing both name and email"),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        // Act
+        let response = app.post_subscriptions(invalid_body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[rstest(
+    invalid_body,
+    error_message,
+    case::missing_email("name=le%20guin", "missing the email"),
+    case::missing_name("email=ursula_le_guin%40gmail.com", "missing the name"),
+    case::missing_both_name_and_email("", "missing both name and email"),
+    backend => [DatabaseBackend::Postgres, DatabaseBackend::Sqlite]
+)]
+#[tokio::test]
+async fn subscribe_returns_400_when_fields_are_missing_parameterized(
+    invalid_body: &'static str,
+    error_message: &str,
+    backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+
+    // Act
+    let response = app.post_subscriptions(invalid_body).await;
+
+    // Assert
+    assert_eq!(
+        400,
+        response.status().as_u16(),
+        "The API did not fail with 400 Bad Request when payload was {}.",
+        error_message
+    );
+}
+
+#[rstest(
+    invalid_body,
+    error_message,
+    case::empty_email("name=le%20guin&email=", "empty email"),
+    case::empty_name("name=&email=ursula_le_guin%40gmail.com", "empty name"),
+    case::invalid_email("name=Ursula&email=definitely-not-an-email", "invalid email"),
+    backend => [DatabaseBackend::Postgres, DatabaseBackend::Sqlite]
+)]
+#[tokio::test]
+async fn subscribe_returns_400_when_fields_are_present_but_invalid(
+    invalid_body: &'static str,
+    error_message: &str,
+    backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+
+    // Act
+    let response = app.post_subscriptions(invalid_body).await;
+
+    // Assert
+    assert_eq!(
+        400,
+        response.status().as_u16(),
+        "The API did not fail with 400 Bad Request when payload was an {}.",
+        error_message
+    );
+}
+
+#[rstest(
+    email,
+    error_message,
+    case::disposable_domain("john.doe%40mailinator.com", "a disposable domain"),
+    case::disposable_subdomain("john.doe%40mail.yopmail.com", "a disposable subdomain"),
+    backend => [DatabaseBackend::Postgres, DatabaseBackend::Sqlite]
+)]
+#[tokio::test]
+async fn subscribe_returns_400_when_email_is_rejected_by_the_policy(
+    email: &str,
+    error_message: &str,
+    backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!("name=John%20Doe&email={}", email))
+        .await;
+
+    // Assert
+    assert_eq!(
+        400,
+        response.status().as_u16(),
+        "The API did not fail with 400 Bad Request when the email was from {}.",
+        error_message
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn subscribe_flags_role_accounts_for_review(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+
+    // Act
+    let body = "name=le%20guin&email=info%40gmail.com";
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    // Act
+    let saved = app
+        .repository
+        .find_by_email("info@gmail.com")
+        .await
+        .unwrap()
+        .expect("Failed to fetch saved subscription.");
+
+    // Assert
+    assert_eq!("info@gmail.com", saved.email);
+    assert!(saved.review_reason.is_some());
+}
+
+#[rstest(
+    email,
+    expected_status,
+    case::deliverable_domain("ursula_le_guin%40gmail.com", 200),
+    case::domain_with_a_typo("ursula_le_guin%40gmial.con", 400),
+    backend => [DatabaseBackend::Postgres, DatabaseBackend::Sqlite]
+)]
+#[tokio::test]
+async fn subscribe_verifies_that_the_email_domain_can_receive_emails(
+    email: &str,
+    expected_status: u16,
+    backend: DatabaseBackend,
+) {
+    // Arrange
+    let overrides = Overrides {
+        domain_verifier: Some(Arc::new(StubDomainVerifier::new(["gmail.com"]))),
+        ..Default::default()
+    };
+    let app = spawn_app_with(backend, |_| {}, overrides).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!("name=le%20guin&email={}", email))
+        .await;
+
+    // Assert
+    assert_eq!(expected_status, response.status().as_u16());
+}
+
+#[rstest(
+    body,
+    error_message,
+    case::honeypot_filled_in(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&website=spam.yq",
+        "the honeypot field was filled in"
+    ),
+    case::form_token_missing(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "the form token is missing"
+    ),
+    case::form_token_forged(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token=0.00",
+        "the form token is forged"
+    ),
+    backend => [DatabaseBackend::Postgres, DatabaseBackend::Sqlite]
+)]
+#[tokio::test]
+async fn subscribe_silently_drops_automated_signups(
+    body: &'static str,
+    error_message: &str,
+    backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |c| c.abuse_protection.min_fill_time_millis = 50,
+        Overrides::default(),
+    )
+    .await;
+
+    // Act
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = app
+        .repository
+        .list(None)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert!(
+        saved.is_empty(),
+        "The signup was saved although {}.",
+        error_message
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn subscribe_silently_drops_forms_filled_in_too_fast(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |c| c.abuse_protection.min_fill_time_millis = 60_000,
+        Overrides::default(),
+    )
+    .await;
+    let form_token = app.get_form_token().await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            form_token
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = app
+        .repository
+        .list(None)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert!(
+        saved.is_empty(),
+        "The signup was saved although the form was filled in too fast."
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn subscribe_accepts_forms_with_a_valid_form_token(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |c| c.abuse_protection.min_fill_time_millis = 50,
+        Overrides::default(),
+    )
+    .await;
+    let form_token = app.get_form_token().await;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            form_token
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = app
+        .repository
+        .find_by_email("ursula_le_guin@gmail.com")
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert!(
+        saved.is_some(),
+        "The signup with a valid form token was dropped."
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn subscribe_returns_429_when_an_ip_address_sends_too_many_requests(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |configuration| configuration.abuse_protection.ip_rate_limit.max_requests = 2,
+        Overrides::default(),
+    )
+    .await;
+
+    for (i, expected_status) in [200, 200, 429].into_iter().enumerate() {
+        // Act
+        let response = app
+            .post_subscriptions(format!("name=le%20guin&email=ursula_{}%40gmail.com", i))
+            .await;
+
+        // Assert
+        assert_eq!(expected_status, response.status().as_u16());
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn subscribe_returns_429_when_an_email_domain_gets_too_many_signups(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app_with(
+        backend,
+        |configuration| {
+            configuration
+                .abuse_protection
+                .domain_rate_limit
+                .max_requests = 1
+        },
+        Overrides::default(),
+    )
+    .await;
+
+    for (email, expected_status) in [
+        ("ursula%40victim.yq", 200),
+        ("john%40VICTIM.yq", 429),
+        ("ursula%40gmail.com", 200),
+    ] {
+        // Act
+        let response = app
+            .post_subscriptions(format!("name=le%20guin&email={}", email))
+            .await;
+
+        // Assert
+        assert_eq!(expected_status, response.status().as_u16());
+    }
+}
+
+#[rstest(
+    challenge_response,
+    expected_status,
+    case::solved("&challenge_response=solved", 200),
+    case::wrong("&challenge_response=guessed", 403),
+    case::missing("", 403),
+    backend => [DatabaseBackend::Postgres, DatabaseBackend::Sqlite]
+)]
+#[tokio::test]
+async fn subscribe_requires_a_solved_challenge_when_enabled(
+    challenge_response: &str,
+    expected_status: u16,
+    backend: DatabaseBackend,
+) {
+    // Arrange
+    let overrides = Overrides {
+        challenge_verifier: Some(Arc::new(FakeChallengeVerifier::new("solved"))),
+        ..Default::default()
+    };
+    let app = spawn_app_with(backend, |_| {}, overrides).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com{}",
+            challenge_response
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(expected_status, response.status().as_u16());
+}
+
+#[rstest]
+#[tokio::test]
+async fn repository_confirms_and_unsubscribes_subscribers(
+    #[values(DatabaseBackend::Postgres, DatabaseBackend::Sqlite)] backend: DatabaseBackend,
+) {
+    // Arrange
+    let app = spawn_app(backend).await;
+    let repository = &app.repository;
+    for email in ["ursula%40gmail.com", "john%40gmail.com"] {
+        app.post_subscriptions(format!("name=le%20guin&email={}", email))
+            .await;
+    }
+    let ursula = repository
+        .find_by_email("ursula@gmail.com")
+        .await
+        .unwrap()
+        .expect("Failed to find the subscriber.");
+    let john = repository
+        .find_by_email("john@gmail.com")
+        .await
+        .unwrap()
+        .expect("Failed to find the subscriber.");
+    assert_eq!(SubscriptionStatus::PendingConfirmation, ursula.status);
+
+    // Act
+    assert!(repository.confirm(ursula.id).await.unwrap());
+    assert!(repository.unsubscribe(john.id).await.unwrap());
+    assert!(!repository.confirm(Uuid::new_v4()).await.unwrap());
+
+    // Assert
+    let confirmed = repository
+        .list(Some(SubscriptionStatus::Confirmed))
+        .await
+        .unwrap();
+    assert_eq!(1, confirmed.len());
+    assert_eq!("ursula@gmail.com", confirmed[0].email);
+    assert_eq!(2, repository.list(None).await.unwrap().len());
+}
@@ -0,0 +1,107 @@
+//! tests/api/tls.rs
+
+use crate::helpers::{spawn_app_with, Overrides};
+use zero2prod::configuration::{DatabaseBackend, Settings};
+
+/// Serve HTTPS with the test certificate, for `localhost`
+fn enable_tls(configuration: &mut Settings) {
+    configuration.application.tls.enabled = true;
+    configuration.application.tls.certificate_path = "tests/fixtures/tls/localhost.crt".into();
+    configuration.application.tls.private_key_path = "tests/fixtures/tls/localhost.key".into();
+    configuration.application.tls.hsts = true;
+    configuration.application.tls.redirect_port = Some(0);
+}
+
+/// A client which trusts the test certificate
+fn tls_client() -> reqwest::Client {
+    let certificate = std::fs::read("tests/fixtures/tls/localhost.crt")
+        .expect("Failed to read the test certificate.");
+    reqwest::Client::builder()
+        .add_root_certificate(
+            reqwest::Certificate::from_pem(&certificate).expect("Invalid test certificate."),
+        )
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Failed to build the client.")
+}
+
+#[tokio::test]
+async fn https_is_served_over_http2_with_hsts() {
+    // Arrange
+    let app = spawn_app_with(DatabaseBackend::Sqlite, enable_tls, Overrides::default()).await;
+
+    // Act
+    let response = tls_client()
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(response.status().is_success());
+    assert_eq!(reqwest::Version::HTTP_2, response.version());
+    assert_eq!(
+        Some("max-age=31536000; includeSubDomains"),
+        response
+            .headers()
+            .get("Strict-Transport-Security")
+            .and_then(|value| value.to_str().ok())
+    );
+}
+
+#[tokio::test]
+async fn plain_http_requests_are_redirected_to_https() {
+    // Arrange
+    let app = spawn_app_with(DatabaseBackend::Sqlite, enable_tls, Overrides::default()).await;
+    let redirect_address = app.redirect_address.expect("No redirect server.");
+
+    // Act
+    let response = tls_client()
+        .get(format!("{}/health_check?verbose=true", redirect_address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(308, response.status().as_u16());
+    assert_eq!(
+        Some(format!("{}/health_check?verbose=true", &app.address).as_str()),
+        response
+            .headers()
+            .get("Location")
+            .and_then(|value| value.to_str().ok())
+    );
+}
+
+#[tokio::test]
+async fn hsts_is_sent_behind_a_proxy_which_terminates_tls() {
+    // Arrange
+    let app = spawn_app_with(
+        DatabaseBackend::Sqlite,
+        |configuration| configuration.application.tls.hsts = true,
+        Overrides::default(),
+    )
+    .await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let behind_proxy = client
+        .get(format!("{}/health_check", &app.address))
+        .header("X-Forwarded-Proto", "https")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let plain_http = client
+        .get(format!("{}/health_check", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(behind_proxy
+        .headers()
+        .contains_key("Strict-Transport-Security"));
+    assert!(!plain_http
+        .headers()
+        .contains_key("Strict-Transport-Security"));
+}
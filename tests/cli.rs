@@ -0,0 +1,439 @@
+//! tests/cli.rs
+
+use sqlx::{Connection, Executor, PgConnection};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use zero2prod::configuration::get_configuration;
+use zero2prod::maintenance::mark_test_database;
+
+/// Runs our binary with `args`, and with the `APP_`-prefixed configuration overrides in `envs`
+///
+/// It runs in the `local` environment, unless `envs` sets `APP_ENVIRONMENT`.
+fn run(args: &[&str], envs: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_zero2prod"))
+        .args(args)
+        .env_remove("APP_ENVIRONMENT")
+        .env_remove("APP_CONFIG_DIR")
+        .envs(envs.iter().copied())
+        .output()
+        .expect("Failed to run the binary.")
+}
+
+/// Runs our binary like `run` does, and writes `stdin` to its standard input
+fn run_with_stdin(args: &[&str], envs: &[(&str, &str)], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_zero2prod"))
+        .args(args)
+        .env_remove("APP_ENVIRONMENT")
+        .env_remove("APP_CONFIG_DIR")
+        .envs(envs.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run the binary.");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child
+        .wait_with_output()
+        .expect("Failed to wait for the binary.")
+}
+
+/// A SQLite database file of a single test, which is removed when the test is over
+struct SqliteDatabase(PathBuf);
+
+impl SqliteDatabase {
+    fn new() -> Self {
+        Self(std::env::temp_dir().join(format!("{}.sqlite", uuid::Uuid::new_v4())))
+    }
+
+    /// The configuration overrides which point the binary at this database
+    fn envs(&self) -> [(&str, &str); 2] {
+        [
+            ("APP_DATABASE__BACKEND", "sqlite"),
+            ("APP_DATABASE__SQLITE_PATH", self.0.to_str().unwrap()),
+        ]
+    }
+}
+
+impl Drop for SqliteDatabase {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut file = self.0.clone().into_os_string();
+            file.push(suffix);
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
+
+#[test]
+fn check_config_prints_the_configuration_without_secrets() {
+    // Act
+    let output = run(&["--check-config"], &[]);
+
+    // Assert
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+    let printed: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(8000, printed["application"]["port"]);
+    assert_eq!("[REDACTED]", printed["database"]["password"]);
+    assert_eq!("[REDACTED]", printed["email_client"]["authorization_token"]);
+}
+
+#[test]
+fn config_print_is_the_same_as_check_config() {
+    // Act
+    let config_print = run(&["config", "print"], &[]);
+    let check_config = run(&["--check-config"], &[]);
+
+    // Assert
+    assert!(config_print.status.success());
+    assert_eq!(check_config.stdout, config_print.stdout);
+}
+
+#[test]
+fn an_invalid_configuration_is_reported_with_a_non_zero_exit_code() {
+    // Act
+    let output = run(
+        &["--check-config"],
+        &[
+            ("APP_EMAIL_CLIENT__TIMEOUT_MILLIS", "0"),
+            ("APP_EMAIL_CLIENT__SENDER_EMAIL", "not-an-email"),
+        ],
+    );
+
+    // Assert
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(Some(1), output.status.code());
+    assert!(
+        stderr.contains("The configuration is invalid:"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("email_client.timeout_millis"), "{}", stderr);
+    assert!(stderr.contains("email_client.sender_email"), "{}", stderr);
+}
+
+#[test]
+fn secrets_can_be_read_from_files() {
+    // Arrange
+    let secret_file = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::write(&secret_file, "s3cr3t\n").unwrap();
+    let missing_file = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+
+    // Act
+    let found = run(
+        &["--check-config"],
+        &[("APP_DATABASE__PASSWORD_FILE", secret_file.to_str().unwrap())],
+    );
+    let missing = run(
+        &["--check-config"],
+        &[(
+            "APP_DATABASE__PASSWORD_FILE",
+            missing_file.to_str().unwrap(),
+        )],
+    );
+
+    // Assert
+    let stdout = String::from_utf8(found.stdout).unwrap();
+    assert!(found.status.success(), "{}", stdout);
+    assert!(!stdout.contains("s3cr3t"), "{}", stdout);
+    let stderr = String::from_utf8(missing.stderr).unwrap();
+    assert_eq!(Some(1), missing.status.code());
+    assert!(stderr.contains("database.password_file"), "{}", stderr);
+    std::fs::remove_file(secret_file).unwrap();
+}
+
+#[test]
+fn named_profiles_are_loaded_from_the_configuration_directory() {
+    // Arrange
+    let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir(&directory).unwrap();
+    std::fs::copy("configuration/base.yaml", directory.join("base.yaml")).unwrap();
+    std::fs::copy("configuration/local.yaml", directory.join("ci.yaml")).unwrap();
+    std::fs::write(
+        directory.join("ci.local.yaml"),
+        "application:\n  port: 9001\n",
+    )
+    .unwrap();
+    let directory_path = directory.to_str().unwrap();
+
+    // Act
+    let ci = run(
+        &["--check-config"],
+        &[
+            ("APP_ENVIRONMENT", "ci"),
+            ("APP_CONFIG_DIR", directory_path),
+        ],
+    );
+    let staging = run(
+        &["--check-config"],
+        &[
+            ("APP_ENVIRONMENT", "staging"),
+            ("APP_CONFIG_DIR", directory_path),
+        ],
+    );
+
+    // Assert
+    let stdout = String::from_utf8(ci.stdout).unwrap();
+    assert!(ci.status.success(), "{}", stdout);
+    let printed: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(9001, printed["application"]["port"]);
+    let stderr = String::from_utf8(staging.stderr).unwrap();
+    assert_eq!(Some(1), staging.status.code());
+    assert!(stderr.contains("staging.yaml"), "{}", stderr);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+/// Creates a test database, marked as created `hours_ago`
+async fn create_test_database(connection: &mut PgConnection, hours_ago: i64) -> String {
+    let database_name = uuid::Uuid::new_v4().to_string();
+    let created_at = chrono::Utc::now() - chrono::Duration::hours(hours_ago);
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, database_name).as_str())
+        .await
+        .expect("Failed to create database.");
+    connection
+        .execute(mark_test_database(&database_name, created_at).as_str())
+        .await
+        .expect("Failed to mark the test database.");
+    database_name
+}
+
+async fn database_exists(connection: &mut PgConnection, database_name: &str) -> bool {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1)")
+        .bind(database_name)
+        .fetch_one(connection)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn leftover_test_databases_are_dropped() {
+    // Arrange
+    let configuration = get_configuration().expect("Failed to read configuration.");
+    let mut connection = PgConnection::connect_with(&configuration.database.without_db())
+        .await
+        .expect("Failed to connect to Postgres.");
+    // The databases of the tests running right now are younger than the default of 24 hours
+    let leftover = create_test_database(&mut connection, 48).await;
+    let recent = create_test_database(&mut connection, 0).await;
+
+    // Act
+    let output = run(&["gc-test-databases"], &[]);
+
+    // Assert
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.lines().any(|line| line == leftover), "{}", stdout);
+    assert!(!stdout.lines().any(|line| line == recent), "{}", stdout);
+    assert!(!database_exists(&mut connection, &leftover).await);
+    assert!(database_exists(&mut connection, &recent).await);
+    connection
+        .execute(format!(r#"DROP DATABASE "{}";"#, recent).as_str())
+        .await
+        .expect("Failed to drop the database.");
+}
+
+#[test]
+fn test_databases_are_not_collected_in_production() {
+    // Act
+    let output = run(
+        &["gc-test-databases"],
+        &[
+            ("APP_ENVIRONMENT", "production"),
+            (
+                "APP_ABUSE_PROTECTION__FORM_TOKEN_SECRET",
+                "a-production-grade-form-token-secret",
+            ),
+        ],
+    );
+
+    // Assert
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(Some(1), output.status.code());
+    assert!(stderr.contains("--any-environment"), "{}", stderr);
+}
+
+#[test]
+fn unknown_arguments_are_rejected_with_the_usage() {
+    // Act
+    let output = run(&["gc-test-databases", "--older-than-days", "1"], &[]);
+
+    // Assert
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(Some(2), output.status.code());
+    assert!(stderr.contains("Usage:"), "{}", stderr);
+}
+
+#[test]
+fn migrations_are_applied_listed_and_reverted() {
+    // Arrange
+    let database = SqliteDatabase::new();
+
+    // Act
+    let up = run(&["migrate", "up"], &database.envs());
+    let reverted = run(&["migrate", "revert"], &database.envs());
+    let status = run(&["migrate", "status"], &database.envs());
+
+    // Assert
+    let applied = String::from_utf8(up.stdout).unwrap();
+    assert!(up.status.success(), "{}", applied);
+    let reverted = String::from_utf8(reverted.stdout).unwrap();
+    assert_eq!(applied.lines().last(), reverted.lines().next());
+    let status = String::from_utf8(status.stdout).unwrap();
+    let states: Vec<_> = status
+        .lines()
+        .map(|line| line.split('\t').nth(1).unwrap())
+        .collect();
+    assert_eq!(applied.lines().count(), states.len());
+    assert_eq!(Some(&"pending"), states.last());
+    assert!(states[..states.len() - 1]
+        .iter()
+        .all(|state| *state == "applied"));
+}
+
+#[test]
+fn an_admin_is_created_with_the_password_from_stdin() {
+    // Arrange
+    let database = SqliteDatabase::new();
+    assert!(run(&["migrate", "up"], &database.envs()).status.success());
+
+    // Act
+    let created = run_with_stdin(
+        &["create-admin", "ursula"],
+        &database.envs(),
+        "correct horse battery staple\n",
+    );
+    let duplicate = run_with_stdin(
+        &["create-admin", "ursula"],
+        &database.envs(),
+        "another long password\n",
+    );
+
+    // Assert
+    let stdout = String::from_utf8(created.stdout).unwrap();
+    assert!(created.status.success(), "{}", stdout);
+    assert!(uuid::Uuid::parse_str(stdout.trim()).is_ok(), "{}", stdout);
+    let stderr = String::from_utf8(duplicate.stderr).unwrap();
+    assert_eq!(Some(1), duplicate.status.code());
+    assert!(stderr.contains("already taken"), "{}", stderr);
+}
+
+#[test]
+fn imported_subscribers_are_exported() {
+    // Arrange
+    let database = SqliteDatabase::new();
+    let csv = "email,name\n\
+               ursula_le_guin@gmail.com,le guin\n\
+               ursula_le_guin@gmail.com,ursula\n\
+               not-an-email,nobody\n";
+
+    // Act
+    let imported = run_with_stdin(&["subscribers", "import"], &database.envs(), csv);
+    let exported = run(
+        &["subscribers", "export", "--format", "ndjson"],
+        &database.envs(),
+    );
+
+    // Assert
+    let stderr = String::from_utf8(imported.stderr).unwrap();
+    assert!(imported.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains("Accepted 1 rows, skipped 1 duplicates, and rejected 1 rows."),
+        "{}",
+        stderr
+    );
+    let stdout = String::from_utf8(exported.stdout).unwrap();
+    let subscribers: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(1, subscribers.len());
+    assert_eq!("ursula_le_guin@gmail.com", subscribers[0]["email"]);
+    assert_eq!("pending_confirmation", subscribers[0]["status"]);
+}
+
+#[test]
+fn csv_imports_write_the_report_to_stdout() {
+    // Arrange
+    let database = SqliteDatabase::new();
+    let csv = "email,name\n\
+               ursula_le_guin@gmail.com,le guin\n\
+               not-an-email,nobody\n";
+
+    // Act
+    let imported = run_with_stdin(
+        &["subscribers", "import", "--confirmed"],
+        &database.envs(),
+        csv,
+    );
+    let exported = run(&["subscribers", "export"], &database.envs());
+
+    // Assert
+    let stderr = String::from_utf8(imported.stderr).unwrap();
+    assert!(imported.status.success(), "{}", stderr);
+    let report = String::from_utf8(imported.stdout).unwrap();
+    assert_eq!(
+        vec![
+            "row,email,outcome,reason",
+            "2,ursula_le_guin@gmail.com,accepted,",
+        ],
+        report.lines().take(2).collect::<Vec<_>>()
+    );
+    assert!(report
+        .lines()
+        .nth(2)
+        .unwrap()
+        .starts_with("3,not-an-email,rejected,"));
+    let exported = String::from_utf8(exported.stdout).unwrap();
+    let row = exported.lines().nth(1).unwrap();
+    assert!(
+        row.contains(",ursula_le_guin@gmail.com,le guin,confirmed,"),
+        "{}",
+        row
+    );
+}
+
+#[test]
+fn subscribers_are_exported_as_csv_with_filters() {
+    // Arrange
+    let database = SqliteDatabase::new();
+    let csv = "email,name,subscribed_at\n\
+               ursula_le_guin@gmail.com,le guin,2019-05-01\n\
+               jrr_tolkien@gmail.com,tolkien,2021-05-01\n";
+    let imported = run_with_stdin(&["subscribers", "import"], &database.envs(), csv);
+    assert!(imported.status.success());
+
+    // Act
+    let exported = run(
+        &[
+            "subscribers",
+            "export",
+            "--format",
+            "csv",
+            "--status",
+            "pending_confirmation",
+            "--subscribed-since",
+            "2020-01-01",
+        ],
+        &database.envs(),
+    );
+
+    // Assert
+    let stderr = String::from_utf8(exported.stderr).unwrap();
+    assert!(exported.status.success(), "{}", stderr);
+    assert!(stderr.contains("Exported 1 subscribers."), "{}", stderr);
+    let stdout = String::from_utf8(exported.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(2, lines.len());
+    assert_eq!(
+        "id,email,name,status,subscribed_at,tags,review_reason",
+        lines[0]
+    );
+    assert!(lines[1].contains(",jrr_tolkien@gmail.com,tolkien,pending_confirmation,2021-05-01T"));
+}